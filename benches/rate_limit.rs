@@ -0,0 +1,68 @@
+//! Cost of a rate limit check as the number of tracked clients grows, it should
+//! stay flat. Run with `cargo bench --bench rate_limit`
+use std::net::{IpAddr, Ipv4Addr};
+use chrono::Duration;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+//* The crate is a binary, the limiter is pulled in on its own
+#[allow(dead_code)]
+#[path = "../src/security/rate_limit.rs"]
+mod rate_limit;
+
+use rate_limit::{RateLimitState, RateType};
+
+const NOW: i64 = 1_700_000_000_000;
+
+fn client(n: u32) -> IpAddr {
+   IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + n))
+}
+
+fn tracking(clients: u32) -> RateLimitState<IpAddr> {
+   let state = RateLimitState::new(RateType::new(Duration::minutes(1), 25));
+   for n in 0..clients {
+      state.hit_at(client(n), NOW);
+   }
+
+   state
+}
+
+fn hit(c: &mut Criterion) {
+   let mut group = c.benchmark_group("hit");
+   for clients in [ 1_000, 100_000, 1_000_000 ].iter().copied() {
+      let state = tracking(clients);
+
+      group.bench_with_input(BenchmarkId::new("known_client", clients), &clients, |b, &clients| {
+         let mut n = 0;
+         b.iter(|| {
+            n = (n + 7919) % clients;
+            black_box(state.hit_at(client(n), NOW + 1_000))
+         })
+      });
+      group.bench_with_input(BenchmarkId::new("new_client", clients), &clients, |b, &clients| {
+         let mut n = clients;
+         b.iter(|| {
+            n += 1;
+            black_box(state.hit_at(client(n), NOW + 1_000))
+         })
+      });
+   }
+   group.finish();
+}
+
+fn evict_idle(c: &mut Criterion) {
+   let mut group = c.benchmark_group("evict_idle");
+   group.sample_size(10);
+   for clients in [ 10_000, 100_000 ].iter().copied() {
+      group.bench_with_input(BenchmarkId::from_parameter(clients), &clients, |b, &clients| {
+         b.iter_batched(
+            || tracking(clients),
+            |state| black_box(state.evict_idle_at(NOW + Duration::minutes(2).num_milliseconds())),
+            criterion::BatchSize::LargeInput
+         )
+      });
+   }
+   group.finish();
+}
+
+criterion_group!(benches, hit, evict_idle);
+criterion_main!(benches);
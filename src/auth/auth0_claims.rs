@@ -22,7 +22,13 @@ pub mod auth0_perm_claims {
    pub enum ScopePerm {
       MAILER_BASE_ACCESS,
       MAILER_WEBP_MSGS_READ,
-      MAILER_WEBP_MSGS_DEL
+      MAILER_WEBP_MSGS_DEL,
+      MAILER_WEBP_MSGS_REPLY,
+      MAILER_WEBP_DELIVERY_MANAGE,
+      MAILER_WEBP_PRIVACY_MANAGE,
+      MAILER_WEBP_MSGS_IMPORT,
+      MAILER_WEBP_SPAM_MANAGE,
+      MAILER_WEBP_LIMITS_MANAGE
    }
 
    impl NewAuth0Perms for IsPerm {
@@ -48,6 +54,12 @@ pub mod auth0_perm_claims {
             "mailer:baseaccess" => Some(ScopePerm::MAILER_BASE_ACCESS),
             "mailer:webp:messages:read" => Some(ScopePerm::MAILER_WEBP_MSGS_READ),
             "mailer:webp:messages:delete" => Some(ScopePerm::MAILER_WEBP_MSGS_DEL),
+            "mailer:webp:messages:reply" => Some(ScopePerm::MAILER_WEBP_MSGS_REPLY),
+            "mailer:webp:delivery:manage" => Some(ScopePerm::MAILER_WEBP_DELIVERY_MANAGE),
+            "mailer:webp:privacy:manage" => Some(ScopePerm::MAILER_WEBP_PRIVACY_MANAGE),
+            "mailer:webp:messages:import" => Some(ScopePerm::MAILER_WEBP_MSGS_IMPORT),
+            "mailer:webp:spam:manage" => Some(ScopePerm::MAILER_WEBP_SPAM_MANAGE),
+            "mailer:webp:limits:manage" => Some(ScopePerm::MAILER_WEBP_LIMITS_MANAGE),
             _ => None,
          }
       }
@@ -75,6 +87,12 @@ pub mod auth0_perm_claims {
             ScopePerm::MAILER_BASE_ACCESS => "mailer:baseaccess".to_string(),
             ScopePerm::MAILER_WEBP_MSGS_READ => "mailer:webp:messages:read".to_string(),
             ScopePerm::MAILER_WEBP_MSGS_DEL => "mailer:webp:messages:delete".to_string(),
+            ScopePerm::MAILER_WEBP_MSGS_REPLY => "mailer:webp:messages:reply".to_string(),
+            ScopePerm::MAILER_WEBP_DELIVERY_MANAGE => "mailer:webp:delivery:manage".to_string(),
+            ScopePerm::MAILER_WEBP_PRIVACY_MANAGE => "mailer:webp:privacy:manage".to_string(),
+            ScopePerm::MAILER_WEBP_MSGS_IMPORT => "mailer:webp:messages:import".to_string(),
+            ScopePerm::MAILER_WEBP_SPAM_MANAGE => "mailer:webp:spam:manage".to_string(),
+            ScopePerm::MAILER_WEBP_LIMITS_MANAGE => "mailer:webp:limits:manage".to_string(),
          }
       }
    }
@@ -103,6 +121,12 @@ pub mod auth0_perm_claims {
             ScopePerm::MAILER_BASE_ACCESS => "mailer:baseaccess",
             ScopePerm::MAILER_WEBP_MSGS_READ => "mailer:webp:messages:read",
             ScopePerm::MAILER_WEBP_MSGS_DEL => "mailer:webp:messages:delete",
+            ScopePerm::MAILER_WEBP_MSGS_REPLY => "mailer:webp:messages:reply",
+            ScopePerm::MAILER_WEBP_DELIVERY_MANAGE => "mailer:webp:delivery:manage",
+            ScopePerm::MAILER_WEBP_PRIVACY_MANAGE => "mailer:webp:privacy:manage",
+            ScopePerm::MAILER_WEBP_MSGS_IMPORT => "mailer:webp:messages:import",
+            ScopePerm::MAILER_WEBP_SPAM_MANAGE => "mailer:webp:spam:manage",
+            ScopePerm::MAILER_WEBP_LIMITS_MANAGE => "mailer:webp:limits:manage",
          }
       }
    }
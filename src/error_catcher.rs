@@ -1,10 +1,30 @@
 use rocket::{
    response::{
+      self,
       content::RawJson, 
-      status::Custom
+      status::Custom,
+      Responder, Response
    }, 
-   http::Status as HttpStatus
+   http::Status as HttpStatus,
+   Request
 };
+use serde_json::json;
+
+use crate::guards::{ChallengeFailure, ServerOverload};
+
+/// Adds a `Retry-After` header, in seconds, when there's one to tell
+pub struct RetryAfter<R>(pub R, pub Option<i64>);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for RetryAfter<R> {
+   fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+      let mut res = Response::build_from(self.0.respond_to(req)?);
+      if let Some(secs) = self.1 {
+         res.raw_header("Retry-After", secs.to_string());
+      }
+
+      res.ok()
+   }
+}
 
 #[catch(404)]
 pub fn not_found() -> Custom<RawJson<String>> {
@@ -83,6 +103,39 @@ pub fn enhance_calm2() -> Custom<RawJson<String>> {
    )
 }
 
+#[catch(428)]
+pub fn challenge_required(req: &Request) -> Custom<RawJson<String>> {
+   let reason = req.local_cache(|| ChallengeFailure(None)).0.as_ref()
+      .map(|err| err.to_string())
+      .unwrap_or_else(|| "A challenge must be solved before sending a message".to_string());
+
+   Custom(
+      HttpStatus::new(428),
+      RawJson(json!({
+         "error": reason,
+         "challenge": "/challenge",
+         "http_cat": "https://http.cat/428"
+      }).to_string())
+   )
+}
+
+#[catch(503)]
+pub fn service_unavailable(req: &Request) -> RetryAfter<Custom<RawJson<String>>> {
+   let retry_after = req.local_cache(|| ServerOverload(None)).0;
+
+   RetryAfter(
+      Custom(
+         HttpStatus::new(503),
+         RawJson(json!({
+            "error": "We're receiving too many messages right now, please try again later.",
+            "retry_after": retry_after,
+            "http_cat": "https://http.cat/503"
+         }).to_string())
+      ),
+      retry_after
+   )
+}
+
 #[catch(400)]
 pub fn bad_request() -> Custom<RawJson<String>> {
    Custom(
@@ -0,0 +1,84 @@
+mod webhooks;
+
+use std::sync::Arc;
+use chrono::Utc;
+use serde_json::Value as SerdeVal;
+use tokio::sync::broadcast;
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{
+   models::{
+      event::{EventKind, MessageEvent},
+      job::JobKind
+   },
+   queue::JobQueue,
+   store::{EventStore, StoreErr}
+};
+
+pub use webhooks::*;
+
+//* Live subscribers lagging further behind catch up from the stored events
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Records message lifecycle events and fans them out to the subscribed webhooks
+/// and live streams
+#[derive(Clone)]
+pub struct EventBus {
+   store: Arc<dyn EventStore>,
+   webhooks: Webhooks,
+   queue: JobQueue,
+   sender: broadcast::Sender<MessageEvent>
+}
+
+impl EventBus {
+   pub fn new(store: Arc<dyn EventStore>, webhooks: Webhooks, queue: JobQueue) -> Self {
+      let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+      EventBus { store, webhooks, queue, sender }
+   }
+
+   pub fn subscribe(&self) -> broadcast::Receiver<MessageEvent> {
+      self.sender.subscribe()
+   }
+
+   /// Stored events that happened after `last_id`, oldest first
+   pub async fn since(&self, last_id: ObjectId, limit: i64) -> Result<Vec<MessageEvent>, StoreErr> {
+      self.store.since(last_id, limit).await
+   }
+
+   /// Whether the event is still stored, events expire with the events retention
+   pub async fn has_event(&self, id: ObjectId) -> Result<bool, StoreErr> {
+      Ok(self.store.find(id).await?.is_some())
+   }
+
+   pub fn webhooks(&self) -> &Webhooks {
+      &self.webhooks
+   }
+
+   /// Never fails the caller: the change already happened, a lost event is
+   /// only logged
+   pub async fn publish(&self, kind: EventKind, message_id: ObjectId, data: SerdeVal) {
+      let event = MessageEvent {
+         id: ObjectId::new(),
+         kind,
+         message_id,
+         occurred_at: DateTime::from(Utc::now()),
+         data
+      };
+
+      if let Err(err) = self.store.insert(&event).await {
+         warn!("Failed storing {} event of message {}. Error: {}", kind.as_str(), message_id, err);
+         return;
+      }
+
+      //* Fails only when nobody is listening
+      let _ = self.sender.send(event.clone());
+
+      for endpoint in self.webhooks.subscribed(kind) {
+         let job = JobKind::Webhook { endpoint: endpoint.name.clone(), event_id: event.id };
+         if let Err(err) = self.queue.enqueue(job, message_id).await {
+            warn!("Failed queueing {} webhook for event {}. Error: {}", endpoint.name, event.id, err);
+         }
+      }
+   }
+}
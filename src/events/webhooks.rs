@@ -0,0 +1,157 @@
+use std::{env, sync::Arc, time::Duration as StdDuration};
+use chrono::Utc;
+use reqwest::{Client, Url};
+use serde_json::json;
+
+use crate::{
+   models::event::{EventKind, MessageEvent},
+   security::signing
+};
+
+const SIGNATURE_HEADER: &str = "X-Mailer-Signature";
+//* Response bodies are only kept for the delivery log, a glimpse is enough
+const MAX_LOGGED_BODY: usize = 256;
+
+pub struct WebhookEndpoint {
+   pub name: String,
+   pub url: Url,
+   secret: String,
+   //* None means every event
+   pub events: Option<Vec<EventKind>>
+}
+
+impl WebhookEndpoint {
+   pub fn wants(&self, kind: EventKind) -> bool {
+      match &self.events {
+         Some(events) => events.contains(&kind),
+         None => true
+      }
+   }
+}
+
+#[derive(Debug)]
+pub struct WebhookErr {
+   pub msg: String,
+   pub permanent: bool
+}
+
+/// Outbound webhooks, configured through `WEBHOOKS` (comma separated endpoint
+/// names) and `WEBHOOK_<NAME>_{URL,SECRET,EVENTS}`
+#[derive(Clone)]
+pub struct Webhooks {
+   endpoints: Arc<Vec<WebhookEndpoint>>,
+   client: Client
+}
+
+fn endpoint_from_env(name: &str) -> WebhookEndpoint {
+   let var = |suffix: &str| format!("WEBHOOK_{}_{}", name.to_uppercase().replace('-', "_"), suffix);
+
+   let url = match env::var(var("URL")).map(|url| Url::parse(&url)) {
+      Ok(Ok(url)) if url.scheme() == "https" || url.scheme() == "http" => url,
+      _ => panic!("{} must be set to a valid http(s) URL", var("URL"))
+   };
+   let secret = match env::var(var("SECRET")) {
+      Ok(secret) if !secret.is_empty() => secret,
+      _ => panic!("{} must be set, payloads are always signed", var("SECRET"))
+   };
+   let events = match env::var(var("EVENTS")) {
+      Ok(events) if !events.trim().is_empty() && events.trim() != "*" => Some(
+         events.split(',')
+            .map(|event| match EventKind::from_name(event) {
+               Some(kind) => kind,
+               None => panic!("{} has an unknown event \"{}\"", var("EVENTS"), event.trim())
+            })
+            .collect()
+      ),
+      _ => None
+   };
+
+   WebhookEndpoint { name: name.to_string(), url, secret, events }
+}
+
+impl Webhooks {
+   pub fn from_env() -> Self {
+      let endpoints = match env::var("WEBHOOKS") {
+         Ok(names) => names.split(',')
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .map(endpoint_from_env)
+            .collect(),
+         Err(_) => Vec::new()
+      };
+
+      let timeout = match env::var("WEBHOOK_TIMEOUT_SECS") {
+         Ok(val) => match val.parse::<u64>() {
+            Ok(secs) if secs > 0 => StdDuration::from_secs(secs),
+            _ => panic!("WEBHOOK_TIMEOUT_SECS must be a positive amount of seconds")
+         },
+         Err(_) => StdDuration::from_secs(10)
+      };
+      let client = Client::builder()
+         .timeout(timeout)
+         .user_agent(concat!("rust-mailer-api-webhooks/", env!("CARGO_PKG_VERSION")))
+         .build()
+         .expect("Failed building the webhooks HTTP client");
+
+      Webhooks { endpoints: Arc::new(endpoints), client }
+   }
+
+   pub fn endpoints(&self) -> &[WebhookEndpoint] {
+      &self.endpoints
+   }
+
+   pub fn endpoint(&self, name: &str) -> Option<&WebhookEndpoint> {
+      self.endpoints.iter().find(|endpoint| endpoint.name == name)
+   }
+
+   pub fn subscribed(&self, kind: EventKind) -> impl Iterator<Item = &WebhookEndpoint> {
+      self.endpoints.iter().filter(move |endpoint| endpoint.wants(kind))
+   }
+
+   /// POSTs the event to the endpoint. The body is signed as
+   /// `hex(HMAC-SHA256(secret, "<timestamp>.<body>"))` and sent along with the
+   /// timestamp in the `X-Mailer-Signature: t=<timestamp>,v1=<signature>` header,
+   /// so receivers can also reject replayed deliveries
+   pub async fn deliver(&self, endpoint: &WebhookEndpoint, event: &MessageEvent, delivery_id: &str) -> Result<(), WebhookErr> {
+      let body = json!({
+         "id": event.id.to_hex(),
+         "type": event.kind,
+         "message_id": event.message_id.to_hex(),
+         "occurred_at": event.occurred_at.to_chrono().to_rfc3339(),
+         "data": event.data
+      }).to_string();
+
+      let timestamp = Utc::now().timestamp();
+      let signature = signing::hmac_sha256_hex(endpoint.secret.as_bytes(), format!("{}.{}", timestamp, body).as_bytes());
+
+      let res = self.client.post(endpoint.url.clone())
+         .header("Content-Type", "application/json")
+         .header("X-Mailer-Event", event.kind.as_str())
+         .header("X-Mailer-Event-Id", event.id.to_hex())
+         .header("X-Mailer-Delivery", delivery_id)
+         .header(SIGNATURE_HEADER, format!("t={},v1={}", timestamp, signature))
+         .body(body)
+         .send().await
+         .map_err(|err| WebhookErr { msg: format!("Request failed: {}", err), permanent: false })?;
+
+      let status = res.status();
+      if status.is_success() {
+         return Ok(());
+      }
+
+      let mut excerpt = res.text().await.unwrap_or_default();
+      if excerpt.len() > MAX_LOGGED_BODY {
+         let mut end = MAX_LOGGED_BODY;
+         while !excerpt.is_char_boundary(end) {
+            end -= 1;
+         }
+         excerpt.truncate(end);
+      }
+
+      Err(WebhookErr {
+         msg: format!("Endpoint answered HTTP {}: {}", status.as_u16(), excerpt),
+         //* 410 Gone is how receivers tell they don't want deliveries anymore
+         permanent: status.as_u16() == 410
+      })
+   }
+}
@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+   mailer::{Mailer, reply},
+   models::message::{Message, ThreadEntry},
+   security::sanitizers::unescape_html
+};
+
+/// Header carrying the message id, so exported emails can be told apart when imported back
+pub const MESSAGE_ID_HEADER: &str = "X-Mailer-Message-Id";
+
+/// One email of a conversation, rendered as RFC 5322 with CRLF line endings
+pub struct RawEmail {
+   //* The message id, followed by the thread email's id for those of the thread
+   pub key: String,
+   pub sender: String,
+   pub date: DateTime<Utc>,
+   pub content: String
+}
+
+//* RFC 2047 encoded-word for anything that isn't plain ASCII, line breaks are never kept
+fn encode_header(value: &str) -> String {
+   let value = value.replace(|c: char| c == '\r' || c == '\n', " ");
+
+   match value.is_ascii() {
+      true => value,
+      false => format!("=?UTF-8?B?{}?=", base64::encode(value.as_bytes()))
+   }
+}
+
+fn mailbox(name: &str, email: &str) -> String {
+   let name = name.trim();
+   match name.is_empty() {
+      true => format!("<{}>", encode_header(email)),
+      false if name.is_ascii() => format!("\"{}\" <{}>", encode_header(&name.replace('\\', "\\\\").replace('"', "\\\"")), encode_header(email)),
+      false => format!("{} <{}>", encode_header(name), encode_header(email))
+   }
+}
+
+fn render(headers: &[(&str, String)], body: &str) -> String {
+   let mut content = String::new();
+   for (name, value) in headers.iter() {
+      content.push_str(&format!("{}: {}\r\n", name, value));
+   }
+   content.push_str("MIME-Version: 1.0\r\n");
+   content.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+   content.push_str("Content-Transfer-Encoding: 8bit\r\n\r\n");
+
+   for line in body.lines() {
+      content.push_str(line);
+      content.push_str("\r\n");
+   }
+
+   content
+}
+
+/// The contact form message as the email owners got, followed by every email of its thread.
+/// Stored fields are HTML-escaped, emails get them back as plain text
+pub fn message_emails(mailer: &Mailer, msg: &Message) -> Vec<RawEmail> {
+   let date = msg.created_at.map(|date| date.to_chrono()).unwrap_or_else(Utc::now);
+
+   let mut headers = vec![
+      ("From", mailbox(&unescape_html(&msg.name), &msg.from)),
+      ("To", match mailer.owners().is_empty() {
+         true => "undisclosed-recipients:;".to_string(),
+         false => mailer.owners().iter().map(|owner| owner.to_string()).collect::<Vec<String>>().join(", ")
+      }),
+      ("Subject", encode_header(&unescape_html(&msg.subject))),
+      ("Date", date.to_rfc2822())
+   ];
+   if let Some(msg_oid) = msg.id {
+      headers.push(("Message-ID", reply::conversation_root(mailer, &msg_oid)));
+      headers.push((MESSAGE_ID_HEADER, msg_oid.to_hex()));
+   }
+
+   let mut emails = vec![ RawEmail {
+      key: msg.id.map(|id| id.to_hex()).unwrap_or_default(),
+      sender: msg.from.clone(),
+      date,
+      content: render(&headers, &unescape_html(&msg.message))
+   } ];
+   emails.extend(msg.thread.iter().map(|entry| thread_email(msg, entry)));
+
+   emails
+}
+
+fn thread_email(msg: &Message, entry: &ThreadEntry) -> RawEmail {
+   let date = entry.created_at.to_chrono();
+
+   let mut headers = vec![
+      ("From", encode_header(&entry.from)),
+      ("To", encode_header(&entry.to)),
+      ("Subject", encode_header(&unescape_html(&entry.subject))),
+      ("Date", date.to_rfc2822()),
+      ("Message-ID", encode_header(&entry.message_id))
+   ];
+   if let Some(in_reply_to) = &entry.in_reply_to {
+      headers.push(("In-Reply-To", encode_header(in_reply_to)));
+   }
+   if !entry.references.is_empty() {
+      headers.push(("References", encode_header(&entry.references.join(" "))));
+   }
+   let key = format!("{}.{}", msg.id.map(|id| id.to_hex()).unwrap_or_default(), entry.id.to_hex());
+   headers.push((MESSAGE_ID_HEADER, key.clone()));
+
+   RawEmail {
+      key,
+      sender: entry.from.clone(),
+      date,
+      content: render(&headers, &unescape_html(&entry.body))
+   }
+}
+
+/// mboxrd entry: a "From " separator line, then the email with LF line endings
+/// and every line starting with (any number of ">" and) "From " quoted once more
+pub fn mbox_entry(email: &RawEmail) -> String {
+   let sender = match email.sender.trim() {
+      sender if sender.is_empty() || sender.contains(char::is_whitespace) => "MAILER-DAEMON",
+      sender => sender
+   };
+
+   let mut entry = format!("From {} {}\n", sender, email.date.format("%a %b %e %H:%M:%S %Y"));
+   for line in email.content.lines() {
+      if line.trim_start_matches('>').starts_with("From ") {
+         entry.push('>');
+      }
+      entry.push_str(line);
+      entry.push('\n');
+   }
+   entry.push('\n');
+
+   entry
+}
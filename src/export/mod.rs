@@ -0,0 +1,33 @@
+mod mail;
+mod zip_stream;
+
+use rocket::{
+   http::ContentType,
+   response::{self, Responder, Response},
+   Request
+};
+
+pub use mail::*;
+pub use zip_stream::ZipStream;
+
+/// Serves `body` as a file download named `filename`
+pub struct Attachment<R> {
+   body: R,
+   content_type: ContentType,
+   filename: String
+}
+
+impl<R> Attachment<R> {
+   pub fn new(body: R, content_type: ContentType, filename: &str) -> Self {
+      Attachment { body, content_type, filename: filename.to_string() }
+   }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Attachment<R> {
+   fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+      Response::build_from(self.body.respond_to(req)?)
+         .header(self.content_type)
+         .raw_header("Content-Disposition", format!("attachment; filename=\"{}\"", self.filename))
+         .ok()
+   }
+}
@@ -0,0 +1,169 @@
+use std::{
+   io::{self, Seek, SeekFrom, Write},
+   sync::{Arc, Mutex}
+};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use zip::{
+   write::FileOptions,
+   result::ZipResult,
+   CompressionMethod,
+   DateTime as ZipDateTime,
+   ZipWriter
+};
+
+/// Bytes written to the archive that haven't been handed out yet. The zip
+/// writer seeks back to fill in each file's header once it's done with it, so
+/// only what precedes the file being written can be let go
+#[derive(Default)]
+struct Pending {
+   buf: Vec<u8>,
+   //* Archive offset of `buf[0]`
+   offset: u64,
+   pos: u64
+}
+
+#[derive(Clone, Default)]
+struct Sink(Arc<Mutex<Pending>>);
+
+impl Sink {
+   fn position(&self) -> u64 {
+      self.0.lock().unwrap().pos
+   }
+
+   /// Takes out everything written before `until`
+   fn drain(&self, until: u64) -> Vec<u8> {
+      let mut pending = self.0.lock().unwrap();
+      let len = (until.saturating_sub(pending.offset) as usize).min(pending.buf.len());
+
+      pending.offset += len as u64;
+      pending.buf.drain(..len).collect()
+   }
+}
+
+impl Write for Sink {
+   fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+      let mut pending = self.0.lock().unwrap();
+      let start = (pending.pos - pending.offset) as usize;
+      let end = start + data.len();
+
+      if end > pending.buf.len() {
+         pending.buf.resize(end, 0);
+      }
+      pending.buf[start..end].copy_from_slice(data);
+      pending.pos += data.len() as u64;
+
+      Ok(data.len())
+   }
+
+   fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+   }
+}
+
+fn offset_by(pos: u64, delta: i64) -> Option<u64> {
+   match delta >= 0 {
+      true => pos.checked_add(delta as u64),
+      false => pos.checked_sub(delta.unsigned_abs())
+   }
+}
+
+impl Seek for Sink {
+   fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+      let mut pending = self.0.lock().unwrap();
+      let end = pending.offset + pending.buf.len() as u64;
+
+      let pos = match to {
+         SeekFrom::Start(pos) => Some(pos),
+         SeekFrom::Current(delta) => offset_by(pending.pos, delta),
+         SeekFrom::End(delta) => offset_by(end, delta)
+      };
+      match pos {
+         Some(pos) if pos >= pending.offset && pos <= end => {
+            pending.pos = pos;
+            Ok(pos)
+         },
+         _ => Err(io::Error::new(io::ErrorKind::Unsupported, "Seeking into data already sent"))
+      }
+   }
+}
+
+/// Zip archive built file by file, handing out its bytes as soon as they are final
+pub struct ZipStream {
+   writer: ZipWriter<Sink>,
+   sink: Sink
+}
+
+fn zip_date(date: DateTime<Utc>) -> ZipDateTime {
+   ZipDateTime::from_date_and_time(
+      date.year() as u16, date.month() as u8, date.day() as u8,
+      date.hour() as u8, date.minute() as u8, date.second() as u8
+   ).unwrap_or_default()
+}
+
+impl ZipStream {
+   pub fn new() -> Self {
+      let sink = Sink::default();
+      ZipStream { writer: ZipWriter::new(sink.clone()), sink }
+   }
+
+   /// Adds a file, returning the archive bytes that are now final
+   pub fn add(&mut self, name: &str, modified: DateTime<Utc>, content: &[u8]) -> ZipResult<Vec<u8>> {
+      //* Everything before this point is final once the previous file is closed by `start_file`
+      let settled = self.sink.position();
+
+      let options = FileOptions::default()
+         .compression_method(CompressionMethod::Deflated)
+         .last_modified_time(zip_date(modified));
+      self.writer.start_file(name, options)?;
+      self.writer.write_all(content)?;
+
+      Ok(self.sink.drain(settled))
+   }
+
+   /// Closes the archive, returning its remaining bytes
+   pub fn finish(mut self) -> ZipResult<Vec<u8>> {
+      self.writer.finish()?;
+      Ok(self.sink.drain(u64::MAX))
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use std::io::{Cursor, Read};
+   use zip::ZipArchive;
+   use super::*;
+
+   #[test]
+   fn streamed_archive_is_readable() {
+      let files = [
+         ("first.eml", "Subject: Hi\r\n\r\nHello!\r\n".repeat(50)),
+         ("second.eml", "Subject: Re: Hi\r\n\r\nHello back!\r\n".to_string())
+      ];
+
+      let mut zip = ZipStream::new();
+      let mut archive = Vec::new();
+      for (name, content) in files.iter() {
+         archive.extend(zip.add(name, Utc::now(), content.as_bytes()).unwrap());
+      }
+      archive.extend(zip.finish().unwrap());
+
+      let mut read = ZipArchive::new(Cursor::new(archive)).unwrap();
+      assert_eq!(read.len(), files.len());
+      for (name, content) in files.iter() {
+         let mut file = read.by_name(name).unwrap();
+         let mut unzipped = String::new();
+         file.read_to_string(&mut unzipped).unwrap();
+
+         assert_eq!(&unzipped, content);
+      }
+   }
+
+   #[test]
+   fn bytes_are_handed_out_before_the_end() {
+      let mut zip = ZipStream::new();
+
+      assert!(zip.add("first.eml", Utc::now(), b"first").unwrap().is_empty());
+      //* Adding a file settles the one before it
+      assert!(!zip.add("second.eml", Utc::now(), b"second").unwrap().is_empty());
+   }
+}
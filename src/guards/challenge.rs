@@ -0,0 +1,43 @@
+use rocket::{
+   http::Status as HttpStatus,
+   request::{FromRequest, Outcome},
+   async_trait
+};
+
+use crate::security::challenge::{ChallengeConfig, ChallengeErr, CHALLENGE_HEADER};
+
+/// Passes once the request solved the configured challenge, or right away when
+/// challenges are off. Failures are answered with 428 by `error_catcher::challenge_required`
+pub struct ChallengePassed;
+
+/// Why the challenge failed, kept in the request cache for the catcher
+pub struct ChallengeFailure(pub Option<ChallengeErr>);
+
+#[async_trait]
+impl<'r> FromRequest<'r> for ChallengePassed {
+   type Error = ChallengeErr;
+
+   async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
+      let verifier = match request.rocket().state::<ChallengeConfig>().and_then(|config| config.verifier.as_ref()) {
+         Some(verifier) => verifier,
+         None => return Outcome::Success(ChallengePassed)
+      };
+
+      let res = match request.headers().get_one(CHALLENGE_HEADER) {
+         Some(response) if !response.trim().is_empty() => verifier.verify(response, request.client_ip()).await,
+         _ => Err(ChallengeErr::Missing)
+      };
+
+      match res {
+         Ok(()) => Outcome::Success(ChallengePassed),
+         Err(err) => {
+            if let ChallengeErr::Provider(msg) = &err {
+               warn!("Failed verifying a {} challenge: {}", verifier.name(), msg);
+            }
+            request.local_cache(|| ChallengeFailure(Some(err.clone())));
+
+            Outcome::Failure((HttpStatus::new(428), err))
+         }
+      }
+   }
+}
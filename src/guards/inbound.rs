@@ -0,0 +1,37 @@
+use std::env;
+use rocket::{
+   http::Status as HttpStatus,
+   request::{FromRequest, Outcome},
+   async_trait
+};
+
+use crate::security::signing::constant_time_eq;
+
+const SECRET_HEADER: &str = "X-Inbound-Secret";
+
+/// Authenticates mail forwarders (webhooks, local LMTP bridges) through a
+/// shared secret, as they can't go through Auth0
+pub struct InboundAuth;
+
+#[derive(Debug)]
+pub enum InboundAuthErr {
+   Disabled,
+   Unauthorized
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for InboundAuth {
+   type Error = InboundAuthErr;
+
+   async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
+      let secret = match env::var("INBOUND_SECRET") {
+         Ok(val) if !val.is_empty() => val,
+         _ => return Outcome::Failure((HttpStatus::new(404), InboundAuthErr::Disabled))
+      };
+
+      match request.headers().get_one(SECRET_HEADER) {
+         Some(given) if constant_time_eq(given.as_bytes(), secret.as_bytes()) => Outcome::Success(InboundAuth),
+         _ => Outcome::Failure((HttpStatus::new(401), InboundAuthErr::Unauthorized))
+      }
+   }
+}
@@ -0,0 +1,22 @@
+use std::{convert::Infallible, str::FromStr};
+use mongodb::bson::oid::ObjectId;
+use rocket::{
+   request::{FromRequest, Outcome},
+   async_trait
+};
+
+/// Id of the last event an `EventSource` received, sent back by browsers when reconnecting
+pub struct LastEventId(pub Option<ObjectId>);
+
+#[async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+   type Error = Infallible;
+
+   async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
+      //* An unknown id is treated as no id at all: the stream only goes live
+      let last_id = request.headers().get_one("Last-Event-ID")
+         .and_then(|id| ObjectId::from_str(id.trim()).ok());
+
+      Outcome::Success(LastEventId(last_id))
+   }
+}
@@ -1,5 +1,13 @@
 mod auth;
+mod challenge;
+mod inbound;
+mod last_event_id;
 mod rate_limit;
+mod server_limit;
 
 pub use auth::*;
-pub use rate_limit::*;
\ No newline at end of file
+pub use challenge::*;
+pub use inbound::*;
+pub use last_event_id::*;
+pub use rate_limit::*;
+pub use server_limit::*;
\ No newline at end of file
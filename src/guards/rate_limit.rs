@@ -1,35 +1,151 @@
+use std::{cmp::Reverse, io::Cursor};
+use regex::Regex;
+use serde_json::json;
 use rocket::{
-   Request, 
-   Data, 
-   futures::future::BoxFuture, http::uri::Origin,
+   Request,
+   Response,
+   Data,
+   fairing::{Fairing, Info, Kind},
+   http::{uri::Origin, ContentType, Status as HttpStatus},
+   request::Outcome,
+   async_trait,
    uri
 };
-use tokio::sync::RwLock;
-use super::super::security::RateLimitState;
+use super::super::security::{Identity, RateDecision, RatePolicies};
+use super::Auth;
 
-pub struct PerMinRateLimit(pub RwLock<RateLimitState>);
+//* Rocket doesn't let fairings peek further into the body
+const PEEK_BYTES: usize = 512;
+const SENDER_RGX: &str = r#""from"\s*:\s*"([^"\\]{1,320})""#;
 
-pub fn rate_limiter<'a>(req: &'a mut Request<'_>, _data: &'a Data<'_>) -> BoxFuture<'a, ()> {
-   Box::pin(async move {
-      let ip = req.client_ip();
-      if ip.is_none() {
-         req.set_uri(Origin::from(uri!("/420")));
-         return;
+/// Counts every request against the rate limit policies matching it, and tells
+/// clients where they stand through the `RateLimit-*` headers of the response
+pub struct RateLimiter;
+
+/// How the request fared, kept in the request cache for the response. The decision
+/// is the most restrictive of the policies it was counted by, none when no policy matched
+pub struct RateLimited {
+   pub limited: bool,
+   pub decision: Option<RateDecision>
+}
+
+//* Whole seconds, rounded up so clients never come back too early
+fn ceil_secs(duration: chrono::Duration) -> i64 {
+   (duration.num_milliseconds() + 999).div_euclid(1000).max(0)
+}
+
+//* Denied beats allowed, then the longest wait, then the fewest requests left
+fn stricter(a: RateDecision, b: RateDecision) -> RateDecision {
+   let rank = |decision: &RateDecision| (!decision.allowed, decision.retry_after, Reverse(decision.remaining));
+   if rank(&b) > rank(&a) { b } else { a }
+}
+
+//* Sender of a JSON body, as long as it shows up in the peeked bytes
+async fn peeked_sender(data: &mut Data<'_>) -> Option<String> {
+   let peeked = String::from_utf8_lossy(data.peek(PEEK_BYTES).await).into_owned();
+
+   Regex::new(SENDER_RGX).unwrap()
+      .captures(&peeked)
+      .map(|caps| caps[1].trim().to_lowercase())
+}
+
+async fn identity_key(req: &Request<'_>, data: &mut Data<'_>, identity: Identity) -> Option<String> {
+   match identity {
+      Identity::Sub => {
+         //* The token is verified here as well as by the route, an unverified `sub` could be anything
+         if let Outcome::Success(auth) = req.guard::<Auth>().await {
+            if let Some(sub) = auth.decoded_payload.sub {
+               return Some(format!("sub:{}", sub));
+            }
+         }
+      },
+      Identity::Email => {
+         if req.content_type().map_or(false, |ct| ct.is_json()) {
+            if let Some(sender) = peeked_sender(data).await {
+               return Some(format!("email:{}", sender));
+            }
+         }
+      },
+      Identity::Ip => {}
+   }
+
+   req.client_ip().map(|ip| format!("ip:{}", ip))
+}
+
+#[async_trait]
+impl Fairing for RateLimiter {
+   fn info(&self) -> Info {
+      Info {
+         name: "Rate limit handler",
+         kind: Kind::Request | Kind::Response
       }
-      let ip = ip.unwrap().to_string();
+   }
 
-      let rate_state = req.rocket().state::<PerMinRateLimit>();
-      if rate_state.is_none() {
+   async fn on_request(&self, req: &mut Request<'_>, data: &mut Data<'_>) {
+      let policies = req.rocket().state::<RatePolicies>();
+      if policies.is_none() {
          req.set_uri(Origin::from(uri!("/500")));
          return;
       }
-      let mut rate_state = rate_state.unwrap().0.write().await;
-      
-      let on_the_limit = rate_state.full_check_on_the_limit(ip);
-      if !on_the_limit {
-         req.set_uri(Origin::from(uri!("/420")));
+
+      let matching = policies.unwrap().matching(req.method(), req.uri().path().as_str());
+      let mut strictest: Option<RateDecision> = None;
+      for policy in matching {
+         let key = identity_key(req, data, policy.identity).await;
+         if key.is_none() {
+            req.local_cache(|| RateLimited { limited: true, decision: Some(policy.refused()) });
+            req.set_uri(Origin::from(uri!("/420")));
+            return;
+         }
+
+         let decision = match policy.hit(key.unwrap()).await {
+            Ok(decision) => decision,
+            Err(err) => {
+               //* A limiter outage shouldn't take the whole API down with it
+               warn!("Rate limit policy \"{}\" failed counting a request, letting it through: {}", policy.name, err);
+               continue;
+            }
+         };
+         strictest = Some(match strictest {
+            Some(current) => stricter(current, decision),
+            None => decision
+         });
+      }
+
+      //* Limited requests are sent nowhere so no route runs, `on_response` answers them
+      if let Some(decision) = strictest {
+         req.local_cache(|| RateLimited { limited: !decision.allowed, decision: Some(decision) });
+         if !decision.allowed {
+            req.set_uri(Origin::from(uri!("/420")));
+         }
       }
+   }
+
+   async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+      let outcome = req.local_cache(|| RateLimited { limited: false, decision: None });
+      let retry_after = outcome.decision
+         .filter(|decision| !decision.allowed)
+         .map(|decision| ceil_secs(decision.retry_after).max(1));
+
+      if outcome.limited {
+         let body = json!({
+            "error": "Enhance your calm bro. A.k.a.: You're being rate limited!",
+            "retry_after": retry_after,
+            "http_cat": "https://http.cat/429"
+         }).to_string();
 
-      return;
-   })
-}
\ No newline at end of file
+         res.set_status(HttpStatus::TooManyRequests);
+         res.set_header(ContentType::JSON);
+         res.set_sized_body(body.len(), Cursor::new(body));
+      }
+
+      if let Some(decision) = outcome.decision {
+         res.set_raw_header("RateLimit-Limit", decision.limit.to_string());
+         res.set_raw_header("RateLimit-Remaining", decision.remaining.to_string());
+         res.set_raw_header("RateLimit-Reset", ceil_secs(decision.reset_after).to_string());
+      }
+      if let Some(secs) = retry_after {
+         res.set_raw_header("Retry-After", secs.to_string());
+      }
+   }
+}
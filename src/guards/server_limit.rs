@@ -0,0 +1,70 @@
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use rocket::{
+   http::Status as HttpStatus,
+   request::{FromRequest, Outcome},
+   async_trait
+};
+
+use crate::security::ServerLimit;
+
+/// The global submissions cap, shared by the send route, health and admins
+pub struct ServerBreaker(pub Mutex<ServerLimit>);
+
+impl ServerBreaker {
+   pub fn lock(&self) -> std::sync::MutexGuard<'_, ServerLimit> {
+      match self.0.lock() {
+         Ok(limit) => limit,
+         Err(poisoned) => poisoned.into_inner()
+      }
+   }
+}
+
+/// Passes while the server limit isn't reached, holding one slot of it. The slot is
+/// given back when dropped, unless the route calls `accept` once the submission is
+/// stored. Failures are answered with 503 by `error_catcher::service_unavailable`
+pub struct ServerCapacity<'r> {
+   reserved: Option<(&'r ServerBreaker, DateTime<Utc>)>
+}
+
+impl ServerCapacity<'_> {
+   /// Keeps the slot, the submission counts against the server limit
+   pub fn accept(mut self) {
+      self.reserved = None;
+   }
+}
+
+//* Rejected or failed submissions don't eat into the server limit
+impl Drop for ServerCapacity<'_> {
+   fn drop(&mut self) {
+      if let Some((breaker, window_start)) = self.reserved.take() {
+         breaker.lock().release(window_start);
+      }
+   }
+}
+
+/// Seconds until the breaker closes again, kept in the request cache for the catcher
+pub struct ServerOverload(pub Option<i64>);
+
+#[async_trait]
+impl<'r> FromRequest<'r> for ServerCapacity<'r> {
+   type Error = ();
+
+   async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
+      let breaker = match request.rocket().state::<ServerBreaker>() {
+         Some(breaker) => breaker,
+         None => return Outcome::Success(ServerCapacity { reserved: None })
+      };
+
+      let mut limit = breaker.lock();
+      if let Some(window_start) = limit.reserve() {
+         return Outcome::Success(ServerCapacity { reserved: Some((breaker, window_start)) });
+      }
+
+      let retry_after = limit.status().retry_after;
+      drop(limit);
+      request.local_cache(|| ServerOverload(Some(retry_after.num_seconds().max(1))));
+
+      Outcome::Failure((HttpStatus::new(503), ()))
+   }
+}
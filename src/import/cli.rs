@@ -0,0 +1,46 @@
+use std::fs;
+use serde_json::json;
+
+use crate::store::Store;
+use super::{ImportFormat, RecordStatus, import_messages};
+
+const USAGE: &str = "Usage: rust-mailer-api import <mbox|eml|jsonl> <file>...";
+
+/// `import` command, same as the import route but reading files. The store is picked
+/// from the environment like the server does. Prints one JSON report per file, exits
+/// with 1 when any record failed and 2 on bad usage
+pub async fn run(args: Vec<String>) -> i32 {
+   let format = match args.first().map(|name| ImportFormat::from_name(name)) {
+      Some(Some(format)) if args.len() > 1 => format,
+      _ => {
+         eprintln!("{}", USAGE);
+         return 2;
+      }
+   };
+
+   let store = Store::from_env().await;
+   let mut failed = false;
+   for path in args[1..].iter() {
+      let raw = match fs::read(path) {
+         Ok(raw) => raw,
+         Err(err) => {
+            eprintln!("Failed reading {}: {}", path, err);
+            failed = true;
+            continue;
+         }
+      };
+
+      let report = import_messages(&store, format, &raw).await;
+      failed |= report.count(RecordStatus::Failed) > 0;
+
+      println!("{}", json!({
+         "file": path,
+         "report": report.to_json()
+      }));
+   }
+
+   match failed {
+      true => 1,
+      false => 0
+   }
+}
@@ -0,0 +1,444 @@
+mod cli;
+
+use std::{io::{Cursor, Read}, str::FromStr};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{json, Value as SerdeVal};
+use sha2::{Digest, Sha256};
+use mongodb::bson::{oid::ObjectId, DateTime as BsonDateTime};
+
+use crate::{
+   mailer::inbound::InboundEmail,
+   models::message::Message,
+   security::{sanitizers, signing::to_hex},
+   store::{Store, StoreErr}
+};
+
+pub use cli::run as run_cli;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportFormat {
+   Mbox,
+   Eml,
+   Jsonl
+}
+
+impl ImportFormat {
+   pub fn from_name(name: &str) -> Option<Self> {
+      match name.to_lowercase().as_str() {
+         "mbox" => Some(ImportFormat::Mbox),
+         "eml" => Some(ImportFormat::Eml),
+         "jsonl" | "ndjson" => Some(ImportFormat::Jsonl),
+         _ => None
+      }
+   }
+}
+
+/// A message read from the import, not yet checked against the store
+struct Candidate {
+   msg: Message,
+   //* Id the message had when it was exported, if it came from one of our exports
+   exported_id: Option<ObjectId>,
+   //* Thread emails of an export only ever land in a thread, never keep their id
+   thread_email: bool
+}
+
+/// One line of a JSON lines import, shaped like the messages of the API
+#[derive(Deserialize)]
+struct JsonRecord {
+   id: Option<String>,
+   from: String,
+   #[serde(default)]
+   name: String,
+   #[serde(default)]
+   subject: String,
+   #[serde(default)]
+   message: String,
+   locale: Option<String>,
+   read: Option<bool>,
+   archived: Option<bool>,
+   #[serde(alias = "created_at")]
+   sent_at: Option<String>,
+   message_id: Option<String>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordStatus {
+   Imported,
+   Duplicate,
+   Failed
+}
+
+impl RecordStatus {
+   fn as_str(&self) -> &'static str {
+      match self {
+         RecordStatus::Imported => "imported",
+         RecordStatus::Duplicate => "duplicate",
+         RecordStatus::Failed => "failed"
+      }
+   }
+}
+
+pub struct RecordOutcome {
+   //* Position of the email in the MBOX or zip, or line number for JSON lines
+   pub record: usize,
+   pub status: RecordStatus,
+   //* The message created or, for duplicates, the one already stored
+   pub id: Option<ObjectId>,
+   pub error: Option<String>
+}
+
+#[derive(Default)]
+pub struct ImportReport {
+   pub records: Vec<RecordOutcome>
+}
+
+impl ImportReport {
+   pub fn count(&self, status: RecordStatus) -> usize {
+      self.records.iter().filter(|outcome| outcome.status == status).count()
+   }
+
+   pub fn to_json(&self) -> SerdeVal {
+      json!({
+         "imported": self.count(RecordStatus::Imported),
+         "duplicates": self.count(RecordStatus::Duplicate),
+         "failed": self.count(RecordStatus::Failed),
+         "records": self.records.iter().map(|outcome| json!({
+            "record": outcome.record,
+            "status": outcome.status.as_str(),
+            "id": outcome.id.map(|id| id.to_string()),
+            "error": outcome.error
+         })).collect::<Vec<SerdeVal>>()
+      })
+   }
+}
+
+fn content_hash(msg: &Message) -> String {
+   let mut hasher = Sha256::new();
+   hasher.update(msg.from.to_lowercase().as_bytes());
+   hasher.update(b"\0");
+   hasher.update(msg.subject.as_bytes());
+   hasher.update(b"\0");
+   hasher.update(msg.message.as_bytes());
+   hasher.update(b"\0");
+   if let Some(created_at) = msg.created_at {
+      hasher.update(created_at.timestamp_millis().to_string().as_bytes());
+   }
+
+   to_hex(&hasher.finalize())
+}
+
+/// Validates and sanitizes the sender's fields the way the contact form does.
+/// Imported messages are history, they land read unless told otherwise
+fn candidate(from: &str, name: String, subject: String, message: String, created_at: Option<DateTime<Utc>>) -> Result<Message, String> {
+   let from = from.trim();
+   if !Regex::new(sanitizers::EMAIL_RGX).unwrap().is_match(from) {
+      return Err(format!("Invalid sender address \"{}\"", from));
+   }
+
+   let mut msg = Message {
+      id: None,
+      created_at: created_at.map(BsonDateTime::from),
+      from: from.to_string(),
+      name: sanitizers::message_sanitizing(name),
+      subject: sanitizers::message_sanitizing(subject),
+      message: sanitizers::message_sanitizing(message),
+      locale: None,
+      read: true,
+      archived: false,
+      delivery: None,
+      ack: None,
+      thread: Vec::new(),
+      deleted_at: None,
+      source_id: None,
+      content_hash: None,
+      spam: None
+   };
+   msg.content_hash = Some(content_hash(&msg));
+
+   Ok(msg)
+}
+
+fn email_candidate(raw: &[u8]) -> Result<Candidate, String> {
+   let email = InboundEmail::parse(raw).ok_or_else(|| "Not a valid RFC 5322 email, or it has no sender address".to_string())?;
+
+   let mut msg = candidate(&email.from_addr, email.from_name.unwrap_or_default(), email.subject, email.text, email.date)?;
+   msg.source_id = email.message_id;
+
+   let (exported_id, thread_email) = match email.export_key.as_deref().map(|key| key.split_once('.')) {
+      Some(Some((msg_id, _))) => (ObjectId::from_str(msg_id).ok(), true),
+      Some(None) => (email.export_key.as_deref().and_then(|key| ObjectId::from_str(key).ok()), false),
+      None => (None, false)
+   };
+
+   Ok(Candidate { msg, exported_id, thread_email })
+}
+
+fn json_candidate(line: &str) -> Result<Candidate, String> {
+   let record: JsonRecord = serde_json::from_str(line).map_err(|err| format!("Invalid JSON record: {}", err))?;
+
+   let sent_at = match record.sent_at.as_deref().map(DateTime::parse_from_rfc3339) {
+      Some(Ok(date)) => Some(date.with_timezone(&Utc)),
+      Some(Err(_)) => return Err("Invalid \"sent_at\", must be an RFC 3339 date".to_string()),
+      None => None
+   };
+
+   let mut msg = candidate(&record.from, record.name, record.subject, record.message, sent_at)?;
+   msg.locale = record.locale;
+   msg.read = record.read.unwrap_or(true);
+   msg.archived = record.archived.unwrap_or(false);
+   msg.source_id = record.message_id;
+
+   Ok(Candidate {
+      msg,
+      exported_id: record.id.as_deref().and_then(|id| ObjectId::from_str(id).ok()),
+      thread_email: false
+   })
+}
+
+/// Splits an mboxrd/mboxo file on its "From " lines, unquoting the body lines
+/// the mboxrd way (one ">" less on lines starting with ">From ")
+fn mbox_emails(raw: &[u8]) -> Vec<Result<Vec<u8>, String>> {
+   let mut emails = Vec::new();
+   let mut current: Option<Vec<u8>> = None;
+   let mut leading = Vec::new();
+
+   for line in raw.split_inclusive(|byte| *byte == b'\n') {
+      if line.starts_with(b"From ") {
+         if let Some(email) = current.replace(Vec::new()) {
+            emails.push(Ok(email));
+         }
+         continue;
+      }
+
+      match current.as_mut() {
+         Some(email) => {
+            let quotes = line.iter().take_while(|byte| **byte == b'>').count();
+            match quotes > 0 && line[quotes..].starts_with(b"From ") {
+               true => email.extend_from_slice(&line[1..]),
+               false => email.extend_from_slice(line)
+            }
+         },
+         None => leading.extend_from_slice(line)
+      }
+   }
+   if let Some(email) = current {
+      emails.push(Ok(email));
+   }
+
+   if !leading.iter().all(u8::is_ascii_whitespace) {
+      emails.insert(0, Err("Data found before the first \"From \" line, not an MBOX file".to_string()));
+   }
+
+   emails
+}
+
+/// Every `.eml` file of a zip, as our EML exports are shaped
+fn zipped_emails(raw: &[u8]) -> Vec<Result<Vec<u8>, String>> {
+   let mut archive = match zip::ZipArchive::new(Cursor::new(raw)) {
+      Ok(archive) => archive,
+      Err(err) => return vec![ Err(format!("Invalid zip file: {}", err)) ]
+   };
+
+   let mut emails = Vec::new();
+   for idx in 0..archive.len() {
+      let mut file = match archive.by_index(idx) {
+         Ok(file) => file,
+         Err(err) => {
+            emails.push(Err(format!("Unreadable zip entry: {}", err)));
+            continue;
+         }
+      };
+      if file.is_dir() || !file.name().to_lowercase().ends_with(".eml") {
+         continue;
+      }
+
+      let mut email = Vec::new();
+      emails.push(match file.read_to_end(&mut email) {
+         Ok(_) => Ok(email),
+         Err(err) => Err(format!("Unreadable zip entry {}: {}", file.name(), err))
+      });
+   }
+
+   emails
+}
+
+fn candidates(format: ImportFormat, raw: &[u8]) -> Vec<(usize, Result<Candidate, String>)> {
+   match format {
+      //* Either a single email or a zip of them
+      ImportFormat::Eml if raw.starts_with(b"PK\x03\x04") => zipped_emails(raw).into_iter()
+         .enumerate()
+         .map(|(idx, email)| (idx + 1, email.and_then(|email| email_candidate(&email))))
+         .collect(),
+      ImportFormat::Eml => vec![ (1, email_candidate(raw)) ],
+      ImportFormat::Mbox => mbox_emails(raw).into_iter()
+         .enumerate()
+         .map(|(idx, email)| (idx + 1, email.and_then(|email| email_candidate(&email))))
+         .collect(),
+      ImportFormat::Jsonl => raw.split(|byte| *byte == b'\n')
+         .enumerate()
+         .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+         .map(|(idx, line)| (idx + 1, match std::str::from_utf8(line) {
+            Ok(line) => json_candidate(line),
+            Err(_) => Err("Line is not valid UTF-8".to_string())
+         }))
+         .collect()
+   }
+}
+
+/// Message already holding this record: the one it was exported from, the one
+/// whose thread holds it, or one imported earlier from the same email or content
+async fn stored_duplicate(store: &Store, candidate: &Candidate) -> Result<Option<ObjectId>, StoreErr> {
+   if let Some(exported_id) = candidate.exported_id {
+      if let Some(msg) = store.messages.find(exported_id).await? {
+         if !candidate.thread_email || msg.thread.iter().any(|entry| Some(&entry.message_id) == candidate.msg.source_id.as_ref()) {
+            return Ok(Some(exported_id));
+         }
+      }
+   }
+
+   if let Some(source_id) = &candidate.msg.source_id {
+      if let Some(msg) = store.messages.find_by_thread_email(&[ source_id.clone() ]).await? {
+         return Ok(msg.id);
+      }
+   }
+
+   store.messages.find_imported(
+      candidate.msg.source_id.as_deref(),
+      candidate.msg.content_hash.as_deref().unwrap_or_default()
+   ).await
+}
+
+/// Stores every record that isn't already stored. Bad records are reported and
+/// skipped, the rest of the import goes on. No event is published and no email
+/// is sent for imported messages
+pub async fn import_messages(store: &Store, format: ImportFormat, raw: &[u8]) -> ImportReport {
+   let mut report = ImportReport::default();
+
+   for (record, candidate) in candidates(format, raw) {
+      let outcome = |status, id, error| RecordOutcome { record, status, id, error };
+
+      let mut candidate = match candidate {
+         Ok(candidate) => candidate,
+         Err(err) => {
+            report.records.push(outcome(RecordStatus::Failed, None, Some(err)));
+            continue;
+         }
+      };
+
+      match stored_duplicate(store, &candidate).await {
+         Ok(Some(id)) => {
+            report.records.push(outcome(RecordStatus::Duplicate, Some(id), None));
+            continue;
+         },
+         Ok(None) => (),
+         Err(err) => {
+            warn!("Failed checking imported record {} for duplicates. Error: {}", record, err);
+            report.records.push(outcome(RecordStatus::Failed, None, Some("Failed checking for duplicates".to_string())));
+            continue;
+         }
+      }
+
+      //* Without their conversation, thread emails would turn into messages of their own
+      if candidate.thread_email {
+         let parent = match candidate.exported_id {
+            Some(exported_id) => store.messages.find(exported_id).await.map(|msg| msg.is_some()),
+            None => Ok(false)
+         };
+         match parent {
+            Ok(true) => (),
+            Ok(false) => {
+               report.records.push(outcome(RecordStatus::Failed, None, Some("Parent conversation not found".to_string())));
+               continue;
+            },
+            Err(err) => {
+               warn!("Failed looking up the conversation of imported record {}. Error: {}", record, err);
+               report.records.push(outcome(RecordStatus::Failed, None, Some("Failed looking up the parent conversation".to_string())));
+               continue;
+            }
+         }
+      }
+
+      //* Restoring an export keeps the message ids, so links to them still work
+      if !candidate.thread_email {
+         candidate.msg.id = candidate.exported_id;
+      }
+      match store.messages.insert(&candidate.msg).await {
+         Ok(id) => report.records.push(outcome(RecordStatus::Imported, Some(id), None)),
+         Err(err) => {
+            warn!("Failed storing imported record {}. Error: {}", record, err);
+            report.records.push(outcome(RecordStatus::Failed, None, Some("Failed storing the message".to_string())));
+         }
+      }
+   }
+
+   report
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::export::{mbox_entry, RawEmail};
+
+   fn raw_email(content: &str) -> RawEmail {
+      RawEmail {
+         key: ObjectId::new().to_hex(),
+         sender: "jane@example.com".to_string(),
+         date: Utc::now(),
+         content: content.to_string()
+      }
+   }
+
+   #[test]
+   fn mboxrd_quoting_round_trips() {
+      let content = "From: Jane <jane@example.com>\r\nSubject: Hi\r\n\r\nFrom here\r\n>From there\r\n>>From afar\r\nFrom.\r\n";
+      let mbox = [ mbox_entry(&raw_email(content)), mbox_entry(&raw_email("Subject: Bye\r\n\r\nBye!\r\n")) ].concat();
+
+      let emails = mbox_emails(mbox.as_bytes()).into_iter()
+         .map(|email| String::from_utf8(email.unwrap()).unwrap())
+         .collect::<Vec<String>>();
+      assert_eq!(emails.len(), 2);
+      assert_eq!(emails[0].trim_end(), content.replace("\r\n", "\n").trim_end());
+      assert_eq!(emails[1].trim_end(), "Subject: Bye\n\nBye!");
+   }
+
+   #[test]
+   fn data_before_the_first_separator_is_an_error() {
+      let emails = mbox_emails(b"Subject: Hi\n\nHello\n");
+      assert!(emails[0].is_err());
+   }
+
+   #[rocket::async_test]
+   async fn records_already_stored_are_duplicates() {
+      let store = Store::memory();
+      let jsonl = concat!(
+         r#"{"from":"jane@example.com","name":"Jane","subject":"Hi","message":"Hello!","sent_at":"2024-01-02T03:04:05Z"}"#, "\n",
+         r#"{"from":"john@example.com","name":"John","subject":"Yo","message":"Hey!","message_id":"<1@example.com>"}"#, "\n",
+         r#"{"from":"JANE@example.com","name":"Jane","subject":"Hi","message":"Hello!","sent_at":"2024-01-02T03:04:05Z"}"#, "\n"
+      );
+
+      let first = import_messages(&store, ImportFormat::Jsonl, jsonl.as_bytes()).await;
+      assert_eq!(first.count(RecordStatus::Imported), 2);
+      //* Same sender, content and date as the first line
+      assert_eq!(first.records[2].status, RecordStatus::Duplicate);
+      assert_eq!(first.records[2].id, first.records[0].id);
+
+      let again = import_messages(&store, ImportFormat::Jsonl, jsonl.as_bytes()).await;
+      assert_eq!(again.count(RecordStatus::Duplicate), 3);
+      assert_eq!(again.records.iter().map(|outcome| outcome.id).collect::<Vec<_>>(), first.records.iter().map(|outcome| outcome.id).collect::<Vec<_>>());
+   }
+
+   #[rocket::async_test]
+   async fn thread_emails_need_their_conversation() {
+      let store = Store::memory();
+      let eml = format!(
+         "From: Jane <jane@example.com>\r\nTo: owner@example.com\r\nSubject: Re: Hi\r\nMessage-ID: <2@example.com>\r\n{}: {}.{}\r\n\r\nThanks!\r\n",
+         crate::export::MESSAGE_ID_HEADER, ObjectId::new().to_hex(), ObjectId::new().to_hex()
+      );
+
+      let report = import_messages(&store, ImportFormat::Eml, eml.as_bytes()).await;
+      assert_eq!(report.records[0].status, RecordStatus::Failed);
+      assert_eq!(report.records[0].error.as_deref(), Some("Parent conversation not found"));
+   }
+}
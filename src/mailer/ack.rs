@@ -0,0 +1,78 @@
+use std::{env, time::Duration as StdDuration};
+use chrono::Duration;
+use lettre::{
+   Message as Email,
+   message::{Mailbox, MultiPart},
+};
+
+use crate::{
+   models::message::Message,
+   security::{RateLimitState, RateType}
+};
+use super::{Mailer, MailerErr, templates::{MailTemplates, TemplateKind, TemplateVars}};
+
+pub struct AckConfig {
+   pub enabled: bool,
+   throttle: RateLimitState<String>
+}
+
+impl AckConfig {
+   pub fn from_env() -> Self {
+      let enabled = match env::var("ACK_ENABLED") {
+         Ok(val) => val.eq_ignore_ascii_case("true") || val == "1",
+         Err(_) => false
+      };
+
+      let max_acks = match env::var("ACK_THROTTLE_MAX") {
+         Ok(val) => val.parse::<u32>().expect("ACK_THROTTLE_MAX must be a positive integer"),
+         Err(_) => 3
+      };
+      let window = match env::var("ACK_THROTTLE_WINDOW_MINS") {
+         Ok(val) => Duration::minutes(val.parse::<i64>().expect("ACK_THROTTLE_WINDOW_MINS must be a positive integer")),
+         Err(_) => Duration::hours(1)
+      };
+
+      AckConfig {
+         enabled,
+         throttle: RateLimitState::new(RateType::new(window, max_acks))
+      }
+   }
+
+   /// Counts an acknowledgement towards the address' quota, returns false when
+   /// the address already got too many of them
+   pub fn allow(&self, addr: &str) -> bool {
+      self.throttle.hit(addr.trim().to_lowercase()).allowed
+   }
+
+   /// Forgets addresses whose acknowledgements all fell out of the window, every `every`
+   pub fn spawn_evictor(&self, every: StdDuration) {
+      self.throttle.spawn_evictor(every);
+   }
+}
+
+/// Builds the automatic reply confirming the sender that their message arrived
+pub fn acknowledgement(mailer: &Mailer, templates: &MailTemplates, msg: &Message) -> Result<Email, MailerErr> {
+   let sender = match mailer.sender() {
+      Some(sender) => sender.clone(),
+      None => return Err(MailerErr::Config("outbound delivery is disabled".to_string()))
+   };
+   let msg_oid = match msg.id {
+      Some(oid) => oid,
+      None => return Err(MailerErr::Build("message has not been stored yet".to_string()))
+   };
+   let recipient = match Mailer::parse_address(&msg.from) {
+      Some(addr) => Mailbox::new(Some(msg.name.clone()), addr),
+      None => return Err(MailerErr::Build(format!("invalid sender address \"{}\"", msg.from)))
+   };
+
+   let rendered = templates.render(TemplateKind::Acknowledgement, msg.locale.as_deref(), &TemplateVars::for_message(msg))
+      .map_err(|e| MailerErr::Build(e.to_string()))?;
+
+   Email::builder()
+      .message_id(Some(mailer.message_id(&format!("{}.ack", msg_oid.to_hex()))))
+      .from(sender)
+      .to(recipient)
+      .subject(rendered.subject)
+      .multipart(MultiPart::alternative_plain_html(rendered.text, rendered.html))
+      .map_err(|e| MailerErr::Build(e.to_string()))
+}
@@ -0,0 +1,139 @@
+use std::str::FromStr;
+use chrono::{DateTime, TimeZone, Utc};
+use mail_parser::{MessageParser, MimeHeaders, Address as ParsedAddress, HeaderValue};
+use mongodb::bson::oid::ObjectId;
+
+use crate::{export::MESSAGE_ID_HEADER, models::message::AttachmentMeta};
+use super::Mailer;
+
+/// The parts of a raw RFC 5322 email the mailer cares about
+pub struct InboundEmail {
+   pub message_id: Option<String>,
+   pub in_reply_to: Vec<String>,
+   pub references: Vec<String>,
+   pub from_addr: String,
+   pub from_name: Option<String>,
+   pub recipients: Vec<String>,
+   pub subject: String,
+   pub text: String,
+   pub attachments: Vec<AttachmentMeta>,
+   pub date: Option<DateTime<Utc>>,
+   //* Message id stamped by our own exports, followed by the thread email's id for those of a thread
+   pub export_key: Option<String>
+}
+
+fn with_brackets(id: &str) -> String {
+   format!("<{}>", id.trim().trim_start_matches('<').trim_end_matches('>'))
+}
+
+fn id_list(value: &HeaderValue) -> Vec<String> {
+   value.as_text_list()
+      .unwrap_or_default()
+      .into_iter()
+      .flat_map(|ids| ids.split_whitespace())
+      .map(with_brackets)
+      .collect()
+}
+
+fn addresses(value: Option<&ParsedAddress>) -> Vec<String> {
+   match value {
+      Some(addrs) => addrs.iter()
+         .filter_map(|addr| addr.address())
+         .map(|addr| addr.to_lowercase())
+         .collect(),
+      None => Vec::new()
+   }
+}
+
+impl InboundEmail {
+   pub fn parse(raw: &[u8]) -> Option<Self> {
+      let parsed = MessageParser::default().parse(raw)?;
+
+      let from = parsed.from().and_then(|from| from.first())?;
+      let from_addr = from.address()?.to_lowercase();
+      let from_name = from.name().map(|name| name.to_string());
+
+      let mut recipients = addresses(parsed.to());
+      recipients.extend(addresses(parsed.cc()));
+      //* Forwarders usually keep the original envelope recipient here
+      if let Some(delivered_to) = parsed.header_raw("Delivered-To") {
+         recipients.push(delivered_to.trim().trim_start_matches('<').trim_end_matches('>').to_lowercase());
+      }
+
+      //* Prefers the plain text alternative, falling back to the HTML one stripped of its tags
+      let text = match parsed.body_text(0) {
+         Some(text) => text.to_string(),
+         None => parsed.body_html(0)
+            .map(|html| ammonia::Builder::empty().clean(&html).to_string())
+            .unwrap_or_default()
+      };
+
+      let attachments = parsed.attachments()
+         .map(|part| AttachmentMeta {
+            name: part.attachment_name().map(|name| name.to_string()),
+            content_type: part.content_type()
+               .map(|ct| match ct.subtype() {
+                  Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+                  None => ct.ctype().to_string()
+               })
+               .unwrap_or_else(|| "application/octet-stream".to_string()),
+            size: part.len() as u64
+         })
+         .collect();
+
+      Some(InboundEmail {
+         message_id: parsed.message_id().map(with_brackets),
+         in_reply_to: id_list(parsed.in_reply_to()),
+         references: id_list(parsed.references()),
+         from_addr,
+         from_name,
+         recipients,
+         subject: parsed.subject().unwrap_or_default().to_string(),
+         text,
+         attachments,
+         date: parsed.date()
+            .filter(|date| date.is_valid())
+            .and_then(|date| Utc.timestamp_opt(date.to_timestamp(), 0).single()),
+         export_key: parsed.header_raw(MESSAGE_ID_HEADER).map(|key| key.trim().to_string())
+      })
+   }
+
+   /// Conversation id carried by our tagged reply address (e.g.: replies+<id>@domain),
+   /// any other tagged recipient is ignored
+   pub fn tagged_conversation(&self, mailer: &Mailer) -> Option<ObjectId> {
+      self.recipients.iter().find_map(|addr| {
+         let tag = mailer.reply_tag(addr)?;
+
+         ObjectId::from_str(&tag).ok()
+      })
+   }
+
+   /// Conversation id carried by one of the Message-IDs we generated, which are
+   /// shaped as `<id[.suffix]@our-domain>`
+   pub fn referenced_conversation(&self, mailer: &Mailer) -> Option<ObjectId> {
+      let domain = mailer.message_id_domain();
+
+      self.in_reply_to.iter()
+         .chain(self.references.iter().rev())
+         .find_map(|id| {
+            let (local_part, id_domain) = id.trim_start_matches('<').trim_end_matches('>').split_once('@')?;
+            if !id_domain.eq_ignore_ascii_case(&domain) {
+               return None;
+            }
+
+            ObjectId::from_str(local_part.split('.').next()?).ok()
+         })
+   }
+
+   /// Every Message-ID this email refers to, for matching against stored threads
+   pub fn all_references(&self) -> Vec<String> {
+      let mut ids = self.in_reply_to.clone();
+      for id in self.references.iter() {
+         if !ids.contains(id) {
+            ids.push(id.clone());
+         }
+      }
+
+      ids
+   }
+}
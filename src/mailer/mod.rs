@@ -0,0 +1,8 @@
+mod smtp;
+pub mod ack;
+pub mod inbound;
+pub mod relay;
+pub mod reply;
+pub mod templates;
+
+pub use smtp::*;
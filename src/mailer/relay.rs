@@ -0,0 +1,39 @@
+use lettre::{
+   Message as Email,
+   message::{Mailbox, MultiPart},
+};
+use crate::models::message::Message;
+use super::{Mailer, MailerErr, templates::{MailTemplates, TemplateKind, TemplateVars}};
+
+/// Builds the email relaying a contact form message to the configured owners
+pub fn owner_notification(mailer: &Mailer, templates: &MailTemplates, msg: &Message) -> Result<Email, MailerErr> {
+   let sender = match mailer.sender() {
+      Some(sender) => sender.clone(),
+      None => return Err(MailerErr::Config("outbound delivery is disabled".to_string()))
+   };
+   let msg_oid = match msg.id {
+      Some(oid) => oid,
+      None => return Err(MailerErr::Build("message has not been stored yet".to_string()))
+   };
+
+   let rendered = templates.render(TemplateKind::OwnerNotification, Some(templates.owner_locale()), &TemplateVars::for_message(msg))
+      .map_err(|e| MailerErr::Build(e.to_string()))?;
+
+   let mut builder = Email::builder()
+      .message_id(Some(mailer.message_id(&msg_oid.to_hex())))
+      .from(sender)
+      .subject(rendered.subject);
+
+   for owner in mailer.owners() {
+      builder = builder.to(owner.clone());
+   }
+
+   //* Lets owners answer straight from their mail client
+   if let Some(addr) = Mailer::parse_address(&msg.from) {
+      builder = builder.reply_to(Mailbox::new(Some(msg.name.clone()), addr));
+   }
+
+   builder
+      .multipart(MultiPart::alternative_plain_html(rendered.text, rendered.html))
+      .map_err(|e| MailerErr::Build(e.to_string()))
+}
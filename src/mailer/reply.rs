@@ -0,0 +1,70 @@
+use lettre::{
+   Message as Email,
+   message::{Mailbox, MultiPart},
+};
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::message::{Message, ThreadEntry};
+use super::{Mailer, MailerErr, templates::{MailTemplates, TemplateKind, TemplateVars}};
+
+/// Message-ID of the conversation's first email (the owners notification)
+pub fn conversation_root(mailer: &Mailer, msg_oid: &ObjectId) -> String {
+   mailer.message_id(&msg_oid.to_hex())
+}
+
+pub fn reply_message_id(mailer: &Mailer, msg_oid: &ObjectId, entry_id: &ObjectId) -> String {
+   mailer.message_id(&format!("{}.{}", msg_oid.to_hex(), entry_id.to_hex()))
+}
+
+/// Computes `In-Reply-To` and `References` for a new email appended to the thread:
+/// it answers the latest email exchanged, and references every one of them
+pub fn threading_headers(mailer: &Mailer, msg: &Message, msg_oid: &ObjectId) -> (String, Vec<String>) {
+   let mut references = vec![ conversation_root(mailer, msg_oid) ];
+   for entry in msg.thread.iter() {
+      if !references.contains(&entry.message_id) {
+         references.push(entry.message_id.clone());
+      }
+   }
+
+   let in_reply_to = references.last().cloned().unwrap();
+   (in_reply_to, references)
+}
+
+/// Builds the email of an outbound thread entry
+pub fn reply(mailer: &Mailer, templates: &MailTemplates, msg: &Message, entry: &ThreadEntry) -> Result<Email, MailerErr> {
+   let sender = match mailer.sender() {
+      Some(sender) => sender.clone(),
+      None => return Err(MailerErr::Config("outbound delivery is disabled".to_string()))
+   };
+   let recipient = match Mailer::parse_address(&entry.to) {
+      Some(addr) => Mailbox::new(Some(msg.name.clone()), addr),
+      None => return Err(MailerErr::Build(format!("invalid recipient address \"{}\"", entry.to)))
+   };
+
+   //* Replies are written in the sender's language, so is the quoting around them
+   let rendered = templates.render(TemplateKind::Reply, msg.locale.as_deref(), &TemplateVars::for_reply(msg, entry))
+      .map_err(|e| MailerErr::Build(e.to_string()))?;
+
+   let mut builder = Email::builder()
+      .message_id(Some(entry.message_id.clone()))
+      .from(sender)
+      .to(recipient)
+      .subject(rendered.subject);
+
+   //* Routes the sender's answer back to the inbound endpoint
+   if let Some(msg_oid) = msg.id {
+      if let Some(addr) = mailer.tagged_reply_addr(&msg_oid.to_hex()) {
+         builder = builder.reply_to(Mailbox::new(mailer.sender().and_then(|sender| sender.name.clone()), addr));
+      }
+   }
+   if let Some(in_reply_to) = &entry.in_reply_to {
+      builder = builder.in_reply_to(in_reply_to.clone());
+   }
+   if !entry.references.is_empty() {
+      builder = builder.references(entry.references.join(" "));
+   }
+
+   builder
+      .multipart(MultiPart::alternative_plain_html(rendered.text, rendered.html))
+      .map_err(|e| MailerErr::Build(e.to_string()))
+}
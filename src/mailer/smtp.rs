@@ -0,0 +1,258 @@
+use std::{env, fmt};
+use lettre::{
+   AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+   Address,
+   Message as Email,
+   message::Mailbox,
+   transport::smtp::authentication::{Credentials, Mechanism},
+};
+
+#[derive(Debug)]
+pub enum MailerErr {
+   Config(String),
+   Build(String),
+   Transport(String)
+}
+
+impl fmt::Display for MailerErr {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+         MailerErr::Config(msg) => write!(f, "invalid SMTP configuration: {}", msg),
+         MailerErr::Build(msg) => write!(f, "failed building email: {}", msg),
+         MailerErr::Transport(msg) => write!(f, "SMTP transport error: {}", msg)
+      }
+   }
+}
+
+pub enum SmtpSecurity {
+   //* Plain text connection, only meant for local SMTP sinks (e.g.: mailpit, MailHog)
+   None,
+   StartTls,
+   Tls
+}
+
+impl SmtpSecurity {
+   fn from_env_val(val: &str) -> Result<Self, MailerErr> {
+      match val.to_lowercase().as_str() {
+         "none" => Ok(SmtpSecurity::None),
+         "starttls" => Ok(SmtpSecurity::StartTls),
+         "tls" => Ok(SmtpSecurity::Tls),
+         other => Err(MailerErr::Config(format!("unknown SMTP_SECURITY value \"{}\"", other)))
+      }
+   }
+}
+
+pub struct SmtpConfig {
+   pub host: String,
+   pub port: Option<u16>,
+   pub security: SmtpSecurity,
+   pub credentials: Option<(String, String)>,
+   pub mechanisms: Option<Vec<Mechanism>>,
+   pub sender: Mailbox,
+   pub owners: Vec<Mailbox>
+}
+
+fn parse_mailbox(val: &str, var: &str) -> Result<Mailbox, MailerErr> {
+   val.trim().parse::<Mailbox>()
+      .map_err(|e| MailerErr::Config(format!("{} contains an invalid address \"{}\": {}", var, val, e)))
+}
+
+impl SmtpConfig {
+   /// Reads the relay config from the environment. Returns `Ok(None)` when
+   /// `SMTP_HOST` is not set, meaning outbound delivery is disabled.
+   pub fn from_env() -> Result<Option<Self>, MailerErr> {
+      let host = match env::var("SMTP_HOST") {
+         Ok(val) if !val.is_empty() => val,
+         _ => return Ok(None)
+      };
+
+      let port = match env::var("SMTP_PORT") {
+         Ok(val) => Some(val.parse::<u16>()
+            .map_err(|_| MailerErr::Config(format!("SMTP_PORT \"{}\" is not a valid port", val)))?),
+         Err(_) => None
+      };
+
+      let security = match env::var("SMTP_SECURITY") {
+         Ok(val) => SmtpSecurity::from_env_val(&val)?,
+         Err(_) => SmtpSecurity::StartTls
+      };
+
+      let credentials = match (env::var("SMTP_USER"), env::var("SMTP_PASS")) {
+         (Ok(user), Ok(pass)) => Some((user, pass)),
+         (Err(_), Err(_)) => None,
+         _ => return Err(MailerErr::Config("SMTP_USER and SMTP_PASS must be set together".to_string()))
+      };
+
+      let mechanisms = match env::var("SMTP_AUTH") {
+         Ok(val) => {
+            let mut mechanisms = Vec::new();
+            for mechanism in val.split(',') {
+               mechanisms.push(match mechanism.trim().to_lowercase().as_str() {
+                  "plain" => Mechanism::Plain,
+                  "login" => Mechanism::Login,
+                  "xoauth2" => Mechanism::Xoauth2,
+                  other => return Err(MailerErr::Config(format!("unknown SMTP_AUTH mechanism \"{}\"", other)))
+               });
+            }
+            Some(mechanisms)
+         },
+         Err(_) => None
+      };
+
+      let sender = match env::var("SMTP_FROM") {
+         Ok(val) => parse_mailbox(&val, "SMTP_FROM")?,
+         Err(_) => return Err(MailerErr::Config("SMTP_FROM must be set when SMTP_HOST is".to_string()))
+      };
+
+      let owners = match env::var("MAILER_OWNER_ADDRS") {
+         Ok(val) => val.split(',')
+            .filter(|addr| !addr.trim().is_empty())
+            .map(|addr| parse_mailbox(addr, "MAILER_OWNER_ADDRS"))
+            .collect::<Result<Vec<Mailbox>, MailerErr>>()?,
+         Err(_) => Vec::new()
+      };
+      if owners.is_empty() {
+         return Err(MailerErr::Config("MAILER_OWNER_ADDRS must list at least one address when SMTP_HOST is set".to_string()));
+      }
+
+      Ok(Some(SmtpConfig {
+         host,
+         port,
+         security,
+         credentials,
+         mechanisms,
+         sender,
+         owners
+      }))
+   }
+}
+
+#[derive(Clone)]
+struct SmtpRelay {
+   transport: AsyncSmtpTransport<Tokio1Executor>,
+   sender: Mailbox,
+   owners: Vec<Mailbox>
+}
+
+#[derive(Clone)]
+pub struct Mailer {
+   relay: Option<SmtpRelay>,
+   //* Base address sender replies are routed to, tagged per conversation (e.g.: replies+<id>@domain)
+   reply_addr: Option<Address>
+}
+
+fn reply_addr_from_env() -> Result<Option<Address>, MailerErr> {
+   match env::var("INBOUND_REPLY_ADDR") {
+      Ok(val) if !val.is_empty() => val.trim().parse::<Address>()
+         .map(Some)
+         .map_err(|e| MailerErr::Config(format!("INBOUND_REPLY_ADDR \"{}\" is invalid: {}", val, e))),
+      _ => Ok(None)
+   }
+}
+
+impl Mailer {
+   pub fn from_env() -> Result<Self, MailerErr> {
+      match SmtpConfig::from_env()? {
+         Some(config) => Self::new(config),
+         None => {
+            warn!("SMTP_HOST is not set, outbound email delivery is disabled");
+            Ok(Mailer { relay: None, reply_addr: reply_addr_from_env()? })
+         }
+      }
+   }
+
+   pub fn new(config: SmtpConfig) -> Result<Self, MailerErr> {
+      let builder = match config.security {
+         SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+         SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+            .map_err(|e| MailerErr::Config(e.to_string()))?,
+         SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+            .map_err(|e| MailerErr::Config(e.to_string()))?
+      };
+
+      let builder = match config.port {
+         Some(port) => builder.port(port),
+         None => builder
+      };
+      let builder = match config.credentials {
+         Some((user, pass)) => builder.credentials(Credentials::new(user, pass)),
+         None => builder
+      };
+      let builder = match config.mechanisms {
+         Some(mechanisms) => builder.authentication(mechanisms),
+         None => builder
+      };
+
+      Ok(Mailer {
+         relay: Some(SmtpRelay {
+            transport: builder.build(),
+            sender: config.sender,
+            owners: config.owners
+         }),
+         reply_addr: reply_addr_from_env()?
+      })
+   }
+
+   pub fn is_enabled(&self) -> bool {
+      self.relay.is_some()
+   }
+
+   pub fn sender(&self) -> Option<&Mailbox> {
+      self.relay.as_ref().map(|relay| &relay.sender)
+   }
+
+   pub fn owners(&self) -> &[Mailbox] {
+      match &self.relay {
+         Some(relay) => &relay.owners,
+         None => &[]
+      }
+   }
+
+   pub fn message_id_domain(&self) -> String {
+      self.sender()
+         .map(|sender| sender.email.domain().to_string())
+         .unwrap_or_else(|| "localhost".to_string())
+   }
+
+   /// Builds a RFC 5322 Message-ID on the sender's domain
+   pub fn message_id(&self, local_part: &str) -> String {
+      format!("<{}@{}>", local_part, self.message_id_domain())
+   }
+
+   /// Reply address tagged with the conversation's id, if inbound replies are set up
+   pub fn tagged_reply_addr(&self, tag: &str) -> Option<Address> {
+      self.reply_addr.as_ref().and_then(|addr| {
+         format!("{}+{}@{}", addr.user(), tag, addr.domain()).parse::<Address>().ok()
+      })
+   }
+
+   /// Tag of `addr` when it's one of our tagged reply addresses, the user part and
+   /// domain of `INBOUND_REPLY_ADDR` with a `+<tag>` suffix
+   pub fn reply_tag(&self, addr: &str) -> Option<String> {
+      let reply_addr = self.reply_addr.as_ref()?;
+      let (local_part, domain) = addr.trim().rsplit_once('@')?;
+      let (user, tag) = local_part.split_once('+')?;
+
+      if !user.eq_ignore_ascii_case(reply_addr.user()) || !domain.eq_ignore_ascii_case(reply_addr.domain()) {
+         return None;
+      }
+
+      Some(tag.to_string())
+   }
+
+   pub fn parse_address(addr: &str) -> Option<Address> {
+      addr.parse::<Address>().ok()
+   }
+
+   pub async fn send(&self, email: Email) -> Result<(), MailerErr> {
+      let relay = match &self.relay {
+         Some(relay) => relay,
+         None => return Err(MailerErr::Config("outbound delivery is disabled".to_string()))
+      };
+
+      match relay.transport.send(email).await {
+         Ok(_) => Ok(()),
+         Err(err) => Err(MailerErr::Transport(err.to_string()))
+      }
+   }
+}
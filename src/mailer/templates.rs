@@ -0,0 +1,383 @@
+use std::{env, fmt, fs, path::Path, sync::Arc, collections::BTreeSet};
+use handlebars::{Handlebars, no_escape};
+use serde::Serialize;
+use chrono::Utc;
+
+use crate::{
+   models::message::{Message, ThreadEntry},
+   security::sanitizers::unescape_html
+};
+
+const DEFAULT_DIR: &str = "templates";
+const DEFAULT_LOCALE: &str = "en";
+//* Every template is made of these three files: `<kind>.<locale>.<part>.hbs`
+const PARTS: [&str; 3] = ["subject", "txt", "html"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TemplateKind {
+   OwnerNotification,
+   Acknowledgement,
+   Reply
+}
+
+impl TemplateKind {
+   pub const ALL: [TemplateKind; 3] = [
+      TemplateKind::OwnerNotification,
+      TemplateKind::Acknowledgement,
+      TemplateKind::Reply
+   ];
+
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         TemplateKind::OwnerNotification => "owner_notification",
+         TemplateKind::Acknowledgement => "acknowledgement",
+         TemplateKind::Reply => "reply"
+      }
+   }
+
+   pub fn from_name(name: &str) -> Option<Self> {
+      TemplateKind::ALL.iter().copied().find(|kind| kind.as_str() == name)
+   }
+}
+
+#[derive(Debug)]
+pub enum TemplateErr {
+   Io(String),
+   Compile(String),
+   Missing(String),
+   Render(String)
+}
+
+impl fmt::Display for TemplateErr {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+         TemplateErr::Io(msg) => write!(f, "Template IO error: {}", msg),
+         TemplateErr::Compile(msg) => write!(f, "Template compile error: {}", msg),
+         TemplateErr::Missing(msg) => write!(f, "Missing template: {}", msg),
+         TemplateErr::Render(msg) => write!(f, "Template render error: {}", msg)
+      }
+   }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MessageVars {
+   pub id: String,
+   pub name: String,
+   pub from: String,
+   pub subject: String,
+   pub message: String,
+   pub sent_at: String
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ReplyVars {
+   pub subject: String,
+   pub body: String,
+   pub author: Option<String>
+}
+
+/// Variables available to every template. Message fields were already
+/// sanitized when stored, HTML templates may print them with `{{{ }}}`.
+/// Subject and plain text templates get them unescaped
+#[derive(Serialize, Clone, Debug)]
+pub struct TemplateVars {
+   pub message: MessageVars,
+   pub reply: Option<ReplyVars>,
+   //* Original message with every line prefixed by "> "
+   pub quoted: String
+}
+
+impl TemplateVars {
+   pub fn for_message(msg: &Message) -> Self {
+      TemplateVars {
+         message: MessageVars {
+            id: msg.id.map(|oid| oid.to_hex()).unwrap_or_default(),
+            name: msg.name.clone(),
+            from: msg.from.clone(),
+            subject: msg.subject.clone(),
+            message: msg.message.clone(),
+            sent_at: msg.created_at
+               .map(|date| date.to_chrono().to_rfc2822())
+               .unwrap_or_default()
+         },
+         reply: None,
+         quoted: msg.message.lines()
+            .map(|line| format!("> {}", line))
+            .collect::<Vec<String>>()
+            .join("\n")
+      }
+   }
+
+   pub fn for_reply(msg: &Message, entry: &ThreadEntry) -> Self {
+      TemplateVars {
+         reply: Some(ReplyVars {
+            subject: entry.subject.clone(),
+            body: entry.body.clone(),
+            author: entry.author.clone()
+         }),
+         ..TemplateVars::for_message(msg)
+      }
+   }
+
+   //* Same variables with the sanitizer's entities turned back into text
+   fn unescaped(&self) -> Self {
+      TemplateVars {
+         message: MessageVars {
+            name: unescape_html(&self.message.name),
+            subject: unescape_html(&self.message.subject),
+            message: unescape_html(&self.message.message),
+            ..self.message.clone()
+         },
+         reply: self.reply.as_ref().map(|reply| ReplyVars {
+            subject: unescape_html(&reply.subject),
+            body: unescape_html(&reply.body),
+            author: reply.author.clone()
+         }),
+         quoted: unescape_html(&self.quoted)
+      }
+   }
+
+   /// Placeholder data used to validate templates and for previews
+   pub fn sample() -> Self {
+      let message = "Hi!\nI'd like to know more about your work.";
+
+      TemplateVars {
+         message: MessageVars {
+            id: "000000000000000000000000".to_string(),
+            name: "Jane Doe".to_string(),
+            from: "jane.doe@example.com".to_string(),
+            subject: "Hello there".to_string(),
+            message: message.to_string(),
+            sent_at: Utc::now().to_rfc2822()
+         },
+         reply: Some(ReplyVars {
+            subject: "Re: Hello there".to_string(),
+            body: "Thanks for reaching out, let's talk!".to_string(),
+            author: None
+         }),
+         quoted: message.lines()
+            .map(|line| format!("> {}", line))
+            .collect::<Vec<String>>()
+            .join("\n")
+      }
+   }
+}
+
+pub struct RenderedEmail {
+   pub locale: String,
+   pub subject: String,
+   pub text: String,
+   pub html: String
+}
+
+struct TemplateSet {
+   //* HTML escaping for the html part, none for subject and plain text
+   html: Handlebars<'static>,
+   text: Handlebars<'static>,
+   locales: BTreeSet<String>,
+   default_locale: String,
+   owner_locale: String
+}
+
+/// Handlebars templates of every email sent, loaded once from `MAIL_TEMPLATES_DIR`
+#[derive(Clone)]
+pub struct MailTemplates {
+   set: Arc<TemplateSet>
+}
+
+fn template_key(kind: &str, locale: &str, part: &str) -> String {
+   format!("{}.{}.{}", kind, locale, part)
+}
+
+fn normalize_locale(locale: &str) -> String {
+   locale.trim().replace('_', "-").to_lowercase()
+}
+
+impl MailTemplates {
+   pub fn from_env() -> Result<Self, TemplateErr> {
+      let dir = env::var("MAIL_TEMPLATES_DIR").unwrap_or_else(|_| DEFAULT_DIR.to_string());
+      let default_locale = match env::var("MAIL_DEFAULT_LOCALE") {
+         Ok(val) if !val.trim().is_empty() => normalize_locale(&val),
+         _ => DEFAULT_LOCALE.to_string()
+      };
+      let owner_locale = match env::var("MAIL_OWNER_LOCALE") {
+         Ok(val) if !val.trim().is_empty() => normalize_locale(&val),
+         _ => default_locale.clone()
+      };
+
+      MailTemplates::load(Path::new(&dir), default_locale, owner_locale)
+   }
+
+   /// Compiles every template of `dir` and renders each one with sample data,
+   /// so a broken template stops the server at startup instead of failing deliveries
+   pub fn load(dir: &Path, default_locale: String, owner_locale: String) -> Result<Self, TemplateErr> {
+      let mut html = Handlebars::new();
+      let mut text = Handlebars::new();
+      html.set_strict_mode(true);
+      text.set_strict_mode(true);
+      text.register_escape_fn(no_escape);
+
+      let entries = fs::read_dir(dir)
+         .map_err(|err| TemplateErr::Io(format!("\"{}\": {}", dir.display(), err)))?;
+
+      let mut locales = BTreeSet::new();
+      for entry in entries {
+         let path = entry.map_err(|err| TemplateErr::Io(err.to_string()))?.path();
+         let file_name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.ends_with(".hbs") => name.trim_end_matches(".hbs").to_string(),
+            _ => continue
+         };
+
+         let (kind, locale, part) = match file_name.split('.').collect::<Vec<&str>>()[..] {
+            [kind, locale, part] if TemplateKind::from_name(kind).is_some() && PARTS.contains(&part) => (kind, normalize_locale(locale), part),
+            _ => {
+               warn!("Ignoring unknown mail template file \"{}\"", path.display());
+               continue;
+            }
+         };
+
+         let registry = match part {
+            "html" => &mut html,
+            _ => &mut text
+         };
+         registry.register_template_file(&template_key(kind, &locale, part), &path)
+            .map_err(|err| TemplateErr::Compile(format!("\"{}\": {}", path.display(), err)))?;
+
+         locales.insert(locale);
+      }
+
+      let templates = MailTemplates {
+         set: Arc::new(TemplateSet { html, text, locales, default_locale, owner_locale })
+      };
+      templates.validate()?;
+
+      Ok(templates)
+   }
+
+   fn has_part(&self, kind: TemplateKind, locale: &str, part: &str) -> bool {
+      let key = template_key(kind.as_str(), locale, part);
+
+      match part {
+         "html" => self.set.html.has_template(&key),
+         _ => self.set.text.has_template(&key)
+      }
+   }
+
+   fn validate(&self) -> Result<(), TemplateErr> {
+      let sample = TemplateVars::sample();
+
+      for kind in TemplateKind::ALL {
+         if !PARTS.iter().all(|part| self.has_part(kind, &self.set.default_locale, part)) {
+            return Err(TemplateErr::Missing(format!(
+               "\"{}\" must have subject, txt and html parts for the default locale \"{}\"",
+               kind.as_str(), self.set.default_locale
+            )));
+         }
+
+         for locale in self.set.locales.iter() {
+            let present = PARTS.iter().filter(|part| self.has_part(kind, locale, part)).count();
+            //* A partial translation would mix languages within the same email
+            if present != 0 && present != PARTS.len() {
+               return Err(TemplateErr::Missing(format!(
+                  "\"{}\" for locale \"{}\" must have subject, txt and html parts", kind.as_str(), locale
+               )));
+            }
+            if present == PARTS.len() {
+               self.render_locale(kind, locale, &sample)?;
+            }
+         }
+      }
+
+      Ok(())
+   }
+
+   /// Best available locale: exact match, then its primary language, then the default one
+   pub fn resolve_locale(&self, kind: TemplateKind, locale: Option<&str>) -> String {
+      if let Some(locale) = locale.map(normalize_locale) {
+         let primary = locale.split('-').next().unwrap_or_default().to_string();
+
+         for candidate in [locale, primary] {
+            if self.has_part(kind, &candidate, "subject") {
+               return candidate;
+            }
+         }
+      }
+
+      self.set.default_locale.clone()
+   }
+
+   pub fn owner_locale(&self) -> &str {
+      &self.set.owner_locale
+   }
+
+   /// Locales with a full translation of the given template
+   pub fn locales(&self, kind: TemplateKind) -> Vec<String> {
+      self.set.locales.iter()
+         .filter(|locale| self.has_part(kind, locale, "subject"))
+         .cloned()
+         .collect()
+   }
+
+   fn render_locale(&self, kind: TemplateKind, locale: &str, vars: &TemplateVars) -> Result<RenderedEmail, TemplateErr> {
+      let render_err = |part: &str, err: handlebars::RenderError| TemplateErr::Render(format!(
+         "\"{}\": {}", template_key(kind.as_str(), locale, part), err
+      ));
+
+      let plain = vars.unescaped();
+      let subject = self.set.text.render(&template_key(kind.as_str(), locale, "subject"), &plain)
+         .map_err(|err| render_err("subject", err))?;
+      let text = self.set.text.render(&template_key(kind.as_str(), locale, "txt"), &plain)
+         .map_err(|err| render_err("txt", err))?;
+      let html = self.set.html.render(&template_key(kind.as_str(), locale, "html"), vars)
+         .map_err(|err| render_err("html", err))?;
+
+      Ok(RenderedEmail {
+         locale: locale.to_string(),
+         //* Headers can't span lines
+         subject: subject.split_whitespace().collect::<Vec<&str>>().join(" "),
+         text,
+         html
+      })
+   }
+
+   pub fn render(&self, kind: TemplateKind, locale: Option<&str>, vars: &TemplateVars) -> Result<RenderedEmail, TemplateErr> {
+      let locale = self.resolve_locale(kind, locale);
+
+      self.render_locale(kind, &locale, vars)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::security::sanitizers::message_sanitizing;
+
+   #[test]
+   fn only_html_parts_keep_entities() {
+      let templates = MailTemplates::load(Path::new(DEFAULT_DIR), DEFAULT_LOCALE.to_string(), DEFAULT_LOCALE.to_string()).unwrap();
+      let msg = Message {
+         id: None,
+         created_at: None,
+         from: "jane@example.com".to_string(),
+         name: message_sanitizing("Jane & co".to_string()),
+         subject: message_sanitizing("Q&A <3".to_string()),
+         message: message_sanitizing("Tom & Jerry <3".to_string()),
+         locale: None,
+         read: false,
+         archived: false,
+         delivery: None,
+         ack: None,
+         thread: Vec::new(),
+         deleted_at: None,
+         source_id: None,
+         content_hash: None,
+         spam: None
+      };
+
+      let rendered = templates.render(TemplateKind::OwnerNotification, None, &TemplateVars::for_message(&msg)).unwrap();
+      assert_eq!(rendered.subject, "[Contact] Q&A <3");
+      assert!(rendered.text.contains("From: Jane & co <jane@example.com>"));
+      assert!(rendered.text.contains("Tom & Jerry <3"));
+      assert!(rendered.html.contains("Tom &amp; Jerry &lt;3"));
+   }
+}
@@ -2,37 +2,181 @@
 #[macro_use]
 extern crate rocket;
 
-use tokio::sync::RwLock;
+use std::{env, process, sync::Mutex, time::Duration};
 
 #[cfg(debug_assertions)]
 use console_subscriber;
 
 mod auth;
+mod events;
+mod export;
 mod guards;
+mod import;
+mod mailer;
 mod models;
 mod mongo;
+mod queue;
+mod retention;
 mod routes_mod;
 mod security;
+mod spam;
+mod store;
 mod error_catcher;
 
 use rocket_cors::Cors;
 use auth::PublicKeys;
-use chrono::Duration;
-use guards::{rate_limiter, PerMinRateLimit};
-use mongo::MessageCmsDb;
-use rocket::fairing::AdHoc;
+use events::{EventBus, Webhooks};
+use guards::{RateLimiter, ServerBreaker};
+use mailer::{Mailer, ack::AckConfig, templates::MailTemplates};
+use queue::{JobQueue, QueueConfig, WorkerCtx};
+use retention::RetentionConfig;
+use rocket::{fairing::AdHoc, Rocket, Build};
 use routes_mod::*;
-use security::{RateLimitState, RateType, HeaderFairings};
+use security::{RatePolicies, RatePolicyConfig, LimiterStore, ServerLimit, HeaderFairings, challenge::ChallengeConfig, bot_trap::BotTrap};
+use spam::SpamFilter;
+use store::Store;
 
-#[launch]
-async fn rocket() -> _ {
+//* Clients idle for a whole window are dropped from the rate limiter this often
+const RATE_LIMIT_EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+#[rocket::main]
+async fn main() -> Result<(), rocket::Error> {
+    //* `import` runs the message import from the command line instead of serving
+    let mut args = env::args().skip(1);
+    if args.next().as_deref() == Some("import") {
+        process::exit(import::run_cli(args.collect()).await);
+    }
+
+    let _rocket = rocket().await.launch().await?;
+    Ok(())
+}
+
+async fn rocket() -> Rocket<Build> {
     #[cfg(debug_assertions)]
     console_subscriber::init();
 
     rocket::build()
         .attach(AdHoc::try_on_ignite(
-            "Message CMS DB Connection",
-            |rocket_build| async { Ok(rocket_build.manage(MessageCmsDb::init().await)) },
+            "Message store",
+            |rocket_build| async { Ok(rocket_build.manage(Store::from_env().await)) },
+        ))
+        .attach(AdHoc::try_on_ignite(
+            "SMTP mail relay",
+            |rocket_build| async {
+                match Mailer::from_env() {
+                    Ok(mailer) => Ok(rocket_build.manage(mailer)),
+                    Err(e) => {
+                        error!("Failed to set up the SMTP mail relay: {}", e);
+                        Err(rocket_build)
+                    }
+                }
+            },
+        ))
+        .attach(AdHoc::try_on_ignite(
+            "Mail templates",
+            |rocket_build| async {
+                match MailTemplates::from_env() {
+                    Ok(templates) => Ok(rocket_build.manage(templates)),
+                    Err(e) => {
+                        error!("Failed to load the mail templates: {}", e);
+                        Err(rocket_build)
+                    }
+                }
+            },
+        ))
+        .attach(AdHoc::try_on_ignite(
+            "Spam filter",
+            |rocket_build| async {
+                let filter = match SpamFilter::from_env() {
+                    Ok(filter) => filter,
+                    Err(e) => {
+                        error!("Failed to set up the spam filter: {}", e);
+                        return Err(rocket_build);
+                    }
+                };
+
+                //* The classifier only lives in memory, it learns again what admins labeled so far
+                if let Some(store) = rocket_build.state::<Store>() {
+                    match filter.train(store).await {
+                        Ok(learned) => info!("Spam classifier trained with {} labeled messages", learned),
+                        Err(e) => warn!("Failed training the spam classifier: {}", e)
+                    }
+                }
+
+                Ok(rocket_build.manage(filter))
+            },
+        ))
+        .attach(AdHoc::try_on_ignite(
+            "Submission challenge",
+            |rocket_build| async {
+                match ChallengeConfig::from_env() {
+                    Ok(config) => Ok(rocket_build.manage(config)),
+                    Err(e) => {
+                        error!("Failed to set up the submission challenge: {}", e);
+                        Err(rocket_build)
+                    }
+                }
+            },
+        ))
+        .attach(AdHoc::on_ignite(
+            "Server submissions limit",
+            |rocket_build| async { rocket_build.manage(ServerBreaker(Mutex::new(ServerLimit::from_env()))) },
+        ))
+        .attach(AdHoc::on_ignite(
+            "Bot trap",
+            |rocket_build| async { rocket_build.manage(BotTrap::from_env()) },
+        ))
+        .attach(AdHoc::on_ignite(
+            "Sender acknowledgement config",
+            |rocket_build| async { rocket_build.manage(AckConfig::from_env()) },
+        ))
+        .attach(AdHoc::try_on_ignite(
+            "Delivery job queue",
+            |rocket_build| async {
+                let jobs = match rocket_build.state::<Store>() {
+                    Some(store) => store.jobs.clone(),
+                    None => return Err(rocket_build)
+                };
+
+                Ok(rocket_build.manage(JobQueue::new(jobs, QueueConfig::from_env())))
+            },
+        ))
+        .attach(AdHoc::try_on_ignite(
+            "Message events and webhooks",
+            |rocket_build| async {
+                let bus = match (rocket_build.state::<Store>(), rocket_build.state::<JobQueue>()) {
+                    (Some(store), Some(queue)) => EventBus::new(store.events.clone(), Webhooks::from_env(), queue.clone()),
+                    _ => return Err(rocket_build)
+                };
+
+                Ok(rocket_build.manage(bus))
+            },
+        ))
+        .attach(AdHoc::on_liftoff(
+            "Delivery queue worker",
+            |rocket| Box::pin(async move {
+                match (
+                    rocket.state::<JobQueue>(), rocket.state::<Store>(),
+                    rocket.state::<Mailer>(), rocket.state::<MailTemplates>(), rocket.state::<EventBus>()
+                ) {
+                    (Some(queue), Some(store), Some(mailer), Some(templates), Some(events)) => queue::spawn_worker(queue.clone(), WorkerCtx {
+                        store: store.clone(),
+                        mailer: mailer.clone(),
+                        templates: templates.clone(),
+                        webhooks: events.webhooks().clone()
+                    }),
+                    _ => error!("Delivery queue worker could not start: missing managed state")
+                }
+            }),
+        ))
+        .attach(AdHoc::on_liftoff(
+            "Trash and retention purge task",
+            |rocket| Box::pin(async move {
+                match (rocket.state::<Store>(), rocket.state::<EventBus>()) {
+                    (Some(store), Some(events)) => retention::spawn_purger(store.clone(), events.clone(), RetentionConfig::from_env()),
+                    _ => error!("Trash and retention purge task could not start: missing managed state")
+                }
+            }),
         ))
         .attach(AdHoc::try_on_ignite(
             "Auth0 Public JWKS",
@@ -47,23 +191,51 @@ async fn rocket() -> _ {
             },
         ))
         .attach(AdHoc::try_on_ignite(
-            "Per minute rate limit state handler",
+            "Rate limit policies",
             |rocket_build| async {
-                let state_wrapper = PerMinRateLimit(RwLock::new(RateLimitState::new(
-                    RateType::new(Duration::minutes(1), 25),
-                    Duration::minutes(1),
-                )));
+                //* `[[default.rate_limits]]` tables of Rocket.toml, the built-in policy when there are none
+                let configs = match rocket_build.figment().extract_inner::<Vec<RatePolicyConfig>>("rate_limits") {
+                    Ok(configs) => configs,
+                    Err(e) if e.missing() => Vec::new(),
+                    Err(e) => {
+                        error!("Failed to read the rate limit policies: {}", e);
+                        return Err(rocket_build);
+                    }
+                };
 
-                Ok(rocket_build.manage(state_wrapper))
+                let store = match LimiterStore::from_env().await {
+                    Ok(store) => store,
+                    Err(e) => {
+                        error!("Failed to set up the rate limit backend: {}", e);
+                        return Err(rocket_build);
+                    }
+                };
+
+                match RatePolicies::from_config(configs, &store) {
+                    Ok(policies) => Ok(rocket_build.manage(policies)),
+                    Err(e) => {
+                        error!("Failed to set up the rate limit policies: {}", e);
+                        Err(rocket_build)
+                    }
+                }
             },
         ))
-        .attach(AdHoc::on_request(
-            "Per minute rate limit handler",
-            rate_limiter,
+        .attach(AdHoc::on_liftoff(
+            "Rate limit eviction task",
+            |rocket| Box::pin(async move {
+                match (rocket.state::<RatePolicies>(), rocket.state::<AckConfig>()) {
+                    (Some(policies), Some(ack_config)) => {
+                        policies.spawn_evictor(RATE_LIMIT_EVICTION_INTERVAL);
+                        ack_config.spawn_evictor(RATE_LIMIT_EVICTION_INTERVAL);
+                    },
+                    _ => error!("Rate limit eviction task could not start: missing managed state")
+                }
+            }),
         ))
+        .attach(RateLimiter)
         .attach(AdHoc::on_response("Response headers filter fairing", HeaderFairings::header_res_filter))
         .attach(Cors::from_options(&HeaderFairings::rocket_cors_config()).expect("Failed to attach CORS"))
-        .mount("/", routes![sd_msg_route])
+        .mount("/", routes![sd_msg_route, issue_challenge_route, issue_form_token_route, ingest_email_route])
         .mount("/health", routes![check_health_route])
         .mount(
             "/message",
@@ -73,7 +245,25 @@ async fn rocket() -> _ {
                 get_msg_route,
                 toggle_read_archive_route,
                 del_msg_route,
-                del_msg_no_id_route
+                del_msg_no_id_route,
+                get_trash_route,
+                get_spam_route,
+                mark_spam_route,
+                unmark_spam_route,
+                export_msgs_route,
+                restore_msg_route,
+                list_dead_jobs_route,
+                redrive_jobs_route,
+                reply_msg_route,
+                preview_template_route,
+                list_webhooks_route,
+                list_webhook_deliveries_route,
+                stream_events_route,
+                forget_sender_route,
+                subject_access_route,
+                import_msgs_route,
+                get_server_limit_route,
+                tune_server_limit_route
             ],
         )
         .register("/", catchers![
@@ -82,6 +272,8 @@ async fn rocket() -> _ {
             error_catcher::bad_request,
             error_catcher::unauthorized,
             error_catcher::forbidden,
+            error_catcher::challenge_required,
+            error_catcher::service_unavailable,
             error_catcher::enhance_calm,
             error_catcher::enhance_calm2
         ])
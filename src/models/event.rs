@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value as SerdeVal;
+use mongodb::bson::{
+   oid::ObjectId,
+   DateTime
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+   #[serde(rename = "message.created")]
+   MessageCreated,
+   #[serde(rename = "message.read")]
+   MessageRead,
+   #[serde(rename = "message.unread")]
+   MessageUnread,
+   #[serde(rename = "message.archived")]
+   MessageArchived,
+   #[serde(rename = "message.unarchived")]
+   MessageUnarchived,
+   #[serde(rename = "message.trashed")]
+   MessageTrashed,
+   #[serde(rename = "message.restored")]
+   MessageRestored,
+   #[serde(rename = "message.deleted")]
+   MessageDeleted
+}
+
+impl EventKind {
+   pub const ALL: [EventKind; 8] = [
+      EventKind::MessageCreated,
+      EventKind::MessageRead,
+      EventKind::MessageUnread,
+      EventKind::MessageArchived,
+      EventKind::MessageUnarchived,
+      EventKind::MessageTrashed,
+      EventKind::MessageRestored,
+      EventKind::MessageDeleted
+   ];
+
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         EventKind::MessageCreated => "message.created",
+         EventKind::MessageRead => "message.read",
+         EventKind::MessageUnread => "message.unread",
+         EventKind::MessageArchived => "message.archived",
+         EventKind::MessageUnarchived => "message.unarchived",
+         EventKind::MessageTrashed => "message.trashed",
+         EventKind::MessageRestored => "message.restored",
+         EventKind::MessageDeleted => "message.deleted"
+      }
+   }
+
+   pub fn from_name(name: &str) -> Option<Self> {
+      EventKind::ALL.iter().copied().find(|kind| kind.as_str() == name.trim())
+   }
+}
+
+/// Something that happened to a message, ids sort by creation time
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MessageEvent {
+   #[serde(rename = "_id")]
+   pub id: ObjectId,
+   pub kind: EventKind,
+   #[serde(rename = "messageId")]
+   pub message_id: ObjectId,
+   #[serde(rename = "occurredAt")]
+   pub occurred_at: DateTime,
+   pub data: SerdeVal
+}
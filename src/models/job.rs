@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use mongodb::bson::{
+   oid::ObjectId,
+   DateTime
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+   Queued,
+   Leased,
+   Done,
+   Dead
+}
+
+impl JobState {
+   pub fn as_str(&self) -> &str {
+      match self {
+         JobState::Queued => "queued",
+         JobState::Leased => "leased",
+         JobState::Done => "done",
+         JobState::Dead => "dead",
+      }
+   }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobKind {
+   OwnerNotification,
+   Acknowledgement,
+   Reply {
+      #[serde(rename = "entryId")]
+      entry_id: ObjectId
+   },
+   Webhook {
+      endpoint: String,
+      #[serde(rename = "eventId")]
+      event_id: ObjectId
+   }
+}
+
+/// Outcome of a single delivery attempt
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobAttempt {
+   pub at: DateTime,
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub error: Option<String>
+}
+
+pub const MAX_LOGGED_ATTEMPTS: i32 = 20;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeliveryJob {
+   #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+   pub id: Option<ObjectId>,
+   #[serde(rename = "messageId")]
+   pub message_id: ObjectId,
+   pub kind: JobKind,
+   pub state: JobState,
+   pub attempts: u32,
+   #[serde(rename = "maxAttempts")]
+   pub max_attempts: u32,
+   #[serde(rename = "runAt")]
+   pub run_at: DateTime,
+   #[serde(rename = "leasedUntil", default, skip_serializing_if = "Option::is_none")]
+   pub leased_until: Option<DateTime>,
+   #[serde(rename = "lastError", default, skip_serializing_if = "Option::is_none")]
+   pub last_error: Option<String>,
+   //* Latest attempts only, see `MAX_LOGGED_ATTEMPTS`
+   #[serde(default, skip_serializing_if = "Vec::is_empty")]
+   pub log: Vec<JobAttempt>,
+   #[serde(rename = "createdAt")]
+   pub created_at: DateTime,
+   #[serde(rename = "updatedAt")]
+   pub updated_at: DateTime
+}
@@ -1,10 +1,91 @@
 use serde::{Deserialize, Serialize};
 use mongodb::bson::{
-   oid::{ObjectId}, 
+   oid::{ObjectId},
    DateTime
 };
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryState {
+   Pending,
+   Sent,
+   Failed
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeliveryStatus {
+   pub state: DeliveryState,
+   pub attempts: u32,
+   #[serde(rename = "lastError", skip_serializing_if = "Option::is_none")]
+   pub last_error: Option<String>,
+   #[serde(rename = "updatedAt")]
+   pub updated_at: DateTime
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThreadDirection {
+   Outbound,
+   Inbound
+}
+
+/// Describes a MIME attachment of an inbound email, its content is not kept
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AttachmentMeta {
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub name: Option<String>,
+   #[serde(rename = "contentType")]
+   pub content_type: String,
+   pub size: u64
+}
+
+/// One email exchanged with the sender after the original contact form message
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThreadEntry {
+   pub id: ObjectId,
+   pub direction: ThreadDirection,
+   pub from: String,
+   pub to: String,
+   pub subject: String,
+   pub body: String,
+   //* RFC 5322 Message-ID, including the angle brackets
+   #[serde(rename = "messageId")]
+   pub message_id: String,
+   #[serde(rename = "inReplyTo", default, skip_serializing_if = "Option::is_none")]
+   pub in_reply_to: Option<String>,
+   #[serde(default, skip_serializing_if = "Vec::is_empty")]
+   pub references: Vec<String>,
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub author: Option<String>,
+   #[serde(default, skip_serializing_if = "Vec::is_empty")]
+   pub attachments: Vec<AttachmentMeta>,
+   #[serde(rename = "createdAt")]
+   pub created_at: DateTime,
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub delivery: Option<DeliveryStatus>
+}
+
+/// A rule of the spam pipeline that weighed on a message
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SpamHit {
+   pub rule: String,
+   pub score: f64
+}
+
+/// Spam pipeline verdict, along with what an admin said about it if anything
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SpamInfo {
+   pub score: f64,
+   #[serde(default, skip_serializing_if = "Vec::is_empty")]
+   pub hits: Vec<SpamHit>,
+   //* Quarantined messages are kept out of the inbox and notify no one
+   pub quarantined: bool,
+   //* Set once an admin marked (true) or unmarked (false) the message as spam
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub label: Option<bool>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Message {
    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
    pub id: Option<ObjectId>,
@@ -14,6 +95,26 @@ pub struct Message {
    pub name: String,
    pub subject: String,
    pub message: String,
+   //* Language the sender wrote in (e.g.: "pt-BR"), picks the localized email templates
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub locale: Option<String>,
    pub read: bool,
-   pub archived: bool
-}
\ No newline at end of file
+   pub archived: bool,
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub delivery: Option<DeliveryStatus>,
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub ack: Option<DeliveryStatus>,
+   #[serde(default, skip_serializing_if = "Vec::is_empty")]
+   pub thread: Vec<ThreadEntry>,
+   //* Set while the message sits in the trash, purged for good once it's old enough
+   #[serde(rename = "deletedAt", default, skip_serializing_if = "Option::is_none")]
+   pub deleted_at: Option<DateTime>,
+   //* RFC 5322 Message-ID of the email the message was imported from
+   #[serde(rename = "sourceId", default, skip_serializing_if = "Option::is_none")]
+   pub source_id: Option<String>,
+   //* Set on imported messages only, tells apart those imported twice without a Message-ID
+   #[serde(rename = "contentHash", default, skip_serializing_if = "Option::is_none")]
+   pub content_hash: Option<String>,
+   #[serde(default, skip_serializing_if = "Option::is_none")]
+   pub spam: Option<SpamInfo>
+}
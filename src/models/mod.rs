@@ -1 +1,4 @@
-pub mod message;
\ No newline at end of file
+pub mod message;
+pub mod job;
+pub mod event;
+pub mod rate_counter;
@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+use mongodb::bson::DateTime;
+
+/// Requests of one client in one fixed window of a rate limit policy, shared by
+/// every replica through the database
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RateCounter {
+   //* `<policy>|<client>|<window index>`
+   #[serde(rename = "_id")]
+   pub id: String,
+   pub count: i64,
+   //* Once the window after this one is over too, the counter no longer weighs anything
+   #[serde(rename = "expiresAt")]
+   pub expires_at: DateTime
+}
@@ -1,17 +1,22 @@
-use std::{env, panic};
+use std::{env, panic, time::Duration as StdDuration};
 use mongodb::{
-   options::ClientOptions,
+   bson::doc,
+   options::{ClientOptions, IndexOptions},
    Collection,
    Client,
+   IndexModel,
    self,
    error::Error as MongoError,
 };
 
-use crate::models::message::Message;
+use crate::models::{message::Message, job::DeliveryJob, event::MessageEvent, rate_counter::RateCounter};
 
+#[derive(Clone)]
 pub struct MessageCmsDb {
    client: Client,
-   msg_col: Collection<Message>
+   msg_col: Collection<Message>,
+   job_col: Collection<DeliveryJob>,
+   event_col: Collection<MessageEvent>
 }
 
 pub enum ConnCheck {
@@ -19,19 +24,25 @@ pub enum ConnCheck {
    Issue(MongoError)
 }
 
+//* Connection URI and database name
+fn conn_env() -> (String, String) {
+   let uri = match env::var("CMS_DB_CLUST_URI") {
+      Ok(val) => val,
+      Err(_) => panic!("CMS_DB_CLUST_URI environment must be set")
+   };
+
+   let db_name = match env::var("CMS_MSG_DB_NAME") {
+      Ok(val) => val,
+      Err(_) => panic!("CMS_MSG_DB_NAME environment must be set")
+   };
+
+   (uri, db_name)
+}
+
 impl MessageCmsDb {
    pub async fn init() -> Self {
       #[allow(non_snake_case)]
-      let CMS_DB_CLUST_URI = match env::var("CMS_DB_CLUST_URI") {
-         Ok(val) => val,
-         Err(_) => panic!("CMS_DB_CLUST_URI environment must be set")
-      };
-
-      #[allow(non_snake_case)]
-      let CMS_MSG_DB_NAME = match env::var("CMS_MSG_DB_NAME") {
-         Ok(val) => val,
-         Err(_) => panic!("CMS_MSG_DB_NAME environment must be set")
-      };
+      let (CMS_DB_CLUST_URI, CMS_MSG_DB_NAME) = conn_env();
 
       //TODO - finish tls setup
       //let tls_opts = mongodb::options::TlsOptions {
@@ -44,19 +55,110 @@ impl MessageCmsDb {
       match Client::with_options(client_opts) {
          Ok(client) => {
             let msg_col = client.database(CMS_MSG_DB_NAME.as_str())
-            .collection("messages");
+            .collection::<Message>("messages");
+
+            //* Backs the listing's full-text search, subject matches weigh the most
+            let text_idx = IndexModel::builder()
+               .keys(doc! { "subject": "text", "message": "text", "name": "text" })
+               .options(IndexOptions::builder()
+                  .name("messages_text".to_string())
+                  .weights(doc! { "subject": 5, "name": 3, "message": 1 })
+                  .build())
+               .build();
+            if let Err(err) = msg_col.create_index(text_idx, None).await {
+               warn!("Failed creating messages text index: {}", err);
+            }
+
+            //* Backs the trash listing and the purge task
+            let trash_idx = IndexModel::builder()
+               .keys(doc! { "deletedAt": 1 })
+               .build();
+            if let Err(err) = msg_col.create_index(trash_idx, None).await {
+               warn!("Failed creating messages trash index: {}", err);
+            }
+
+            //* Only imported messages carry these, used to skip those already imported
+            for field in ["sourceId", "contentHash"].iter().copied() {
+               let import_idx = IndexModel::builder()
+                  .keys(doc! { field: 1 })
+                  .options(IndexOptions::builder().sparse(true).build())
+                  .build();
+               if let Err(err) = msg_col.create_index(import_idx, None).await {
+                  warn!("Failed creating messages {} index: {}", field, err);
+               }
+            }
+
+            let job_col = client.database(CMS_MSG_DB_NAME.as_str())
+            .collection::<DeliveryJob>("delivery_jobs");
+
+            //* Backs the worker's lease query
+            let lease_idx = IndexModel::builder()
+               .keys(doc! { "state": 1, "runAt": 1 })
+               .build();
+            if let Err(err) = job_col.create_index(lease_idx, None).await {
+               warn!("Failed creating delivery jobs lease index: {}", err);
+            }
+
+            let event_col = client.database(CMS_MSG_DB_NAME.as_str())
+            .collection::<MessageEvent>("message_events");
+
+            //* Events are only kept for webhook retries and catching up, then expire
+            let retention_days = match env::var("EVENTS_RETENTION_DAYS") {
+               Ok(val) => val.parse::<u64>().expect("EVENTS_RETENTION_DAYS must be a positive integer"),
+               Err(_) => 7
+            };
+            let ttl_idx = IndexModel::builder()
+               .keys(doc! { "occurredAt": 1 })
+               .options(IndexOptions::builder().expire_after(StdDuration::from_secs(retention_days * 24 * 60 * 60)).build())
+               .build();
+            if let Err(err) = event_col.create_index(ttl_idx, None).await {
+               warn!("Failed creating message events TTL index: {}", err);
+            }
 
             MessageCmsDb {
                client,
-               msg_col
+               msg_col,
+               job_col,
+               event_col
             }
          },
          Err(err) => panic!("Failed to connect to CMS DB Cluster: {}", err)
       }
    }
+   /// Counters of the shared rate limiter, connected on their own as the messages
+   /// may be stored elsewhere
+   pub async fn init_rate_limits() -> Collection<RateCounter> {
+      let (uri, db_name) = conn_env();
+      let client_opts = ClientOptions::parse(uri)
+         .await.expect("Failed to parse mongodb CMS DB's connection URI");
+      let client = match Client::with_options(client_opts) {
+         Ok(client) => client,
+         Err(err) => panic!("Failed to connect to CMS DB Cluster: {}", err)
+      };
+
+      let rate_col = client.database(db_name.as_str())
+         .collection::<RateCounter>("rate_limits");
+
+      //* Counters expire on their own once they no longer weigh in any window
+      let ttl_idx = IndexModel::builder()
+         .keys(doc! { "expiresAt": 1 })
+         .options(IndexOptions::builder().expire_after(StdDuration::from_secs(0)).build())
+         .build();
+      if let Err(err) = rate_col.create_index(ttl_idx, None).await {
+         warn!("Failed creating rate limits TTL index: {}", err);
+      }
+
+      rate_col
+   }
    pub fn get_msg_col(&self) -> &Collection<Message> {
       &self.msg_col
    }
+   pub fn get_job_col(&self) -> &Collection<DeliveryJob> {
+      &self.job_col
+   }
+   pub fn get_event_col(&self) -> &Collection<MessageEvent> {
+      &self.event_col
+   }
    pub async fn check_conn(&self) -> ConnCheck {
       match self.client.list_database_names(None, None).await {
          Ok(_) => ConnCheck::Ok,
@@ -0,0 +1,180 @@
+mod worker;
+
+use std::{env, sync::Arc};
+use chrono::{Duration, Utc};
+use rand::Rng;
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{
+   models::job::{DeliveryJob, JobKind, JobState},
+   store::{JobStore, JobFilter, StoreErr}
+};
+
+pub use worker::{spawn_worker, WorkerCtx};
+
+pub const LEASE_EXPIRED: &str = "The lease expired before the attempt finished";
+
+pub struct QueueConfig {
+   pub max_attempts: u32,
+   pub backoff_base: Duration,
+   pub backoff_max: Duration,
+   pub lease: Duration,
+   pub poll_interval: Duration
+}
+
+fn env_secs(var: &str, default: i64) -> Duration {
+   match env::var(var) {
+      Ok(val) => match val.parse::<i64>() {
+         Ok(secs) if secs > 0 => Duration::seconds(secs),
+         _ => panic!("{} must be a positive amount of seconds", var)
+      },
+      Err(_) => Duration::seconds(default)
+   }
+}
+
+impl QueueConfig {
+   pub fn from_env() -> Self {
+      let max_attempts = match env::var("QUEUE_MAX_ATTEMPTS") {
+         Ok(val) => match val.parse::<u32>() {
+            Ok(attempts) if attempts > 0 => attempts,
+            _ => panic!("QUEUE_MAX_ATTEMPTS must be a positive integer")
+         },
+         Err(_) => 8
+      };
+
+      QueueConfig {
+         max_attempts,
+         backoff_base: env_secs("QUEUE_BACKOFF_BASE_SECS", 30),
+         backoff_max: env_secs("QUEUE_BACKOFF_MAX_SECS", 6 * 60 * 60),
+         lease: env_secs("QUEUE_LEASE_SECS", 120),
+         poll_interval: env_secs("QUEUE_POLL_SECS", 5)
+      }
+   }
+
+   /// Exponential backoff with "equal jitter": waits somewhere between half and
+   /// the whole of `base * 2^(attempt - 1)`, capped to `backoff_max`
+   pub fn backoff(&self, attempt: u32) -> Duration {
+      let exp = attempt.saturating_sub(1).min(30);
+      let full = self.backoff_base.num_milliseconds()
+         .saturating_mul(1i64 << exp)
+         .min(self.backoff_max.num_milliseconds());
+      let half = full / 2;
+
+      Duration::milliseconds(rand::thread_rng().gen_range(half..=full))
+   }
+}
+
+#[derive(Clone)]
+pub struct JobQueue {
+   store: Arc<dyn JobStore>,
+   config: Arc<QueueConfig>
+}
+
+impl JobQueue {
+   pub fn new(store: Arc<dyn JobStore>, config: QueueConfig) -> Self {
+      JobQueue {
+         store,
+         config: Arc::new(config)
+      }
+   }
+
+   pub fn config(&self) -> &QueueConfig {
+      &self.config
+   }
+
+   pub async fn enqueue(&self, kind: JobKind, message_id: ObjectId) -> Result<(), StoreErr> {
+      let now = DateTime::from(Utc::now());
+
+      let job = DeliveryJob {
+         id: None,
+         message_id,
+         kind,
+         state: JobState::Queued,
+         attempts: 0,
+         max_attempts: self.config.max_attempts,
+         run_at: now,
+         leased_until: None,
+         last_error: None,
+         log: Vec::new(),
+         created_at: now,
+         updated_at: now
+      };
+
+      self.store.insert(&job).await
+   }
+
+   /// Atomically takes the next due job, including the ones whose lease expired
+   /// (e.g.: a worker died mid delivery)
+   pub async fn lease(&self) -> Result<Option<DeliveryJob>, StoreErr> {
+      let now = Utc::now();
+
+      self.store.lease(DateTime::from(now), DateTime::from(now + self.config.lease)).await
+   }
+
+   /// False when the job's lease was lost, e.g. taken over by another worker once expired
+   pub async fn complete(&self, job: &DeliveryJob) -> Result<bool, StoreErr> {
+      let done = DeliveryJob {
+         state: JobState::Done,
+         attempts: job.attempts + 1,
+         last_error: None,
+         ..job.clone()
+      };
+
+      self.store.finish_attempt(&done, None, DateTime::from(Utc::now())).await
+   }
+
+   /// Records a failed attempt and either schedules a retry or dead-letters the
+   /// job. Returns the state the job was moved to, none when its lease was lost.
+   pub async fn fail(&self, job: &DeliveryJob, err: &str, permanent: bool) -> Result<Option<JobState>, StoreErr> {
+      let now = Utc::now();
+      let attempts = job.attempts + 1;
+
+      let (state, run_at) = match permanent || attempts >= job.max_attempts {
+         true => (JobState::Dead, job.run_at),
+         false => (JobState::Queued, DateTime::from(now + self.config.backoff(attempts)))
+      };
+      let failed = DeliveryJob {
+         state,
+         attempts,
+         run_at,
+         last_error: Some(err.to_string()),
+         ..job.clone()
+      };
+
+      self.store.finish_attempt(&failed, Some(err), DateTime::from(now)).await
+         .map(|finished| finished.then_some(state))
+   }
+
+   /// Dead-letters a job whose leases ran out on every attempt it had, without another try
+   pub async fn abandon(&self, job: &DeliveryJob) -> Result<bool, StoreErr> {
+      let dead = DeliveryJob {
+         state: JobState::Dead,
+         last_error: Some(LEASE_EXPIRED.to_string()),
+         ..job.clone()
+      };
+
+      self.store.finish_attempt(&dead, Some(LEASE_EXPIRED), DateTime::from(Utc::now())).await
+   }
+
+   pub async fn list(&self, state: JobState) -> Result<Vec<DeliveryJob>, StoreErr> {
+      self.store.list(&JobFilter { state: Some(state), ..Default::default() }).await
+   }
+
+   /// Webhook jobs, latest first, optionally narrowed to one endpoint and/or state
+   pub async fn webhook_deliveries(&self, endpoint: Option<&str>, state: Option<JobState>, limit: i64) -> Result<Vec<DeliveryJob>, StoreErr> {
+      let filter = JobFilter {
+         state,
+         webhooks_only: true,
+         endpoint: endpoint.map(String::from),
+         message_ids: None,
+         limit: Some(limit)
+      };
+
+      self.store.list(&filter).await
+   }
+
+   /// Puts dead jobs back in the queue with a fresh attempts budget
+   pub async fn redrive(&self, ids: Vec<ObjectId>) -> Result<u64, StoreErr> {
+      self.store.redrive(&ids, DateTime::from(Utc::now())).await
+   }
+}
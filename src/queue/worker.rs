@@ -0,0 +1,175 @@
+use std::time::Duration as StdDuration;
+use chrono::Utc;
+use lettre::Message as Email;
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{
+   events::Webhooks,
+   mailer::{Mailer, MailerErr, relay, reply, ack, templates::MailTemplates},
+   models::{
+      event::MessageEvent,
+      job::{DeliveryJob, JobKind, JobState},
+      message::{Message, DeliveryState, DeliveryStatus}
+   },
+   store::{Store, DeliveryTarget}
+};
+use super::{JobQueue, LEASE_EXPIRED};
+
+/// Everything jobs need to be processed, cheap to clone into the worker task
+#[derive(Clone)]
+pub struct WorkerCtx {
+   pub store: Store,
+   pub mailer: Mailer,
+   pub templates: MailTemplates,
+   pub webhooks: Webhooks
+}
+
+#[derive(Debug)]
+pub struct JobErr {
+   pub msg: String,
+   pub permanent: bool
+}
+
+async fn fetch_message(job: &DeliveryJob, store: &Store) -> Result<Message, JobErr> {
+   match store.messages.find(job.message_id).await {
+      Ok(Some(msg)) => Ok(msg),
+      Ok(None) => Err(JobErr { msg: "Message no longer exists".to_string(), permanent: true }),
+      Err(err) => Err(JobErr { msg: format!("Failed fetching message: {}", err), permanent: false })
+   }
+}
+
+async fn fetch_event(event_id: ObjectId, store: &Store) -> Result<MessageEvent, JobErr> {
+   match store.events.find(event_id).await {
+      Ok(Some(event)) => Ok(event),
+      Ok(None) => Err(JobErr { msg: "Event no longer exists".to_string(), permanent: true }),
+      Err(err) => Err(JobErr { msg: format!("Failed fetching event: {}", err), permanent: false })
+   }
+}
+
+async fn send_email(ctx: &WorkerCtx, email: Result<Email, MailerErr>) -> Result<(), JobErr> {
+   if !ctx.mailer.is_enabled() {
+      return Err(JobErr { msg: "Outbound email delivery is disabled".to_string(), permanent: true });
+   }
+
+   let email = email
+      .map_err(|err| JobErr { msg: err.to_string(), permanent: true })?;
+
+   ctx.mailer.send(email).await
+      .map_err(|err| JobErr { msg: err.to_string(), permanent: false })
+}
+
+async fn process(job: &DeliveryJob, ctx: &WorkerCtx) -> Result<(), JobErr> {
+   match job.kind {
+      JobKind::OwnerNotification => {
+         let msg = fetch_message(job, &ctx.store).await?;
+         send_email(ctx, relay::owner_notification(&ctx.mailer, &ctx.templates, &msg)).await
+      },
+      JobKind::Acknowledgement => {
+         let msg = fetch_message(job, &ctx.store).await?;
+         send_email(ctx, ack::acknowledgement(&ctx.mailer, &ctx.templates, &msg)).await
+      },
+      JobKind::Reply { entry_id } => {
+         let msg = fetch_message(job, &ctx.store).await?;
+         let entry = match msg.thread.iter().find(|entry| entry.id == entry_id) {
+            Some(entry) => entry,
+            None => return Err(JobErr { msg: "Reply no longer exists in the thread".to_string(), permanent: true })
+         };
+
+         send_email(ctx, reply::reply(&ctx.mailer, &ctx.templates, &msg, entry)).await
+      },
+      JobKind::Webhook { ref endpoint, event_id } => {
+         let endpoint = match ctx.webhooks.endpoint(endpoint) {
+            Some(endpoint) => endpoint,
+            None => return Err(JobErr { msg: format!("Webhook endpoint \"{}\" is no longer configured", endpoint), permanent: true })
+         };
+         let event = fetch_event(event_id, &ctx.store).await?;
+         let delivery_id = job.id.map(|id| id.to_hex()).unwrap_or_default();
+
+         ctx.webhooks.deliver(endpoint, &event, &delivery_id).await
+            .map_err(|err| JobErr { msg: err.msg, permanent: err.permanent })
+      }
+   }
+}
+
+/// Mirrors the job outcome on the message so admins can see it
+async fn record_outcome(job: &DeliveryJob, store: &Store, state: DeliveryState, attempts: u32, last_error: Option<String>) {
+   let status = DeliveryStatus {
+      state,
+      attempts,
+      last_error,
+      updated_at: DateTime::from(Utc::now())
+   };
+
+   let target = match job.kind {
+      JobKind::OwnerNotification => DeliveryTarget::Owners,
+      JobKind::Acknowledgement => DeliveryTarget::Ack,
+      JobKind::Reply { entry_id } => DeliveryTarget::Reply(entry_id),
+      //* The job itself is the webhook delivery log
+      JobKind::Webhook { .. } => return
+   };
+
+   if let Err(err) = store.messages.set_delivery(job.message_id, target, &status).await {
+      warn!("Failed recording delivery status of job {:?}. Error: {}", job.id, err);
+   }
+}
+
+//* The lease ran out mid attempt and the job was taken over, its outcome is the new holder's to record
+fn lost_lease(job: &DeliveryJob) {
+   warn!("Delivery job {:?} lost its lease before the attempt finished, leaving it to whoever holds it now", job.id);
+}
+
+async fn run_job(queue: &JobQueue, ctx: &WorkerCtx, job: DeliveryJob) {
+   let store = &ctx.store;
+
+   //* Every attempt it had was taken over after its lease ran out, e.g. the email crashes the worker
+   if job.attempts >= job.max_attempts {
+      warn!("Delivery job {:?} ran out of attempts on expired leases, dead-lettering it", job.id);
+
+      match queue.abandon(&job).await {
+         Ok(true) => record_outcome(&job, store, DeliveryState::Failed, job.attempts, Some(LEASE_EXPIRED.to_string())).await,
+         Ok(false) => lost_lease(&job),
+         Err(err) => warn!("Failed dead-lettering job {:?}. Error: {}", job.id, err)
+      }
+      return;
+   }
+
+   match process(&job, ctx).await {
+      Ok(_) => match queue.complete(&job).await {
+         Ok(true) => record_outcome(&job, store, DeliveryState::Sent, job.attempts + 1, None).await,
+         Ok(false) => lost_lease(&job),
+         Err(err) => {
+            warn!("Failed marking job {:?} as done. Error: {}", job.id, err);
+            record_outcome(&job, store, DeliveryState::Sent, job.attempts + 1, None).await;
+         }
+      },
+      Err(JobErr { msg, permanent }) => {
+         warn!("Delivery job {:?} failed (attempt {}). Error: {}", job.id, job.attempts + 1, msg);
+
+         match queue.fail(&job, &msg, permanent).await {
+            Ok(Some(JobState::Dead)) => record_outcome(&job, store, DeliveryState::Failed, job.attempts + 1, Some(msg)).await,
+            Ok(Some(_)) => record_outcome(&job, store, DeliveryState::Pending, job.attempts + 1, Some(msg)).await,
+            Ok(None) => lost_lease(&job),
+            Err(err) => warn!("Failed recording failure of job {:?}. Error: {}", job.id, err)
+         }
+      }
+   }
+}
+
+/// Polls the queue for due jobs until the process exits
+pub fn spawn_worker(queue: JobQueue, ctx: WorkerCtx) {
+   tokio::spawn(async move {
+      let poll_interval = queue.config().poll_interval.to_std()
+         .unwrap_or(StdDuration::from_secs(5));
+
+      loop {
+         match queue.lease().await {
+            Ok(Some(job)) => run_job(&queue, &ctx, job).await,
+            Ok(None) => tokio::time::sleep(poll_interval).await,
+            Err(err) => {
+               warn!("Failed leasing a delivery job. Error: {}", err);
+               tokio::time::sleep(poll_interval).await;
+            }
+         }
+      }
+   });
+}
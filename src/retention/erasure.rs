@@ -0,0 +1,108 @@
+use chrono::Utc;
+use serde::Serialize;
+use mongodb::bson::oid::ObjectId;
+
+use crate::{
+   events::EventBus,
+   security::signing::hmac_sha256_hex,
+   store::{Store, StoreErr}
+};
+use super::publish_deleted;
+
+const ALIAS_NAME: &str = "Anonymized sender";
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErasureMode {
+   //* Deletes the messages altogether
+   Erase,
+   //* Keeps the messages but replaces the sender's address and name
+   Anonymize
+}
+
+impl ErasureMode {
+   pub fn from_name(name: &str) -> Option<Self> {
+      match name {
+         "erase" => Some(ErasureMode::Erase),
+         "anonymize" => Some(ErasureMode::Anonymize),
+         _ => None
+      }
+   }
+}
+
+/// What was done to honour an erasure request. The address itself is never
+/// part of it, only its keyed hash, so receipts can be kept indefinitely
+#[derive(Serialize, Debug)]
+pub struct ReceiptBody {
+   pub id: String,
+   //* HMAC-SHA256 of the lowercased address, keyed with the receipt secret
+   pub subject_hash: String,
+   pub mode: ErasureMode,
+   pub messages: Vec<String>,
+   pub jobs_removed: u64,
+   pub events_removed: u64,
+   pub requested_by: Option<String>,
+   pub performed_at: String
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErasureReceipt {
+   #[serde(flatten)]
+   pub body: ReceiptBody,
+   //* HMAC-SHA256 of the receipt body as compact JSON, keyed with the receipt secret
+   pub signature: String
+}
+
+impl ErasureReceipt {
+   fn sign(body: ReceiptBody, secret: &str) -> Result<Self, StoreErr> {
+      let raw = serde_json::to_string(&body).map_err(|err| StoreErr(err.to_string()))?;
+
+      Ok(ErasureReceipt {
+         signature: hmac_sha256_hex(secret.as_bytes(), raw.as_bytes()),
+         body
+      })
+   }
+}
+
+pub fn subject_hash(email: &str, secret: &str) -> String {
+   hmac_sha256_hex(secret.as_bytes(), email.trim().to_lowercase().as_bytes())
+}
+
+/// Erases or anonymizes every message sent from `email`, dropping their pending
+/// deliveries and recorded events, which carry the sender's details too
+pub async fn forget_sender(store: &Store, events: &EventBus, email: &str, mode: ErasureMode,
+   requested_by: Option<String>, secret: &str
+) -> Result<ErasureReceipt, StoreErr> {
+   let receipt_id = ObjectId::new();
+
+   let affected = match mode {
+      ErasureMode::Erase => {
+         let ids = store.messages.ids_by_sender(email).await?;
+         store.messages.delete(&ids).await?
+      },
+      ErasureMode::Anonymize => {
+         let alias_email = format!("anonymized-{}@anonymized.invalid", receipt_id.to_hex());
+         store.messages.anonymize_sender(email, &alias_email, ALIAS_NAME).await?
+      }
+   };
+   let jobs_removed = store.jobs.delete_for_messages(&affected).await?;
+   let events_removed = store.events.delete_for_messages(&affected).await?;
+
+   //* Published once the older events are gone, these only carry the message id
+   if mode == ErasureMode::Erase {
+      publish_deleted(events, &affected).await;
+   }
+
+   info!("Erasure {} ({:?}) affected {} messages", receipt_id, mode, affected.len());
+
+   ErasureReceipt::sign(ReceiptBody {
+      id: receipt_id.to_hex(),
+      subject_hash: subject_hash(email, secret),
+      mode,
+      messages: affected.iter().map(|id| id.to_hex()).collect(),
+      jobs_removed,
+      events_removed,
+      requested_by,
+      performed_at: Utc::now().to_rfc3339()
+   }, secret)
+}
@@ -0,0 +1,187 @@
+mod erasure;
+
+use std::{env, time::Duration as StdDuration};
+use chrono::{Duration, Utc};
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde_json::json;
+
+use crate::{
+   events::EventBus,
+   models::event::EventKind,
+   store::{Store, MessageFilter, ListQuery, SortField, StoreErr}
+};
+
+pub use erasure::*;
+
+//* Expired messages are deleted this many at a time
+const RETENTION_BATCH: i64 = 500;
+
+/// Which messages a retention policy applies to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PolicyScope {
+   All,
+   Read,
+   Archived
+}
+
+/// Deletes the messages of `scope` once they are older than `max_age`
+#[derive(Clone, Debug)]
+pub struct RetentionPolicy {
+   pub scope: PolicyScope,
+   pub max_age: Duration
+}
+
+impl RetentionPolicy {
+   /// Parses a `<scope>=<days>` pair, e.g.: "archived=90"
+   fn parse(raw: &str) -> Option<Self> {
+      let (scope, days) = raw.split_once('=')?;
+      let scope = match scope.trim() {
+         "all" => PolicyScope::All,
+         "read" => PolicyScope::Read,
+         "archived" => PolicyScope::Archived,
+         _ => return None
+      };
+
+      match days.trim().parse::<i64>() {
+         Ok(days) if days > 0 => Some(RetentionPolicy { scope, max_age: Duration::days(days) }),
+         _ => None
+      }
+   }
+
+   fn filter(&self) -> MessageFilter {
+      let mut filter = MessageFilter {
+         created_before: Some(Utc::now() - self.max_age),
+         ..Default::default()
+      };
+      match self.scope {
+         PolicyScope::All => {},
+         PolicyScope::Read => filter.read = Some(true),
+         PolicyScope::Archived => filter.archived = Some(true)
+      }
+
+      filter
+   }
+}
+
+pub struct RetentionConfig {
+   //* How long trashed messages can still be restored
+   pub trash_retention: Duration,
+   pub policies: Vec<RetentionPolicy>,
+   pub purge_interval: Duration
+}
+
+impl RetentionConfig {
+   pub fn from_env() -> Self {
+      let trash_retention_days = match env::var("TRASH_RETENTION_DAYS") {
+         Ok(val) => match val.parse::<i64>() {
+            Ok(days) if days >= 0 => days,
+            _ => panic!("TRASH_RETENTION_DAYS must be zero or a positive integer")
+         },
+         Err(_) => 30
+      };
+      let policies = match env::var("RETENTION_POLICIES") {
+         Ok(val) => val.split(',')
+            .filter(|raw| !raw.trim().is_empty())
+            .map(|raw| RetentionPolicy::parse(raw)
+               .unwrap_or_else(|| panic!("Invalid retention policy \"{}\", expected <all|read|archived>=<days>", raw.trim())))
+            .collect(),
+         Err(_) => Vec::new()
+      };
+      let purge_interval_secs = match env::var("TRASH_PURGE_INTERVAL_SECS") {
+         Ok(val) => match val.parse::<i64>() {
+            Ok(secs) if secs > 0 => secs,
+            _ => panic!("TRASH_PURGE_INTERVAL_SECS must be a positive amount of seconds")
+         },
+         Err(_) => 60 * 60
+      };
+
+      RetentionConfig {
+         trash_retention: Duration::days(trash_retention_days),
+         policies,
+         purge_interval: Duration::seconds(purge_interval_secs)
+      }
+   }
+}
+
+async fn publish_deleted(events: &EventBus, ids: &[ObjectId]) {
+   for msg_oid in ids {
+      events.publish(EventKind::MessageDeleted, *msg_oid, json!({ "id": msg_oid.to_string() })).await;
+   }
+}
+
+/// Drops the pending deliveries and recorded events of deleted messages, nothing
+/// would ever use them again
+async fn drop_related(store: &Store, ids: &[ObjectId]) -> Result<(), StoreErr> {
+   if ids.is_empty() {
+      return Ok(());
+   }
+
+   store.jobs.delete_for_messages(ids).await?;
+   store.events.delete_for_messages(ids).await?;
+   Ok(())
+}
+
+/// Deletes every message the policy applies to, returns how many were
+async fn apply_policy(store: &Store, events: &EventBus, policy: &RetentionPolicy) -> Result<usize, StoreErr> {
+   let mut deleted = 0;
+
+   loop {
+      let query = ListQuery {
+         filter: policy.filter(),
+         sort: SortField::CreatedAt,
+         descending: false,
+         after: None,
+         skip: 0,
+         limit: RETENTION_BATCH
+      };
+      let expired = store.messages.list(&query).await?
+         .into_iter()
+         .filter_map(|listed| listed.msg.id)
+         .collect::<Vec<ObjectId>>();
+
+      let removed = store.messages.delete(&expired).await?;
+      drop_related(store, &removed).await?;
+      publish_deleted(events, &removed).await;
+      deleted += removed.len();
+
+      //* Nothing got removed means something else holds on to them, they are left for the next run
+      if (expired.len() as i64) < RETENTION_BATCH || removed.is_empty() {
+         return Ok(deleted);
+      }
+   }
+}
+
+/// Every `purge_interval`, deletes for good the messages that have been in the
+/// trash longer than the trash retention and those expired by a retention policy
+pub fn spawn_purger(store: Store, events: EventBus, config: RetentionConfig) {
+   tokio::spawn(async move {
+      let mut interval = tokio::time::interval(config.purge_interval.to_std()
+         .unwrap_or(StdDuration::from_secs(60 * 60)));
+
+      loop {
+         interval.tick().await;
+
+         let before = DateTime::from_chrono(Utc::now() - config.trash_retention);
+         match store.messages.purge_trashed(before).await {
+            Ok(purged) => {
+               if !purged.is_empty() {
+                  info!("Purged {} messages from the trash", purged.len());
+               }
+               if let Err(err) = drop_related(&store, &purged).await {
+                  warn!("Failed dropping the deliveries and events of purged messages. Error: {}", err);
+               }
+               publish_deleted(&events, &purged).await;
+            },
+            Err(err) => warn!("Failed purging the trash. Error: {}", err)
+         }
+
+         for policy in config.policies.iter() {
+            match apply_policy(&store, &events, policy).await {
+               Ok(0) => {},
+               Ok(deleted) => info!("Retention policy {:?} deleted {} messages", policy.scope, deleted),
+               Err(err) => warn!("Failed applying the {:?} retention policy. Error: {}", policy.scope, err)
+            }
+         }
+      }
+   });
+}
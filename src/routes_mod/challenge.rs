@@ -0,0 +1,43 @@
+use serde_json::json;
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::Status as HttpStatus,
+   State
+};
+
+use crate::security::challenge::{ChallengeConfig, CHALLENGE_HEADER};
+
+/// Hands out a proof-of-work challenge to solve before `POST /send`, or tells
+/// which hosted CAPTCHA the form has to embed
+#[get("/challenge")]
+pub fn issue_challenge(config: &State<ChallengeConfig>) -> Custom<RawJson<String>> {
+   let verifier = match &config.verifier {
+      Some(verifier) => verifier,
+      None => return Custom(
+         HttpStatus::new(404),
+         RawJson(json!({
+            "error": "No challenge is required to send messages."
+         }).to_string())
+      )
+   };
+
+   let res = match verifier.issue() {
+      Some(challenge) => json!({
+         "kind": verifier.name(),
+         "algorithm": "sha256",
+         "token": challenge.token,
+         "difficulty": challenge.difficulty,
+         "expires_at": challenge.expires_at,
+         "header": CHALLENGE_HEADER
+      }),
+      None => json!({
+         "kind": verifier.name(),
+         "header": CHALLENGE_HEADER
+      })
+   };
+
+   Custom(
+      HttpStatus::new(200),
+      RawJson(res.to_string())
+   )
+}
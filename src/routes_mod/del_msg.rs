@@ -1,6 +1,6 @@
 use std::str::FromStr;
 
-use mongodb::bson::{doc, oid::ObjectId, Bson};
+use mongodb::bson::{oid::ObjectId, DateTime};
 use rocket::{
   response::{content::RawJson, status::Custom},
   request::FromParam,
@@ -11,16 +11,37 @@ use rocket::{
 use regex::Regex;
 
 use crate::{
+  events::EventBus,
+  models::event::EventKind,
   auth::{
     auth0_token_related::PermCheckOpt,
     auth0_perm_claims::ScopePerm,
   },
   guards::Auth,
-  mongo::MessageCmsDb
+  store::Store
 };
 
 pub struct Ids(pub Vec<String>);
 
+impl Ids {
+  pub fn oids(&self) -> Result<Vec<ObjectId>, Custom<RawJson<String>>> {
+    let mut oids = Vec::<ObjectId>::new();
+    for id in self.0.iter() {
+      match ObjectId::from_str(id) {
+        Ok(oid) => oids.push(oid),
+        Err(_) => return Err(Custom(
+          HttpStatus::new(409),
+          RawJson(json!({
+            "error": "Error parsing message id(s)"
+          }).to_string())
+        ))
+      }
+    }
+
+    Ok(oids)
+  }
+}
+
 impl<'r> FromParam<'r> for Ids {
   type Error = RawJson<String>;
 
@@ -41,7 +62,7 @@ impl<'r> FromParam<'r> for Ids {
 }
 
 #[post("/del/<ids>")]
-pub async fn del_msg(db: &State<MessageCmsDb>, auth: Auth, ids: Ids) -> Custom<RawJson<String>> {
+pub async fn del_msg(store: &State<Store>, events: &State<EventBus>, auth: Auth, ids: Ids) -> Custom<RawJson<String>> {
   let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_DEL ];
 
   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
@@ -53,44 +74,35 @@ pub async fn del_msg(db: &State<MessageCmsDb>, auth: Auth, ids: Ids) -> Custom<R
     );
   }
   
-  let oids_vec = ids.0.iter()
-    .map(|id| ObjectId::from_str(id).to_owned());
-
-  let mut oids = Vec::<ObjectId>::new();
-  for oid in oids_vec {
-    if oid.as_ref().is_err() {
-      return Custom(
-        HttpStatus::new(409),
-        RawJson(json!({
-          "error": "Error parsing message id(s)"
-        }).to_string())
-      );
-    } else {
-      oids.push(oid.unwrap());
-    }
-  }
-
-  let oids = Bson::from_iter(oids);
+  let oids = match ids.oids() {
+    Ok(oids) => oids,
+    Err(err) => return err
+  };
 
-  let delete_filter = doc! {
-    "_id": {
-      "$in": oids
+  //* Messages are only moved to the trash, the purge task deletes them for good later on
+  let deleted_at = DateTime::now();
+  let res = store.messages.trash(&oids, deleted_at).await;
+  if let Ok(trashed) = &res {
+    for msg_oid in trashed {
+      events.publish(EventKind::MessageTrashed, *msg_oid, json!({
+        "id": msg_oid.to_string(),
+        "deleted_at": deleted_at.to_chrono().to_rfc3339()
+      })).await;
     }
-  };
+  }
 
-  println!("{:?}", &delete_filter.to_string());
-  match db.get_msg_col().delete_many(delete_filter, None).await {
-    Ok(res) 
-    if res.deleted_count.to_be_bytes() != ids.0.len().to_be_bytes() => Custom(
+  match res {
+    Ok(trashed) 
+    if trashed.len() != ids.0.len() => Custom(
       HttpStatus::new(412),
       RawJson(json!({
-        "error": "Some messages could not be deleted as they do not even exist."
+        "error": "Some messages could not be deleted as they do not exist or are already in the trash."
       }).to_string())
     ),
     Ok(_) => Custom(
       HttpStatus::new(200), 
       RawJson(json!({
-        "success": "Messages deleted successfully!"
+        "success": "Messages moved to the trash successfully!"
       }).to_string())
     ),
     Err(err) => {
@@ -114,4 +126,55 @@ pub fn del_msg_no_id() -> Custom<RawJson<String>> {
       "error": "Invalid request. You must first specify a message id."
     }).to_string())
   )
-}
\ No newline at end of file
+}
+#[post("/trash/restore/<ids>")]
+pub async fn restore_msg(store: &State<Store>, events: &State<EventBus>, auth: Auth, ids: Ids) -> Custom<RawJson<String>> {
+  let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_DEL ];
+
+  if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+    return Custom(
+      HttpStatus::new(403),
+      RawJson(json!({
+        "error": "Not authorized: insufficient permissions for this token"
+      }).to_string())
+    );
+  }
+
+  let oids = match ids.oids() {
+    Ok(oids) => oids,
+    Err(err) => return err
+  };
+
+  let res = store.messages.restore(&oids).await;
+  if let Ok(restored) = &res {
+    for msg_oid in restored {
+      events.publish(EventKind::MessageRestored, *msg_oid, json!({ "id": msg_oid.to_string() })).await;
+    }
+  }
+
+  match res {
+    Ok(restored)
+    if restored.len() != ids.0.len() => Custom(
+      HttpStatus::new(412),
+      RawJson(json!({
+        "error": "Some messages could not be restored as they are not in the trash."
+      }).to_string())
+    ),
+    Ok(_) => Custom(
+      HttpStatus::new(200),
+      RawJson(json!({
+        "success": "Messages restored successfully!"
+      }).to_string())
+    ),
+    Err(err) => {
+      warn!("Error restoring messages: {}", err);
+
+      Custom(
+        HttpStatus::new(500),
+        RawJson(json!({
+          "error": "Internal server error. Don't worry, this is our fault."
+        }).to_string())
+      )
+    }
+  }
+}
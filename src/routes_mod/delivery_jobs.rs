@@ -0,0 +1,124 @@
+use std::str::FromStr;
+
+use serde_json::{json, Value as SerdeVal};
+use mongodb::bson::oid::ObjectId;
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::Status as HttpStatus,
+   State
+};
+
+use crate::{
+   auth::{
+      auth0_token_related::PermCheckOpt,
+      auth0_perm_claims::ScopePerm,
+   },
+   guards::Auth,
+   models::job::{DeliveryJob, JobState},
+   queue::JobQueue
+};
+use super::del_msg::Ids;
+
+pub fn job_json(job: &DeliveryJob) -> SerdeVal {
+   json!({
+      "id": job.id.map(|id| id.to_string()),
+      "message_id": job.message_id.to_string(),
+      "kind": job.kind,
+      "state": job.state,
+      "attempts": job.attempts,
+      "max_attempts": job.max_attempts,
+      "last_error": job.last_error,
+      "log": job.log.iter().map(|attempt| json!({
+         "at": attempt.at.to_chrono().to_rfc3339(),
+         "error": attempt.error,
+      })).collect::<Vec<SerdeVal>>(),
+      "run_at": job.run_at.to_chrono().to_rfc3339(),
+      "created_at": job.created_at.to_chrono().to_rfc3339(),
+      "updated_at": job.updated_at.to_chrono().to_rfc3339(),
+   })
+}
+
+pub fn forbidden() -> Custom<RawJson<String>> {
+   Custom(
+      HttpStatus::new(403),
+      RawJson(json!({
+         "error": "Not authorized: insufficient permissions for this token"
+      }).to_string())
+   )
+}
+
+#[get("/jobs/dead")]
+pub async fn list_dead_jobs(queue: &State<JobQueue>, auth: Auth) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_DELIVERY_MANAGE ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return forbidden();
+   }
+
+   match queue.list(JobState::Dead).await {
+      Ok(jobs) => Custom(
+         HttpStatus::new(200),
+         RawJson(json!({
+            "jobs": jobs.iter().map(job_json).collect::<Vec<SerdeVal>>()
+         }).to_string())
+      ),
+      Err(err) => {
+         warn!("Failed listing dead delivery jobs. Error: {:?}", err);
+         Custom(
+            HttpStatus::new(500),
+            RawJson(json!({
+               "error": "Failed retrieving dead jobs. Don't worry this is a fault on our side!"
+            }).to_string())
+         )
+      }
+   }
+}
+
+#[post("/jobs/redrive/<ids>")]
+pub async fn redrive_jobs(queue: &State<JobQueue>, auth: Auth, ids: Ids) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_DELIVERY_MANAGE ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return forbidden();
+   }
+
+   let mut oids = Vec::<ObjectId>::new();
+   for id in ids.0.iter() {
+      match ObjectId::from_str(id) {
+         Ok(oid) => oids.push(oid),
+         Err(_) => return Custom(
+            HttpStatus::new(400),
+            RawJson(json!({
+               "error": "Error parsing job id(s)"
+            }).to_string())
+         )
+      }
+   }
+
+   let requested = oids.len() as u64;
+   match queue.redrive(oids).await {
+      Ok(redriven) if redriven != requested => Custom(
+         HttpStatus::new(412),
+         RawJson(json!({
+            "error": "Some jobs could not be re-driven as they do not exist or are not dead-lettered.",
+            "redriven": redriven
+         }).to_string())
+      ),
+      Ok(redriven) => Custom(
+         HttpStatus::new(200),
+         RawJson(json!({
+            "success": "Jobs queued for delivery again!",
+            "redriven": redriven
+         }).to_string())
+      ),
+      Err(err) => {
+         warn!("Failed re-driving delivery jobs. Error: {:?}", err);
+         Custom(
+            HttpStatus::new(500),
+            RawJson(json!({
+               "error": "Internal server error. Don't worry, this is our fault."
+            }).to_string())
+         )
+      }
+   }
+}
@@ -0,0 +1,135 @@
+use serde_json::json;
+use rocket::{
+   response::{
+      status::Custom,
+      content::RawJson,
+      stream::{Event, EventStream}
+   },
+   http::Status as HttpStatus,
+   tokio::{select, sync::broadcast::error::RecvError, time::Duration},
+   Shutdown,
+   State
+};
+
+use crate::{
+   auth::{
+      auth0_token_related::PermCheckOpt,
+      auth0_perm_claims::ScopePerm,
+   },
+   events::EventBus,
+   guards::{Auth, LastEventId},
+   models::event::MessageEvent
+};
+
+//* Missed events are replayed on reconnection in pages of this many
+const MAX_REPLAY: i64 = 500;
+const HEARTBEAT_SECS: u64 = 15;
+
+fn sse_event(event: &MessageEvent) -> Event {
+   Event::json(&json!({
+      "message_id": event.message_id.to_hex(),
+      "occurred_at": event.occurred_at.to_chrono().to_rfc3339(),
+      "data": event.data
+   }))
+      .id(event.id.to_hex())
+      .event(event.kind.as_str())
+}
+
+//* Tells the client events were lost and couldn't be replayed
+fn reset_event() -> Event {
+   Event::json(&json!({
+      "error": "Some events couldn't be replayed, reload the messages to catch up"
+   }))
+      .event("reset")
+}
+
+/// Live feed of message events for the admin dashboard. Reconnecting clients
+/// get the events they missed, starting after their `Last-Event-ID`
+#[get("/events")]
+pub async fn stream_events(events: &State<EventBus>, auth: Auth, last_event_id: LastEventId, mut end: Shutdown
+) -> Result<EventStream![], Custom<RawJson<String>>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_READ ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return Err(Custom(
+         HttpStatus::new(403),
+         RawJson(json!({
+            "error": "Not authorized: insufficient permissions for this token"
+         }).to_string())
+      ));
+   }
+
+   //* Subscribing before reading the backlog, so nothing falls in between the two
+   let mut rx = events.subscribe();
+   let bus = events.inner().clone();
+   let mut last_sent = last_event_id.0;
+
+   Ok(EventStream! {
+      //* Replays what was missed while disconnected, or dropped by the channel for being too slow
+      let mut catch_up = last_sent.is_some();
+
+      loop {
+         if catch_up {
+            //* Resuming after an expired event would silently skip those expired after it
+            let resumable = match last_sent {
+               Some(last_id) => match bus.has_event(last_id).await {
+                  Ok(found) => found,
+                  Err(err) => {
+                     warn!("Failed looking up event {} to resume from. Error: {}", last_id, err);
+                     false
+                  }
+               },
+               None => false
+            };
+
+            //* Paged until caught up, the backlog is bounded by the events retention
+            let mut caught_up = false;
+            while let Some(last_id) = last_sent.filter(|_| resumable) {
+               let missed = match bus.since(last_id, MAX_REPLAY).await {
+                  Ok(missed) => missed,
+                  Err(err) => {
+                     warn!("Failed replaying events after {}. Error: {}", last_id, err);
+                     break;
+                  }
+               };
+
+               caught_up = (missed.len() as i64) < MAX_REPLAY;
+               for event in missed {
+                  last_sent = Some(event.id);
+                  yield sse_event(&event);
+               }
+               if caught_up {
+                  break;
+               }
+            }
+
+            //* Nothing (left) to resume from or the replay failed, the client has to reload what it shows
+            if !caught_up {
+               yield reset_event();
+            }
+         }
+         catch_up = false;
+
+         let received = select! {
+            received = rx.recv() => received,
+            _ = &mut end => break
+         };
+         let event = match received {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+               catch_up = true;
+               continue;
+            },
+            Err(RecvError::Closed) => break
+         };
+
+         //* Already sent while replaying
+         if matches!(last_sent, Some(last_id) if event.id <= last_id) {
+            continue;
+         }
+
+         last_sent = Some(event.id);
+         yield sse_event(&event);
+      }
+   }.heartbeat(Duration::from_secs(HEARTBEAT_SECS)))
+}
@@ -0,0 +1,223 @@
+use chrono::Utc;
+use serde_json::{json, Value as SerdeVal};
+use rocket::{
+   response::{status::Custom, content::RawJson, stream::ByteStream},
+   http::{Status as HttpStatus, ContentType},
+   State
+};
+
+use crate::{
+   auth::{
+      auth0_token_related::PermCheckOpt,
+      auth0_perm_claims::ScopePerm,
+   },
+   export::{Attachment, ZipStream, message_emails, mbox_entry},
+   guards::Auth,
+   mailer::Mailer,
+   security::sanitizers::unescape_html,
+   store::{Store, ListQuery, Listed, SortField, Position}
+};
+use super::get_msgs::{
+   msgs_filter_params::*,
+   get_msgs_filtering::{get_filter, FilterErr},
+   msgs_paging::{parse_fields, ListField},
+   msgs_search::Search
+};
+
+//* Messages are read from the store this many at a time
+const EXPORT_BATCH: i64 = 100;
+
+#[derive(Clone, Copy, PartialEq)]
+enum ExportFormat {
+   Mbox,
+   Eml,
+   Csv
+}
+
+fn csv_record(values: impl IntoIterator<Item = String>) -> csv::Result<Vec<u8>> {
+   let mut writer = csv::Writer::from_writer(Vec::new());
+   writer.write_record(values)?;
+
+   writer.into_inner().map_err(|err| err.into_error().into())
+}
+
+//* Spreadsheets run cells starting with these as formulas
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+//* Stored text is HTML-escaped, cells get it back as plain text
+fn csv_value(value: SerdeVal) -> String {
+   match value {
+      SerdeVal::Null => String::new(),
+      SerdeVal::String(text) => {
+         let text = unescape_html(&text);
+         //* Senders write these, so they're kept from being run when opened in a spreadsheet
+         match text.starts_with(&FORMULA_PREFIXES[..]) {
+            true => format!("'{}", text),
+            false => text
+         }
+      },
+      other => other.to_string()
+   }
+}
+
+/// Streams every message matching the listing filters, oldest first, as an
+/// MBOX, a zip of `.eml` files or CSV. Nothing but the current batch is held in memory
+#[get("/export?<read>&<date>&<archived>&<sender>&<q>&<format>&<fields>")]
+pub async fn export_msgs(store: &State<Store>, mailer: &State<Mailer>, auth: Auth,
+   read: Option<ReadFilter>, date: Option<DateFilter>, archived: Option<ArchivedFilter>,
+   sender: Option<SenderFilter>, q: Option<String>, format: Option<String>, fields: Option<String>
+) -> Result<Attachment<ByteStream![Vec<u8>]>, Custom<RawJson<String>>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_READ ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return Err(Custom(
+         HttpStatus::new(403),
+         RawJson(json!({
+            "error": "Not authorized: insufficient permissions for this token"
+         }).to_string())
+      ));
+   }
+
+   let format = match format.as_deref() {
+      None | Some("mbox") => ExportFormat::Mbox,
+      Some("eml") => ExportFormat::Eml,
+      Some("csv") => ExportFormat::Csv,
+      Some(_) => return Err(Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": "Invalid format. Must be \"mbox\", \"eml\" or \"csv\"."
+         }).to_string())
+      ))
+   };
+
+   //* CSV exports carry every field unless told otherwise
+   let all_fields = fields.is_none();
+   let params = get_filter(read, date, archived, sender)
+      .and_then(|filter| Ok((filter, parse_fields(fields)?)));
+   let (mut filter, fields) = match params {
+      Ok((filter, _)) if all_fields => (filter, ListField::ALL.to_vec()),
+      Ok(params) => params,
+      Err(FilterErr { msg, unexpected }) => return Err(Custom(
+         HttpStatus::new(if unexpected { 500 } else { 400 }),
+         RawJson(json!({
+            "error": msg
+         }).to_string())
+      ))
+   };
+   filter.search = q.and_then(Search::new).map(|search| search.query);
+   filter.quarantined = Some(false);
+
+   let (content_type, extension) = match format {
+      ExportFormat::Mbox => (ContentType::new("application", "mbox"), "mbox"),
+      ExportFormat::Eml => (ContentType::ZIP, "zip"),
+      ExportFormat::Csv => (ContentType::CSV, "csv")
+   };
+   let filename = format!("messages-{}.{}", Utc::now().format("%Y%m%d%H%M%S"), extension);
+
+   let store = store.inner().clone();
+   let mailer = mailer.inner().clone();
+   let stream = ByteStream! {
+      let mut zip = ZipStream::new();
+      let mut after: Option<Position> = None;
+
+      if format == ExportFormat::Csv {
+         match csv_record(fields.iter().map(|field| field.key().to_string())) {
+            Ok(header) => yield header,
+            Err(err) => warn!("Failed writing the CSV export header. Error: {}", err)
+         }
+      }
+
+      //* Errors can't change the response anymore, the export is cut short and the failure logged
+      'batches: loop {
+         let query = ListQuery {
+            filter: filter.clone(),
+            sort: SortField::CreatedAt,
+            descending: false,
+            after: after.take(),
+            skip: 0,
+            limit: EXPORT_BATCH
+         };
+         let listed = match store.messages.list(&query).await {
+            Ok(listed) => listed,
+            Err(err) => {
+               warn!("Failed listing messages to export. Error: {}", err);
+               break;
+            }
+         };
+
+         for listed in listed.iter() {
+            let msg = &listed.msg;
+            if format == ExportFormat::Csv {
+               match csv_record(fields.iter().map(|field| csv_value(field.value(msg)))) {
+                  Ok(record) => yield record,
+                  Err(err) => {
+                     warn!("Failed writing a CSV export record. Error: {}", err);
+                     break 'batches;
+                  }
+               }
+               continue;
+            }
+
+            //* Listings leave the threads out
+            let id = match msg.id {
+               Some(id) => id,
+               None => continue
+            };
+            let msg = match store.messages.find(id).await {
+               Ok(Some(msg)) => msg,
+               Ok(None) => continue,
+               Err(err) => {
+                  warn!("Failed fetching a message to export. Error: {}", err);
+                  break 'batches;
+               }
+            };
+
+            for email in message_emails(&mailer, &msg) {
+               if format == ExportFormat::Mbox {
+                  yield mbox_entry(&email).into_bytes();
+                  continue;
+               }
+
+               let name = format!("{}-{}.eml", email.date.format("%Y%m%d-%H%M%S"), email.key);
+               match zip.add(&name, email.date, email.content.as_bytes()) {
+                  Ok(bytes) => yield bytes,
+                  Err(err) => {
+                     warn!("Failed adding {} to the export archive. Error: {}", name, err);
+                     break 'batches;
+                  }
+               }
+            }
+         }
+
+         match listed.last() {
+            Some(Listed { msg: last, .. }) if listed.len() as i64 == EXPORT_BATCH && last.id.is_some() => {
+               after = last.id.map(|id| Position { value: SortField::CreatedAt.value_of(last), id });
+            },
+            _ => break
+         }
+      }
+
+      if format == ExportFormat::Eml {
+         match zip.finish() {
+            Ok(bytes) => yield bytes,
+            Err(err) => warn!("Failed closing the export archive. Error: {}", err)
+         }
+      }
+   };
+
+   Ok(Attachment::new(stream, content_type, &filename))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn csv_cells_are_plain_text_and_never_formulas() {
+      assert_eq!(csv_value(json!("Tom &amp; Jerry &lt;3")), "Tom & Jerry <3");
+      assert_eq!(csv_value(json!("=HYPERLINK(&quot;x&quot;)")), "'=HYPERLINK(\"x\")");
+      assert_eq!(csv_value(json!("&lt;b&gt;")), "<b>");
+      assert_eq!(csv_value(json!(null)), "");
+      assert_eq!(csv_value(json!(true)), "true");
+   }
+}
@@ -0,0 +1,28 @@
+use serde_json::json;
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::Status as HttpStatus,
+   State
+};
+
+use crate::security::bot_trap::BotTrap;
+
+/// Signed timestamp the contact form fetches when it loads and sends back as
+/// `form_token`, telling how long it took to be filled
+#[get("/form-token")]
+pub fn issue_form_token(bot_trap: &State<BotTrap>) -> Custom<RawJson<String>> {
+   match bot_trap.issue_token() {
+      Some(token) => Custom(
+         HttpStatus::new(200),
+         RawJson(json!({
+            "form_token": token
+         }).to_string())
+      ),
+      None => Custom(
+         HttpStatus::new(404),
+         RawJson(json!({
+            "error": "Form tokens are not required to send messages."
+         }).to_string())
+      )
+   }
+}
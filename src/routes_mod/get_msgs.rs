@@ -4,23 +4,25 @@ use rocket::{
    State,
    serde::json::serde_json::json
 };
-use serde_json::Value as SerdeVal;
+use serde_json::{Map as SerdeMap, Value as SerdeVal};
 
 use crate::{
    auth::{
       auth0_token_related::PermCheckOpt,
       auth0_perm_claims::ScopePerm,
    },
-   mongo::MessageCmsDb,
+   store::{Store, ListQuery},
    guards::{Auth},
 };
 use msgs_filter_params::*;
 use get_msgs_filtering::{get_filter, FilterErr};
+use msgs_paging::{Sorting, Cursor, CursorPos, parse_fields, DEFAULT_LIMIT, MAX_LIMIT};
+use msgs_search::Search;
 
-mod get_msgs_filtering {
+pub mod get_msgs_filtering {
    use std::str::FromStr;
-   use mongodb::bson::{Document, doc, DateTime as BsonDateTime, Bson};
    use chrono::{DateTime, Utc};
+   use crate::store::MessageFilter;
    use super::msgs_filter_params::*;
    
    #[derive(Debug)]
@@ -29,8 +31,8 @@ mod get_msgs_filtering {
       pub unexpected: bool
    }
 
-   pub fn get_filter(read_filter: Option<ReadFilter>, date_filter: Option<DateFilter>, archived_filter: Option<ArchivedFilter>, sender_filter: Option<SenderFilter>) -> Result<Document, FilterErr> {
-      let mut mongo_query_filters = Document::new();
+   pub fn get_filter(read_filter: Option<ReadFilter>, date_filter: Option<DateFilter>, archived_filter: Option<ArchivedFilter>, sender_filter: Option<SenderFilter>) -> Result<MessageFilter, FilterErr> {
+      let mut msg_filter = MessageFilter::default();
 
       if date_filter.is_some() {
          let date_filter = date_filter.unwrap();
@@ -79,13 +81,13 @@ mod get_msgs_filtering {
             (_, Some(Err(e)), _) => return Err(e),
             (_, _, Some(Err(e))) => return Err(e),
             (Some(Ok(b)), _, _) => {
-               mongo_query_filters.insert("createdAt", doc!{ "$lt": BsonDateTime::from_chrono(b) }); 
+               msg_filter.created_before = Some(b);
             },
             (_, Some(Ok(b)), _) => {
-               mongo_query_filters.insert("createdAt", doc!{ "$gt": BsonDateTime::from_chrono(b) });
+               msg_filter.created_after = Some(b);
             },
             (_, _, Some(Ok((b, e)))) => {
-               mongo_query_filters.insert("createdAt", doc!{ "$gte": BsonDateTime::from_chrono(b), "$lte": BsonDateTime::from_chrono(e) });
+               msg_filter.created_within = Some((b, e));
             },
             (_, _, _) => {}
          }
@@ -93,26 +95,372 @@ mod get_msgs_filtering {
 
       if read_filter.is_some() {
          let read_filter = read_filter.unwrap();
-         mongo_query_filters.insert("read", doc! { "$eq": read_filter.0 });
+         msg_filter.read = Some(read_filter.0);
       }
       
       if archived_filter.is_some() {
          let archived_filter = archived_filter.unwrap();
-         mongo_query_filters.insert("archived", doc! { "$eq": archived_filter.0 });
+         msg_filter.archived = Some(archived_filter.0);
       }
 
       if sender_filter.is_some() {
          let sender_filter = sender_filter.unwrap();
-         let bson_vec = Bson::from(sender_filter.0); 
+         msg_filter.senders = Some(sender_filter.0);
+      }
+
+      Ok(msg_filter)
+   }
+}
+
+pub mod msgs_paging {
+   use serde::{Deserialize, Serialize};
+   use serde_json::{json, Value as SerdeVal};
+   use mongodb::bson::oid::ObjectId;
+   use std::str::FromStr;
+
+   use crate::{
+      models::message::Message,
+      routes_mod::read_message::{delivery_json, spam_json},
+      store::{SortField, SortValue, Position}
+   };
+   use super::get_msgs_filtering::FilterErr;
+
+   pub const DEFAULT_LIMIT: i64 = 50;
+   pub const MAX_LIMIT: i64 = 200;
+
+   fn bad_param(msg: &str) -> FilterErr {
+      FilterErr { msg: msg.to_string(), unexpected: false }
+   }
+
+   fn sort_from_name(name: &str) -> Option<SortField> {
+      match name {
+         "createdAt" | "created_at" | "date" => Some(SortField::CreatedAt),
+         "sender" | "email" | "from" => Some(SortField::Sender),
+         "name" => Some(SortField::Name),
+         "subject" => Some(SortField::Subject),
+         "relevance" => Some(SortField::Relevance),
+         _ => None
+      }
+   }
+
+   fn sort_name(field: SortField) -> &'static str {
+      match field {
+         SortField::CreatedAt => "createdAt",
+         SortField::Sender => "sender",
+         SortField::Name => "name",
+         SortField::Subject => "subject",
+         SortField::Relevance => "relevance"
+      }
+   }
+
+   //* Dates travel in cursors as epoch millis, everything else as strings
+   fn cursor_value(value: SortValue) -> SerdeVal {
+      match value {
+         SortValue::Date(millis) => json!(millis),
+         SortValue::Text(text) => json!(text),
+         SortValue::Null => SerdeVal::Null
+      }
+   }
+
+   fn sort_value(field: SortField, value: &SerdeVal) -> Option<SortValue> {
+      match (field, value) {
+         (_, SerdeVal::Null) => Some(SortValue::Null),
+         (SortField::CreatedAt, val) => val.as_i64().map(SortValue::Date),
+         (_, val) => val.as_str().map(|val| SortValue::Text(val.to_string()))
+      }
+   }
+
+   pub struct Sorting {
+      pub field: SortField,
+      pub descending: bool
+   }
+
+   impl Sorting {
+      /// Defaults to the newest messages first, or to the most relevant ones when searching
+      pub fn parse(sort: Option<String>, order: Option<String>, searching: bool) -> Result<Self, FilterErr> {
+         let field = match sort {
+            Some(sort) => sort_from_name(&sort)
+               .ok_or_else(|| bad_param("Invalid sort. Must be one of: \"createdAt\", \"sender\", \"name\", \"subject\" or \"relevance\""))?,
+            None if searching => SortField::Relevance,
+            None => SortField::CreatedAt
+         };
+         if field == SortField::Relevance && !searching {
+            return Err(bad_param("Sorting by relevance requires a search query (\"q\")"));
+         }
+
+         let descending = match order.as_deref() {
+            Some("asc") if field == SortField::Relevance => return Err(bad_param("Relevance can only be sorted in descending order")),
+            Some("asc") => false,
+            Some("desc") => true,
+            None => field == SortField::CreatedAt || field == SortField::Relevance,
+            Some(_) => return Err(bad_param("Invalid order. Must be \"asc\" or \"desc\""))
+         };
+
+         Ok(Sorting { field, descending })
+      }
+   }
+
+   /// Where the next page starts
+   pub enum CursorPos {
+      After(Position),
+      //* Text scores can't be filtered on, relevance pages are offset based
+      Skip(u64)
+   }
+
+   /// Position right after the last message of a page, handed out base64 encoded
+   #[derive(Serialize, Deserialize, Debug)]
+   pub struct Cursor {
+      #[serde(rename = "s")]
+      sort: String,
+      #[serde(rename = "d")]
+      descending: bool,
+      #[serde(rename = "v", default)]
+      value: SerdeVal,
+      #[serde(rename = "i", default)]
+      id: String,
+      #[serde(rename = "o", default, skip_serializing_if = "Option::is_none")]
+      offset: Option<u64>
+   }
+
+   impl Cursor {
+      /// `offset` is how many messages precede the page's end, used when sorting by relevance
+      pub fn after(sorting: &Sorting, msg: &Message, offset: u64) -> Option<Self> {
+         let (value, offset) = match sorting.field {
+            SortField::Relevance => (SerdeVal::Null, Some(offset)),
+            field => (cursor_value(field.value_of(msg)), None)
+         };
+
+         Some(Cursor {
+            sort: sort_name(sorting.field).to_string(),
+            descending: sorting.descending,
+            value,
+            id: msg.id?.to_hex(),
+            offset
+         })
+      }
+
+      pub fn encode(&self) -> String {
+         base64::encode_config(json!(self).to_string(), base64::URL_SAFE_NO_PAD)
+      }
+
+      pub fn decode(raw: &str) -> Result<Self, FilterErr> {
+         base64::decode_config(raw, base64::URL_SAFE_NO_PAD).ok()
+            .and_then(|bytes| serde_json::from_slice::<Cursor>(&bytes).ok())
+            .ok_or_else(|| bad_param("Invalid cursor"))
+      }
+
+      /// Where the messages coming after the cursor start in the given sorting
+      pub fn position(&self, sorting: &Sorting) -> Result<CursorPos, FilterErr> {
+         if self.sort != sort_name(sorting.field) || self.descending != sorting.descending {
+            return Err(bad_param("The cursor was issued for another sorting, request the first page again"));
+         }
+         if sorting.field == SortField::Relevance {
+            return self.offset.map(CursorPos::Skip).ok_or_else(|| bad_param("Invalid cursor"));
+         }
+
+         let value = sort_value(sorting.field, &self.value).ok_or_else(|| bad_param("Invalid cursor"))?;
+         let id = ObjectId::from_str(&self.id).map_err(|_| bad_param("Invalid cursor"))?;
+
+         Ok(CursorPos::After(Position { value, id }))
+      }
+   }
+
+   #[derive(Clone, Copy, Debug, PartialEq)]
+   pub enum ListField {
+      Id,
+      Sender,
+      Email,
+      SentAt,
+      Subject,
+      Message,
+      Locale,
+      Read,
+      Archived,
+      Delivery,
+      Ack,
+      DeletedAt,
+      Spam
+   }
+
+   impl ListField {
+      pub const ALL: [ListField; 13] = [
+         ListField::Id, ListField::Sender, ListField::Email, ListField::SentAt, ListField::Subject, ListField::Message,
+         ListField::Locale, ListField::Read, ListField::Archived, ListField::Delivery, ListField::Ack, ListField::DeletedAt,
+         ListField::Spam
+      ];
+      //* What the listing always returned before fields could be picked
+      pub const DEFAULT: [ListField; 4] = [ ListField::Id, ListField::Sender, ListField::Email, ListField::SentAt ];
+
+      pub fn key(&self) -> &'static str {
+         match self {
+            ListField::Id => "id",
+            ListField::Sender => "sender",
+            ListField::Email => "email",
+            ListField::SentAt => "sent_at",
+            ListField::Subject => "subject",
+            ListField::Message => "message",
+            ListField::Locale => "locale",
+            ListField::Read => "read",
+            ListField::Archived => "archived",
+            ListField::Delivery => "delivery",
+            ListField::Ack => "ack",
+            ListField::DeletedAt => "deleted_at",
+            ListField::Spam => "spam"
+         }
+      }
+
+      pub fn value(&self, msg: &Message) -> SerdeVal {
+         match self {
+            ListField::Id => msg.id.map(|oid| json!(oid.to_string())).unwrap_or(SerdeVal::Null),
+            ListField::Sender => json!(msg.name),
+            ListField::Email => json!(msg.from),
+            ListField::SentAt => msg.created_at.map(|date| json!(date.to_chrono().to_rfc3339())).unwrap_or(SerdeVal::Null),
+            ListField::Subject => json!(msg.subject),
+            ListField::Message => json!(msg.message),
+            ListField::Locale => json!(msg.locale),
+            ListField::Read => json!(msg.read),
+            ListField::Archived => json!(msg.archived),
+            ListField::Delivery => msg.delivery.as_ref().map(delivery_json).unwrap_or(SerdeVal::Null),
+            ListField::Ack => msg.ack.as_ref().map(delivery_json).unwrap_or(SerdeVal::Null),
+            ListField::DeletedAt => msg.deleted_at.map(|date| json!(date.to_chrono().to_rfc3339())).unwrap_or(SerdeVal::Null),
+            ListField::Spam => msg.spam.as_ref().map(spam_json).unwrap_or(SerdeVal::Null)
+         }
+      }
+   }
+
+   /// Comma separated field names, the default listing fields when absent
+   pub fn parse_fields(fields: Option<String>) -> Result<Vec<ListField>, FilterErr> {
+      let fields = match fields {
+         Some(fields) if !fields.trim().is_empty() => fields,
+         _ => return Ok(ListField::DEFAULT.to_vec())
+      };
+
+      let mut picked = Vec::<ListField>::new();
+      for name in fields.split(',').map(|name| name.trim()) {
+         match ListField::ALL.iter().find(|field| field.key() == name) {
+            Some(field) if !picked.contains(field) => picked.push(*field),
+            Some(_) => {},
+            None => return Err(bad_param(&format!("Unknown field \"{}\"", name)))
+         }
+      }
+
+      Ok(picked)
+   }
+}
+
+pub mod msgs_search {
+   use regex::{Regex, RegexBuilder};
+   use serde_json::{Map as SerdeMap, Value as SerdeVal};
+
+   use crate::{models::message::Message, security::sanitizers::unescape_html};
+
+   //* Same fields the stores search through
+   const SEARCHED_FIELDS: [&str; 3] = ["subject", "message", "name"];
+   const SNIPPET_CONTEXT: usize = 60;
+   const MAX_QUERY_LEN: usize = 256;
+
+   pub struct Search {
+      pub query: String,
+      terms: Option<Regex>
+   }
+
+   fn escape_html(text: &str) -> String {
+      let mut escaped = String::with_capacity(text.len());
+      for ch in text.chars() {
+         match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '\u{a0}' => escaped.push_str("&nbsp;"),
+            ch => escaped.push(ch)
+         }
+      }
+
+      escaped
+   }
+
+   impl Search {
+      pub fn new(query: String) -> Option<Self> {
+         let query: String = query.trim().chars().take(MAX_QUERY_LEN).collect();
+         if query.is_empty() {
+            return None;
+         }
+
+         //* Excluded terms ("-word") are never highlighted, phrases are highlighted word by word
+         let terms = query.split_whitespace()
+            .filter(|term| !term.starts_with('-'))
+            .map(|term| term.trim_matches('"'))
+            .filter(|term| !term.is_empty())
+            .map(regex::escape)
+            .collect::<Vec<String>>();
+         let terms = match terms.is_empty() {
+            true => None,
+            false => RegexBuilder::new(&terms.join("|")).case_insensitive(true).build().ok()
+         };
+
+         Some(Search { query, terms })
+      }
 
-         mongo_query_filters.insert("from", doc! { "$in": bson_vec });
+      //* `text` is plain, every segment is escaped again so only the marks are markup
+      fn highlight(&self, text: &str) -> String {
+         let terms = match &self.terms {
+            Some(terms) => terms,
+            None => return escape_html(text)
+         };
+
+         let mut highlighted = String::with_capacity(text.len());
+         let mut last = 0;
+         for found in terms.find_iter(text) {
+            highlighted.push_str(&escape_html(&text[last..found.start()]));
+            highlighted.push_str(&format!("<mark>{}</mark>", escape_html(found.as_str())));
+            last = found.end();
+         }
+         highlighted.push_str(&escape_html(&text[last..]));
+
+         highlighted
+      }
+
+      /// Portion of `text` around its first match, with every match wrapped in `<mark>`.
+      /// Stored fields are HTML escaped, they are matched unescaped so no entity is
+      /// matched into or cut in half, and snippets come escaped so they can be rendered as is
+      fn snippet(&self, text: &str) -> Option<String> {
+         let text = unescape_html(text);
+         let first = self.terms.as_ref()?.find(&text)?;
+
+         let mut start = first.start().saturating_sub(SNIPPET_CONTEXT);
+         while !text.is_char_boundary(start) {
+            start -= 1;
+         }
+         let mut end = (first.end() + SNIPPET_CONTEXT).min(text.len());
+         while !text.is_char_boundary(end) {
+            end += 1;
+         }
+
+         Some(format!(
+            "{}{}{}",
+            if start > 0 { "…" } else { "" },
+            self.highlight(&text[start..end]),
+            if end < text.len() { "…" } else { "" }
+         ))
       }
 
-      Ok(mongo_query_filters)
+      /// Highlighted snippets of the searched fields that matched
+      pub fn highlights(&self, msg: &Message) -> SerdeVal {
+         let mut highlights = SerdeMap::new();
+         for (field, text) in SEARCHED_FIELDS.iter().zip([&msg.subject, &msg.message, &msg.name]) {
+            if let Some(snippet) = self.snippet(text) {
+               highlights.insert(field.to_string(), SerdeVal::String(snippet));
+            }
+         }
+
+         SerdeVal::Object(highlights)
+      }
    }
 }
 
-mod msgs_filter_params {
+pub mod msgs_filter_params {
    #[derive(FromForm)]
    pub struct ReadFilter(pub bool);
 
@@ -130,10 +478,51 @@ mod msgs_filter_params {
    }
 }
 
-#[get("/?<read>&<date>&<archived>&<sender>")]
-pub async fn get_msgs(cms_db: &State<MessageCmsDb>, auth: Auth, 
+#[get("/?<read>&<date>&<archived>&<sender>&<q>&<limit>&<after>&<sort>&<order>&<count>&<fields>")]
+pub async fn get_msgs(store: &State<Store>, auth: Auth, 
+   read: Option<ReadFilter>, date: Option<DateFilter>, archived: Option<ArchivedFilter>,
+   sender: Option<SenderFilter>, q: Option<String>, limit: Option<i64>, after: Option<String>, sort: Option<String>,
+   order: Option<String>, count: Option<bool>, fields: Option<String>
+) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_READ ];
+
+  if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+    return Custom(
+      HttpStatus::new(403),
+      RawJson(json!({
+        "error": "Not authorized: insufficient permissions for this token"
+      }).to_string())
+    );
+  }
+
+   list_msgs(store, read, date, archived, sender, q, limit, after, sort, order, count, fields, Folder::Inbox).await
+}
+
+#[get("/trash?<read>&<date>&<archived>&<sender>&<q>&<limit>&<after>&<sort>&<order>&<count>&<fields>")]
+pub async fn get_trash(store: &State<Store>, auth: Auth, 
+   read: Option<ReadFilter>, date: Option<DateFilter>, archived: Option<ArchivedFilter>,
+   sender: Option<SenderFilter>, q: Option<String>, limit: Option<i64>, after: Option<String>, sort: Option<String>,
+   order: Option<String>, count: Option<bool>, fields: Option<String>
+) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_READ ];
+
+  if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+    return Custom(
+      HttpStatus::new(403),
+      RawJson(json!({
+        "error": "Not authorized: insufficient permissions for this token"
+      }).to_string())
+    );
+  }
+
+   list_msgs(store, read, date, archived, sender, q, limit, after, sort, order, count, fields, Folder::Trash).await
+}
+
+#[get("/spam?<read>&<date>&<archived>&<sender>&<q>&<limit>&<after>&<sort>&<order>&<count>&<fields>")]
+pub async fn get_spam(store: &State<Store>, auth: Auth, 
    read: Option<ReadFilter>, date: Option<DateFilter>, archived: Option<ArchivedFilter>,
-   sender: Option<SenderFilter>
+   sender: Option<SenderFilter>, q: Option<String>, limit: Option<i64>, after: Option<String>, sort: Option<String>,
+   order: Option<String>, count: Option<bool>, fields: Option<String>
 ) -> Custom<RawJson<String>> {
    let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_READ ];
 
@@ -146,32 +535,98 @@ pub async fn get_msgs(cms_db: &State<MessageCmsDb>, auth: Auth,
     );
   }
 
-   let filter = get_filter(read, date, archived, sender);
-   if filter.is_err() {
-      match filter.unwrap_err() {
-         FilterErr { msg, unexpected: true } => {
+   list_msgs(store, read, date, archived, sender, q, limit, after, sort, order, count, fields, Folder::Spam).await
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Folder {
+   Inbox,
+   Trash,
+   //* Quarantined spam, unless trashed
+   Spam
+}
+
+/// Pages through the inbox, the trash or the spam quarantine, they take the very same parameters
+#[allow(clippy::too_many_arguments)]
+async fn list_msgs(store: &State<Store>,
+   read: Option<ReadFilter>, date: Option<DateFilter>, archived: Option<ArchivedFilter>,
+   sender: Option<SenderFilter>, q: Option<String>, limit: Option<i64>, after: Option<String>, sort: Option<String>,
+   order: Option<String>, count: Option<bool>, fields: Option<String>, folder: Folder
+) -> Custom<RawJson<String>> {
+   let search = q.and_then(Search::new);
+   let params = get_filter(read, date, archived, sender)
+      .and_then(|filter| Ok((filter, Sorting::parse(sort, order, search.is_some())?, parse_fields(fields)?)))
+      .and_then(|(filter, sorting, fields)| {
+         let cursor_pos = match after {
+            Some(after) => Some(Cursor::decode(&after)?.position(&sorting)?),
+            None => None
+         };
+
+         Ok((filter, cursor_pos, sorting, fields))
+      });
+   let (mut filter, cursor_pos, sorting, fields) = match params {
+      Ok(params) => params,
+      Err(FilterErr { msg, unexpected: true }) => {
+         return Custom(
+            HttpStatus::new(500), 
+            RawJson(json!({
+               "error": msg
+            }).to_string())
+         );
+      },
+      Err(FilterErr { msg, unexpected: false }) => {
+         return Custom(
+            HttpStatus::new(400), 
+            RawJson(json!({
+               "error": msg
+            }).to_string())
+         );
+      }
+   };
+   let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+   filter.search = search.as_ref().map(|search| search.query.clone());
+   filter.trashed = folder == Folder::Trash;
+   filter.quarantined = match folder {
+      Folder::Inbox => Some(false),
+      Folder::Trash => None,
+      Folder::Spam => Some(true)
+   };
+
+   //* Counts the whole listing, not only what comes after the cursor
+   let total = match count {
+      Some(true) => match store.messages.count(&filter).await {
+         Ok(total) => Some(total),
+         Err(err) => {
+            warn!("Failed counting messages. Error: {}", err);
             return Custom(
                HttpStatus::new(500), 
                RawJson(json!({
-                  "error": msg
+                  "error": "Failed retrieving messages. Don't worry this is a fault on our side!"
                }).to_string())
             );
          }
-         FilterErr { msg, unexpected: false } => {
-            return Custom(
-               HttpStatus::new(400), 
-               RawJson(json!({
-                  "error": msg
-               }).to_string())
-            );
-         }
-      }
-   }
-   let filter = filter.unwrap();
+      },
+      _ => None
+   };
 
-   match cms_db.get_msg_col().find(Some(filter), None).await {
+   let (after, skip) = match cursor_pos {
+      Some(CursorPos::After(position)) => (Some(position), 0),
+      Some(CursorPos::Skip(skip)) => (None, skip),
+      None => (None, 0)
+   };
+   //* One extra message tells whether there is a next page
+   let query = ListQuery {
+      filter,
+      sort: sorting.field,
+      descending: sorting.descending,
+      after,
+      skip,
+      limit: limit + 1
+   };
+
+   match store.messages.list(&query).await {
       Err(err) => {
-         warn!("Failed retrieving messages. Error: {:?}", err);
+         warn!("Failed retrieving messages. Error: {}", err);
 
          Custom(
             HttpStatus::new(500), 
@@ -180,39 +635,42 @@ pub async fn get_msgs(cms_db: &State<MessageCmsDb>, auth: Auth,
             }).to_string())
          )
       },
-      Ok(mut cursor) => async {
-         let mut msgs_res: Vec<SerdeVal> = Vec::new();
-
-         loop {
-            let doc = cursor.advance().await;
-            if doc.is_err() {
-               warn!("Failed to retrieve a doc from MongoDB. Error: {:?}", doc.err().unwrap());
-               continue;
-            }
-            if !doc.unwrap() {
-               break;
-            }
+      Ok(mut listed) => {
+         let next = match listed.len() as i64 > limit {
+            true => {
+               listed.truncate(limit as usize);
+               listed.last().and_then(|last| Cursor::after(&sorting, &last.msg, skip + limit as u64)).map(|cursor| cursor.encode())
+            },
+            false => None
+         };
 
-            match cursor.deserialize_current() {
-               Err(err) => {
-                  warn!("Failed to deserialize a doc from MongoDB. Error: {:?}", err);
-                  continue;
-               },
-               Ok(msg) => msgs_res.push(json!({
-                  "id": msg.id.unwrap().to_string(),
-                  "sender": msg.name,
-                  "email": msg.from,
-                  "sent_at": msg.created_at.unwrap().to_chrono().to_rfc3339().to_string(),
-               }))
-            }
+         let msgs_res = listed.iter()
+            .map(|listed| {
+               let mut msg = SerdeMap::new();
+               for field in fields.iter() {
+                  msg.insert(field.key().to_string(), field.value(&listed.msg));
+               }
+               if let Some(search) = &search {
+                  msg.insert("score".to_string(), json!(listed.score.unwrap_or_default()));
+                  msg.insert("highlights".to_string(), search.highlights(&listed.msg));
+               }
+
+               SerdeVal::Object(msg)
+            })
+            .collect::<Vec<SerdeVal>>();
+
+         let mut res = json!({
+            "msgs": msgs_res,
+            "next": next
+         });
+         if let Some(total) = total {
+            res["total"] = json!(total);
          }
 
          Custom(
             HttpStatus::new(200), 
-            RawJson(json!({
-               "msgs": msgs_res
-            }).to_string())
+            RawJson(res.to_string())
          )
-      }.await
+      }
    }
 }
@@ -4,13 +4,18 @@ use rocket::{
   State,
   serde::json::serde_json::json
 };
-use crate::mongo::{MessageCmsDb, ConnCheck};
+use crate::{
+  guards::ServerBreaker,
+  store::{Store, ConnCheck}
+};
+use super::server_limit::server_limit_json;
 
 #[get("/")]
-pub async fn check_health(cms_db: &State<MessageCmsDb>) -> Custom<RawJson<String>> {
+pub async fn check_health(store: &State<Store>, breaker: &State<ServerBreaker>) -> Custom<RawJson<String>> {
   info!("Health check requested!...");
+  let server_limit = server_limit_json(&breaker.lock().status());
   
-  match cms_db.check_conn().await {
+  match store.messages.check_conn().await {
     ConnCheck::Ok => Custom(
       HttpStatus::new(200), 
       RawJson(json!({
@@ -22,12 +27,13 @@ pub async fn check_health(cms_db: &State<MessageCmsDb>) -> Custom<RawJson<String
             "db": {
                 "status_msg": "OK",
                 "is_ok": true
-            }
+            },
+            "server_limit": server_limit
          }
       }).to_string())
     ),
     ConnCheck::Issue(err) => {
-      error!("Failed to connect to CMS DB Cluster: {}", err);
+      error!("Failed to reach the message store: {}", err);
       Custom(
         HttpStatus::new(200), 
         RawJson(json!({
@@ -39,7 +45,8 @@ pub async fn check_health(cms_db: &State<MessageCmsDb>) -> Custom<RawJson<String
               "db": {
                   "status_msg": "There seems to be an issue between the server's DB connection.",
                   "is_ok": false
-              }
+              },
+              "server_limit": server_limit
            }
         }).to_string())
       )
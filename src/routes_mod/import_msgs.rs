@@ -0,0 +1,72 @@
+use serde_json::json;
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::Status as HttpStatus,
+   data::{Data, ToByteUnit},
+   State
+};
+
+use crate::{
+   auth::{
+      auth0_token_related::PermCheckOpt,
+      auth0_perm_claims::ScopePerm,
+   },
+   guards::Auth,
+   import::{import_messages, ImportFormat},
+   store::Store
+};
+
+const MAX_IMPORT_SIZE: u64 = 64;
+
+/// Imports the messages of an MBOX, an EML (or a zip of them) or JSON lines body,
+/// reporting for every record whether it was imported, already stored or failed
+#[post("/import?<format>", data = "<raw>")]
+pub async fn import_msgs(store: &State<Store>, auth: Auth, format: Option<String>, raw: Data<'_>) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_IMPORT ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return Custom(
+         HttpStatus::new(403),
+         RawJson(json!({
+            "error": "Not authorized: insufficient permissions for this token"
+         }).to_string())
+      );
+   }
+
+   let format = match format.as_deref().map(ImportFormat::from_name) {
+      None => ImportFormat::Mbox,
+      Some(Some(format)) => format,
+      Some(None) => return Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": "Invalid format. Must be \"mbox\", \"eml\" or \"jsonl\"."
+         }).to_string())
+      )
+   };
+
+   let raw = match raw.open(MAX_IMPORT_SIZE.mebibytes()).into_bytes().await {
+      Ok(raw) if raw.is_complete() => raw.into_inner(),
+      Ok(_) => return Custom(
+         HttpStatus::new(413),
+         RawJson(json!({
+            "error": format!("Import is too large, split it in files of at most {} MiB.", MAX_IMPORT_SIZE)
+         }).to_string())
+      ),
+      Err(err) => {
+         warn!("Failed reading message import. Error: {}", err);
+         return Custom(
+            HttpStatus::new(400),
+            RawJson(json!({
+               "error": "Failed reading the import."
+            }).to_string())
+         );
+      }
+   };
+
+   let report = import_messages(store, format, &raw).await;
+
+   Custom(
+      HttpStatus::new(200),
+      RawJson(report.to_json().to_string())
+   )
+}
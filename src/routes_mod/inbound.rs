@@ -0,0 +1,114 @@
+use chrono::Utc;
+use serde_json::json;
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::Status as HttpStatus,
+   data::{Data, ToByteUnit},
+   State
+};
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{
+   guards::InboundAuth,
+   mailer::{Mailer, inbound::InboundEmail},
+   models::message::{ThreadEntry, ThreadDirection},
+   security::sanitizers,
+   store::Store
+};
+
+const MAX_INBOUND_SIZE: u64 = 10;
+
+fn json_res(code: u16, key: &str, msg: &str) -> Custom<RawJson<String>> {
+   Custom(
+      HttpStatus::new(code),
+      RawJson(json!({ key: msg }).to_string())
+   )
+}
+
+/// Accepts a raw RFC 5322 email and appends it to the conversation it answers
+#[post("/inbound", data = "<raw>")]
+pub async fn ingest_email(store: &State<Store>, mailer: &State<Mailer>, _auth: InboundAuth, raw: Data<'_>) -> Custom<RawJson<String>> {
+   let raw = match raw.open(MAX_INBOUND_SIZE.mebibytes()).into_bytes().await {
+      Ok(raw) if raw.is_complete() => raw.into_inner(),
+      Ok(_) => return json_res(413, "error", "Email is too large."),
+      Err(err) => {
+         warn!("Failed reading inbound email. Error: {}", err);
+         return json_res(400, "error", "Failed reading the email.");
+      }
+   };
+
+   let email = match InboundEmail::parse(&raw) {
+      Some(email) => email,
+      None => return json_res(400, "error", "Body is not a valid RFC 5322 email.")
+   };
+
+   //* Tagged reply address first, then the ids we generated, then any stored Message-ID
+   let msg_oid = match email.tagged_conversation(mailer).or_else(|| email.referenced_conversation(mailer)) {
+      Some(oid) => Some(oid),
+      None if !email.all_references().is_empty() => {
+         match store.messages.find_by_thread_email(&email.all_references()).await {
+            Ok(msg) => msg.and_then(|msg| msg.id),
+            Err(err) => {
+               warn!("Failed matching inbound email references. Error: {}", err);
+               return json_res(500, "error", "Internal server error. Don't worry, this is our fault.");
+            }
+         }
+      },
+      None => None
+   };
+   let msg_oid = match msg_oid {
+      Some(oid) => oid,
+      None => return json_res(422, "error", "Email doesn't belong to any known conversation.")
+   };
+
+   let msg = match store.messages.find(msg_oid).await {
+      Ok(Some(msg)) => msg,
+      Ok(None) => return json_res(422, "error", "Email doesn't belong to any known conversation."),
+      Err(err) => {
+         warn!("Failed fetching message {} for inbound email. Error: {:?}", msg_oid, err);
+         return json_res(500, "error", "Internal server error. Don't worry, this is our fault.");
+      }
+   };
+
+   //* Forwarders retry on timeouts, the same email must only be added once
+   if let Some(message_id) = &email.message_id {
+      if msg.thread.iter().any(|entry| &entry.message_id == message_id) {
+         return json_res(200, "success", "Email was already ingested.");
+      }
+   }
+
+   let entry_id = ObjectId::new();
+   let entry = ThreadEntry {
+      id: entry_id,
+      direction: ThreadDirection::Inbound,
+      from: email.from_addr.clone(),
+      to: email.recipients.join(", "),
+      subject: sanitizers::message_sanitizing(email.subject.clone()),
+      body: sanitizers::message_sanitizing(email.text.clone()),
+      message_id: email.message_id.clone()
+         .unwrap_or_else(|| mailer.message_id(&format!("{}.{}.inbound", msg_oid.to_hex(), entry_id.to_hex()))),
+      in_reply_to: email.in_reply_to.first().cloned(),
+      references: email.references.clone(),
+      author: email.from_name.clone().map(sanitizers::message_sanitizing),
+      attachments: email.attachments,
+      created_at: DateTime::from(Utc::now()),
+      delivery: None
+   };
+
+   //* New activity on the conversation, flag it as unread again
+   match store.messages.push_thread_entry(msg_oid, &entry, true).await {
+      Ok(false) => json_res(422, "error", "Email doesn't belong to any known conversation."),
+      Ok(true) => Custom(
+         HttpStatus::new(200),
+         RawJson(json!({
+            "success": "Email added to the conversation!",
+            "message_id": msg_oid.to_string(),
+            "entry_id": entry_id.to_string()
+         }).to_string())
+      ),
+      Err(err) => {
+         warn!("Failed storing inbound email for message {}. Error: {:?}", msg_oid, err);
+         json_res(500, "error", "Internal server error. Don't worry, this is our fault.")
+      }
+   }
+}
@@ -4,10 +4,36 @@ mod health;
 mod read_message;
 mod msg_opacity;
 mod del_msg;
+mod delivery_jobs;
+mod reply_msg;
+mod inbound;
+mod template_preview;
+mod webhooks;
+mod event_stream;
+mod privacy;
+mod export_msgs;
+mod import_msgs;
+mod spam;
+mod challenge;
+mod form_token;
+mod server_limit;
 
-pub use del_msg::{del_msg as del_msg_route, del_msg_no_id as del_msg_no_id_route};
+pub use del_msg::{del_msg as del_msg_route, del_msg_no_id as del_msg_no_id_route, restore_msg as restore_msg_route};
 pub use msg_opacity::toggle_read_archive as toggle_read_archive_route;
 pub use read_message::{get_msg as get_msg_route, get_msg_no_id as get_msg_no_id_route};
 pub use health::check_health as check_health_route;
 pub use send_msg::send_message as sd_msg_route;
-pub use get_msgs::get_msgs as gt_msg_route;
+pub use get_msgs::{get_msgs as gt_msg_route, get_trash as get_trash_route, get_spam as get_spam_route};
+pub use delivery_jobs::{list_dead_jobs as list_dead_jobs_route, redrive_jobs as redrive_jobs_route};
+pub use reply_msg::reply_msg as reply_msg_route;
+pub use inbound::ingest_email as ingest_email_route;
+pub use template_preview::preview_template as preview_template_route;
+pub use event_stream::stream_events as stream_events_route;
+pub use webhooks::{list_webhooks as list_webhooks_route, list_webhook_deliveries as list_webhook_deliveries_route};
+pub use privacy::{forget as forget_sender_route, subject_access as subject_access_route};
+pub use export_msgs::export_msgs as export_msgs_route;
+pub use import_msgs::import_msgs as import_msgs_route;
+pub use spam::{mark_spam as mark_spam_route, unmark_spam as unmark_spam_route};
+pub use challenge::issue_challenge as issue_challenge_route;
+pub use form_token::issue_form_token as issue_form_token_route;
+pub use server_limit::{get_server_limit as get_server_limit_route, tune_server_limit as tune_server_limit_route};
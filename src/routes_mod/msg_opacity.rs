@@ -1,5 +1,5 @@
 use std::str::FromStr;
-use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::bson::oid::ObjectId;
 use rocket::{
    response::{status::Custom , content::RawJson},
    http::Status as HttpStatus,
@@ -8,16 +8,18 @@ use rocket::{
 use serde_json::json;
 
 use crate::{
+   events::EventBus,
+   models::event::EventKind,
    auth::{
       auth0_token_related::PermCheckOpt,
       auth0_perm_claims::ScopePerm,
     },
-   mongo::MessageCmsDb, 
+   store::{Store, FlagsUpdate},
    guards::Auth,
 };
 
 #[post("/toggle?<toggle_type>&<id>&<value>")]
-pub async fn toggle_read_archive(db: &State<MessageCmsDb>, auth: Auth, 
+pub async fn toggle_read_archive(store: &State<Store>, events: &State<EventBus>, auth: Auth, 
    toggle_type: Option<String>, id: Option<String>, value: Option<bool>
 ) -> Custom<RawJson<String>> {
    if toggle_type.is_none() || id.is_none() || value.is_none() {
@@ -68,12 +70,12 @@ pub async fn toggle_read_archive(db: &State<MessageCmsDb>, auth: Auth,
    }
    let msg_oid = msg_oid.unwrap();
    
-   let update_data = match toggle_type.as_str() {
+   let flags = match toggle_type.as_str() {
       "archive" => {
-         doc! { "$set": { "archived": value } }
+         FlagsUpdate { archived: Some(value), ..Default::default() }
       },
       "read" => {
-         doc! { "$set": { "read": value } }
+         FlagsUpdate { read: Some(value), ..Default::default() }
       },
       _ => {
          return Custom(
@@ -85,9 +87,28 @@ pub async fn toggle_read_archive(db: &State<MessageCmsDb>, auth: Auth,
       }
    };
 
-   let query = doc! { "_id": { "$eq": msg_oid } };
-   match db.get_msg_col().update_one(query, update_data, None).await {
-      Ok(_) => {
+   match store.messages.update_flags(msg_oid, flags).await {
+      Ok(previous) => {
+         //* Only actual changes are events, `previous` predates the update
+         let change = previous.and_then(|msg| match toggle_type.as_str() {
+            "archive" if msg.archived != value => Some((
+               if value { EventKind::MessageArchived } else { EventKind::MessageUnarchived },
+               msg.read, value
+            )),
+            "read" if msg.read != value => Some((
+               if value { EventKind::MessageRead } else { EventKind::MessageUnread },
+               value, msg.archived
+            )),
+            _ => None
+         });
+         if let Some((kind, read, archived)) = change {
+            events.publish(kind, msg_oid, json!({
+               "id": msg_oid.to_string(),
+               "read": read,
+               "archived": archived
+            })).await;
+         }
+
          Custom(
             HttpStatus::new(200),
             RawJson(json!({
@@ -0,0 +1,187 @@
+use std::env;
+
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value as SerdeVal};
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::{Status as HttpStatus, ContentType},
+   serde::json::Json,
+   State
+};
+
+use crate::{
+   events::EventBus,
+   export::{Attachment, message_emails, mbox_entry},
+   guards::Auth,
+   auth::{
+      auth0_token_related::PermCheckOpt,
+      auth0_perm_claims::ScopePerm,
+   },
+   mailer::Mailer,
+   models::message::Message,
+   retention::{forget_sender, ErasureMode},
+   store::{Store, JobFilter}
+};
+use super::{read_message::message_json, delivery_jobs::job_json};
+
+#[derive(Deserialize, Debug)]
+pub struct ForgetPayload {
+   pub email: String,
+   //* "erase" (default) or "anonymize"
+   pub mode: Option<String>
+}
+
+#[post("/privacy/forget", format = "application/json", data = "<payload>")]
+pub async fn forget(store: &State<Store>, events: &State<EventBus>, auth: Auth, payload: Json<ForgetPayload>) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_PRIVACY_MANAGE ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return Custom(
+         HttpStatus::new(403),
+         RawJson(json!({
+            "error": "Not authorized: insufficient permissions for this token"
+         }).to_string())
+      );
+   }
+
+   //* Receipts are worthless unless they can be verified later on
+   let secret = match env::var("ERASURE_RECEIPT_SECRET") {
+      Ok(val) if !val.is_empty() => val,
+      _ => return Custom(
+         HttpStatus::new(503),
+         RawJson(json!({
+            "error": "Erasure is unavailable: no receipt secret is configured."
+         }).to_string())
+      )
+   };
+
+   let payload = payload.into_inner();
+   let email = payload.email.trim();
+   if email.is_empty() {
+      return Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": "An email address must be given."
+         }).to_string())
+      );
+   }
+   let mode = match payload.mode.as_deref().map(ErasureMode::from_name) {
+      None => ErasureMode::Erase,
+      Some(Some(mode)) => mode,
+      Some(None) => return Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": "Invalid mode. Must be \"erase\" or \"anonymize\"."
+         }).to_string())
+      )
+   };
+
+   let requested_by = auth.decoded_payload.sub.as_ref().map(|sub| sub.trim_matches('"').to_string());
+   match forget_sender(store, events, email, mode, requested_by, &secret).await {
+      Ok(receipt) => Custom(
+         HttpStatus::new(200),
+         RawJson(json!({
+            "receipt": receipt
+         }).to_string())
+      ),
+      Err(err) => {
+         warn!("Failed erasing a sender's messages. Error: {}", err);
+         Custom(
+            HttpStatus::new(500),
+            RawJson(json!({
+               "error": "Internal server error, the erasure may be incomplete and should be requested again. Don't worry, this is our fault."
+            }).to_string())
+         )
+      }
+   }
+}
+
+/// Everything stored about a sender: their messages along with the whole
+/// conversations, delivery records and recorded events. As JSON, or as an
+/// MBOX of the conversations' emails
+#[get("/privacy/export?<email>&<format>")]
+pub async fn subject_access(store: &State<Store>, mailer: &State<Mailer>, auth: Auth,
+   email: Option<String>, format: Option<String>
+) -> Result<Attachment<String>, Custom<RawJson<String>>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_PRIVACY_MANAGE ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return Err(Custom(
+         HttpStatus::new(403),
+         RawJson(json!({
+            "error": "Not authorized: insufficient permissions for this token"
+         }).to_string())
+      ));
+   }
+
+   let email = match email.as_deref().map(str::trim) {
+      Some(email) if !email.is_empty() => email.to_string(),
+      _ => return Err(Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": "An email address must be given."
+         }).to_string())
+      ))
+   };
+   let as_mbox = match format.as_deref() {
+      None | Some("json") => false,
+      Some("mbox") => true,
+      Some(_) => return Err(Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": "Invalid format. Must be \"json\" or \"mbox\"."
+         }).to_string())
+      ))
+   };
+
+   let internal_err = |err| {
+      warn!("Failed gathering a sender's data. Error: {}", err);
+      Custom(
+         HttpStatus::new(500),
+         RawJson(json!({
+            "error": "Failed gathering the sender's data. Don't worry this is a fault on our side!"
+         }).to_string())
+      )
+   };
+
+   //* Same lookup as the erasure, whatever the case the address was stored with
+   let ids = store.messages.ids_by_sender(&email).await.map_err(internal_err)?;
+   let mut msgs = Vec::<Message>::with_capacity(ids.len());
+   for id in ids.iter() {
+      if let Some(msg) = store.messages.find(*id).await.map_err(internal_err)? {
+         msgs.push(msg);
+      }
+   }
+   msgs.sort_by_key(|msg| msg.created_at);
+
+   let generated_at = Utc::now();
+   let filename = format!("subject-access-{}", generated_at.format("%Y%m%d%H%M%S"));
+   if as_mbox {
+      let mbox = msgs.iter()
+         .flat_map(|msg| message_emails(mailer, msg))
+         .map(|email| mbox_entry(&email))
+         .collect::<String>();
+
+      return Ok(Attachment::new(mbox, ContentType::new("application", "mbox"), &format!("{}.mbox", filename)));
+   }
+
+   let jobs = store.jobs.list(&JobFilter { message_ids: Some(ids.clone()), ..Default::default() }).await.map_err(internal_err)?;
+   let events = store.events.for_messages(&ids).await.map_err(internal_err)?;
+
+   let export = json!({
+      "subject": email,
+      "generated_at": generated_at.to_rfc3339(),
+      "messages": msgs.iter().map(message_json).collect::<Vec<SerdeVal>>(),
+      "deliveries": jobs.iter().map(job_json).collect::<Vec<SerdeVal>>(),
+      "events": events.iter().map(|event| json!({
+         "id": event.id.to_hex(),
+         "type": event.kind,
+         "message_id": event.message_id.to_hex(),
+         "occurred_at": event.occurred_at.to_chrono().to_rfc3339(),
+         "data": event.data
+      })).collect::<Vec<SerdeVal>>()
+   });
+
+   Ok(Attachment::new(export.to_string(), ContentType::JSON, &format!("{}.json", filename)))
+}
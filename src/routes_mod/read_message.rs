@@ -1,24 +1,87 @@
 use std::str::FromStr;
 
-use serde_json::json;
+use serde_json::{json, Value as SerdeVal};
 use rocket::{
    response::{status::Custom, content::RawJson},
    http::Status as HttpStatus,
    State, log::private::warn
 };
-use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::bson::oid::ObjectId;
 
 use crate::{
-   MessageCmsDb,
+   events::EventBus,
    guards::Auth,
    auth::{
       auth0_token_related::PermCheckOpt,
       auth0_perm_claims::ScopePerm,
    },
+   models::{
+      message::{Message, ThreadEntry, DeliveryStatus, SpamInfo},
+      event::EventKind
+   },
+   store::{Store, FlagsUpdate}
 };
 
+pub fn delivery_json(delivery: &DeliveryStatus) -> SerdeVal {
+   json!({
+      "state": delivery.state,
+      "attempts": delivery.attempts,
+      "last_error": delivery.last_error,
+      "updated_at": delivery.updated_at.to_chrono().to_rfc3339(),
+   })
+}
+
+pub fn spam_json(spam: &SpamInfo) -> SerdeVal {
+   json!({
+      "score": spam.score,
+      "hits": spam.hits.iter().map(|hit| json!({ "rule": hit.rule, "score": hit.score })).collect::<Vec<SerdeVal>>(),
+      "quarantined": spam.quarantined,
+      "label": spam.label
+   })
+}
+
+pub fn thread_entry_json(entry: &ThreadEntry) -> SerdeVal {
+   json!({
+      "id": entry.id.to_string(),
+      "direction": entry.direction,
+      "from": entry.from,
+      "to": entry.to,
+      "subject": entry.subject,
+      "message": entry.body,
+      "message_id": entry.message_id,
+      "in_reply_to": entry.in_reply_to,
+      "author": entry.author,
+      "attachments": entry.attachments.iter().map(|attachment| json!({
+         "name": attachment.name,
+         "content_type": attachment.content_type,
+         "size": attachment.size,
+      })).collect::<Vec<SerdeVal>>(),
+      "sent_at": entry.created_at.to_chrono().to_rfc3339(),
+      "delivery": entry.delivery.as_ref().map(delivery_json),
+   })
+}
+
+pub fn message_json(msg: &Message) -> SerdeVal {
+   json!({
+      "id": msg.id.map(|id| id.to_string()),
+      "subject": msg.subject,
+      "message": msg.message,
+      "locale": msg.locale,
+      "from": msg.from,
+      "name": msg.name,
+      "read": msg.read,
+      "archived": msg.archived,
+      "sent_at": msg.created_at.map(|date| date.to_chrono().to_rfc3339()),
+      "deleted_at": msg.deleted_at.map(|date| date.to_chrono().to_rfc3339()),
+      "delivery": msg.delivery.as_ref().map(delivery_json),
+      "ack": msg.ack.as_ref().map(delivery_json),
+      "spam": msg.spam.as_ref().map(spam_json),
+      "thread": msg.thread.iter().map(thread_entry_json).collect::<Vec<SerdeVal>>(),
+   })
+}
+
 #[get("/get/<id>")]
-pub async fn get_msg(db: &State<MessageCmsDb>, auth: Auth, id: String) -> Custom<RawJson<String>> {
+pub async fn get_msg(store: &State<Store>, events: &State<EventBus>, auth: Auth, id: String) -> Custom<RawJson<String>> {
    let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_READ ];
 
   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
@@ -42,24 +105,24 @@ pub async fn get_msg(db: &State<MessageCmsDb>, auth: Auth, id: String) -> Custom
    }
    let msg_oid = msg_oid.unwrap();
 
-   let filter = doc! { "_id": { "$eq": msg_oid } };
-   let update_data = doc! { "$set": { "read": true } };
-   match db.get_msg_col().find_one_and_update(filter, update_data, None).await {
+   let flags = FlagsUpdate { read: Some(true), ..Default::default() };
+   match store.messages.update_flags(msg_oid, flags).await {
       Ok(Some(msg)) => {
-         
-         let msg_data = json!({
-            "id": msg.id.unwrap().to_string(),
-            "subject": msg.subject,
-            "message": msg.message,
-            "from": msg.from,
-            "name": msg.name,
-            "read": true,
-            "archived": msg.archived,
-         }).to_string();
+         //* The returned message predates the update
+         if !msg.read {
+            events.publish(EventKind::MessageRead, msg_oid, json!({
+               "id": msg_oid.to_string(),
+               "read": true,
+               "archived": msg.archived
+            })).await;
+         }
+
+         let mut msg_data = message_json(&msg);
+         msg_data["read"] = json!(true);
 
          Custom(
             HttpStatus::new(200),
-            RawJson(msg_data)
+            RawJson(msg_data.to_string())
          )
       },
       Ok(None) => Custom(
@@ -69,7 +132,7 @@ pub async fn get_msg(db: &State<MessageCmsDb>, auth: Auth, id: String) -> Custom
          }).to_string())         
       ),
       Err(e) => {
-         warn!("There was an error while fetching message from MongoDB! Err: {:?}", e);
+         warn!("There was an error while fetching message from the store! Err: {}", e);
          Custom(
             HttpStatus::new(500),
             RawJson(json!({
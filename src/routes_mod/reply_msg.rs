@@ -0,0 +1,180 @@
+use std::str::FromStr;
+
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::Status as HttpStatus,
+   serde::json::Json,
+   State
+};
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{
+   guards::Auth,
+   auth::{
+      auth0_token_related::PermCheckOpt,
+      auth0_perm_claims::ScopePerm,
+   },
+   mailer::{Mailer, reply},
+   models::{
+      job::JobKind,
+      message::{ThreadEntry, ThreadDirection, DeliveryState, DeliveryStatus}
+   },
+   queue::JobQueue,
+   security::sanitizers,
+   store::{Store, DeliveryTarget}
+};
+use super::read_message::thread_entry_json;
+
+#[derive(Deserialize, Debug)]
+pub struct ReplyPayload {
+   pub subject: Option<String>,
+   pub message: String
+}
+
+#[post("/<id>/reply", format = "application/json", data = "<payload>", rank = 2)]
+pub async fn reply_msg(store: &State<Store>, mailer: &State<Mailer>, queue: &State<JobQueue>,
+   auth: Auth, id: String, payload: Json<ReplyPayload>
+) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_REPLY ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return Custom(
+         HttpStatus::new(403),
+         RawJson(json!({
+            "error": "Not authorized: insufficient permissions for this token"
+         }).to_string())
+      );
+   }
+
+   let sender = match mailer.sender() {
+      Some(sender) => sender.email.to_string(),
+      None => return Custom(
+         HttpStatus::new(503),
+         RawJson(json!({
+            "error": "Replies are unavailable: outbound email delivery is disabled."
+         }).to_string())
+      )
+   };
+
+   let msg_oid = match ObjectId::from_str(&id) {
+      Ok(oid) => oid,
+      Err(_) => return Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": "Invalid message id"
+         }).to_string())
+      )
+   };
+
+   let payload = payload.into_inner();
+   let body = sanitizers::message_sanitizing(payload.message);
+   if body.trim().is_empty() {
+      return Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": "A reply must have a message."
+         }).to_string())
+      );
+   }
+
+   let msg = match store.messages.find(msg_oid).await {
+      Ok(Some(msg)) => msg,
+      Ok(None) => return Custom(
+         HttpStatus::NotFound,
+         RawJson(json!({
+            "error": "Message couldn't be found!"
+         }).to_string())
+      ),
+      Err(e) => {
+         warn!("There was an error while fetching message from the store! Err: {}", e);
+         return Custom(
+            HttpStatus::new(500),
+            RawJson(json!({
+               "error": "There was an error while retrieving the message! Don't worry this is our fault!"
+            }).to_string())
+         );
+      }
+   };
+
+   let subject = match payload.subject {
+      Some(subject) if !subject.trim().is_empty() => sanitizers::message_sanitizing(subject),
+      _ if msg.subject.to_lowercase().starts_with("re:") => msg.subject.clone(),
+      _ => format!("Re: {}", msg.subject)
+   };
+
+   let entry_id = ObjectId::new();
+   let (in_reply_to, references) = reply::threading_headers(mailer, &msg, &msg_oid);
+   let now = DateTime::from(Utc::now());
+   let entry = ThreadEntry {
+      id: entry_id,
+      direction: ThreadDirection::Outbound,
+      from: sender,
+      to: msg.from.clone(),
+      subject,
+      body,
+      message_id: reply::reply_message_id(mailer, &msg_oid, &entry_id),
+      in_reply_to: Some(in_reply_to),
+      references,
+      author: auth.decoded_payload.sub.as_ref().map(|sub| sub.trim_matches('"').to_string()),
+      attachments: Vec::new(),
+      created_at: now,
+      delivery: Some(DeliveryStatus {
+         state: DeliveryState::Pending,
+         attempts: 0,
+         last_error: None,
+         updated_at: now
+      })
+   };
+
+   match store.messages.push_thread_entry(msg_oid, &entry, false).await {
+      Ok(true) => {},
+      Ok(false) => return Custom(
+         HttpStatus::NotFound,
+         RawJson(json!({
+            "error": "Message couldn't be found!"
+         }).to_string())
+      ),
+      Err(err) => {
+         warn!("Failed storing reply for message {}. Error: {}", msg_oid, err);
+         return Custom(
+            HttpStatus::new(500),
+            RawJson(json!({
+               "error": "Internal server error. Don't worry, this is our fault."
+            }).to_string())
+         );
+      }
+   }
+
+   if let Err(err) = queue.enqueue(JobKind::Reply { entry_id }, msg_oid).await {
+      warn!("Failed queueing reply {} for message {}. Error: {}", entry_id, msg_oid, err);
+
+      //* Nothing will ever send it, the thread mustn't show it as pending
+      let failed = DeliveryStatus {
+         state: DeliveryState::Failed,
+         attempts: 0,
+         last_error: Some("Couldn't be queued for delivery".to_string()),
+         updated_at: DateTime::from(Utc::now())
+      };
+      if let Err(err) = store.messages.set_delivery(msg_oid, DeliveryTarget::Reply(entry_id), &failed).await {
+         warn!("Failed marking reply {} of message {} as failed. Error: {}", entry_id, msg_oid, err);
+      }
+
+      return Custom(
+         HttpStatus::new(500),
+         RawJson(json!({
+            "error": "The reply was saved but couldn't be queued for delivery. Don't worry, this is our fault."
+         }).to_string())
+      );
+   }
+
+   Custom(
+      HttpStatus::new(200),
+      RawJson(json!({
+         "success": "Reply queued for delivery!",
+         "reply": thread_entry_json(&entry)
+      }).to_string())
+   )
+}
@@ -1,19 +1,28 @@
 use unicode_segmentation::UnicodeSegmentation;
 use chrono::Utc;
 use regex::Regex;
-use mongodb::bson::{doc, DateTime};
+use mongodb::bson::{oid::ObjectId, DateTime};
 use rocket::{
     response::{content, status},
     http::Status as HttpStatus, 
     State,
-    warn,
-    serde::{Deserialize, json::{Json, serde_json}}
+    warn, info,
+    serde::{Deserialize, json::{Json, serde_json::{self, Value as SerdeVal}}}
 };
 
 use crate::{
-    MessageCmsDb,
-    models::message::Message,
-    security::sanitizers
+    events::EventBus,
+    guards::{ChallengePassed, ServerCapacity},
+    mailer::{Mailer, ack::AckConfig},
+    models::{
+        message::{Message, DeliveryState, DeliveryStatus},
+        job::JobKind,
+        event::EventKind
+    },
+    queue::JobQueue,
+    security::{sanitizers, bot_trap::{BotTrap, BotAction}},
+    spam::SpamFilter,
+    store::Store
 };
 
 #[derive(Deserialize, Debug)]
@@ -21,9 +30,18 @@ pub struct NewMessagePayload {
    pub from: String,
    pub name: String,
    pub subject: String,
-   pub message: String
+   pub message: String,
+   pub locale: Option<String>,
+   //* Honeypot, the form hides this field so only bots fill it
+   #[serde(default)]
+   pub website: Option<String>,
+   //* Issued by `GET /form-token` when the form is loaded
+   #[serde(default)]
+   pub form_token: Option<String>
 }
 
+const LOCALE_RGX: &str = r"^[a-zA-Z]{2,3}([-_][a-zA-Z0-9]{2,8}){0,2}$";
+
 struct ValidError<'c> {
     message: &'c str,
     code: u16
@@ -47,17 +65,21 @@ impl<'c> NewMessagePayload {
             });
         }
 
+        //* Only well formed language tags (e.g.: "en", "pt-BR") are kept, anything else falls back to the default locale
+        let locale = self.locale.filter(|locale| Regex::new(LOCALE_RGX).unwrap().is_match(locale));
+
         Ok(Self {
             from: self.from,
             name,
             subject,
-            message
+            message,
+            locale,
+            website: self.website,
+            form_token: self.form_token
         })
     }
     fn is_valid(&self) -> Result<(), ValidError<'c>> {
-        const EMAIL_RGX: &str = r"^[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]+@[a-zA-Z0-9]+(\.[a-zA-Z0-9-]{0,61})+$";
-
-        if Regex::new(EMAIL_RGX).unwrap().is_match(&self.from) {
+        if Regex::new(sanitizers::EMAIL_RGX).unwrap().is_match(&self.from) {
             Ok(())
         } else {
             Err(ValidError {
@@ -68,13 +90,40 @@ impl<'c> NewMessagePayload {
     }
 }
 
+/// Data of the `message.created` event
+pub fn created_event_data(msg_oid: ObjectId, msg: &Message) -> SerdeVal {
+    serde_json::json!({
+        "id": msg_oid.to_string(),
+        "from": msg.from,
+        "name": msg.name,
+        "subject": msg.subject,
+        "message": msg.message,
+        "locale": msg.locale,
+        "created_at": msg.created_at.map(|date| date.to_chrono().to_rfc3339())
+    })
+}
+
 //TODO Add something to prevent xss and other attacks (e.g.: "sanitize-html")
 //TODO Find a way to prevent sql injection scripts
 //TODO + other sec shit
 
 #[post("/send", format = "application/json", data = "<message>")]
-pub async fn send_message(cms_db: &State<MessageCmsDb>, message: Json<NewMessagePayload>) -> status::Custom<content::RawJson<String>> {
+#[allow(clippy::too_many_arguments)]
+pub async fn send_message(store: &State<Store>, mailer: &State<Mailer>, ack_config: &State<AckConfig>, queue: &State<JobQueue>, events: &State<EventBus>, spam: &State<SpamFilter>, bot_trap: &State<BotTrap>, _challenge: ChallengePassed, capacity: ServerCapacity<'_>, message: Json<NewMessagePayload>) -> status::Custom<content::RawJson<String>> {
     let message = message.into_inner();
+
+    //* Bots are told their message went through, so they don't adapt
+    let bot_signal = bot_trap.inspect(message.website.as_deref(), message.form_token.as_deref());
+    if let Some(signal) = bot_signal {
+        info!("Caught a bot submission ({})", signal.as_str());
+
+        if bot_trap.action == BotAction::Discard {
+            return status::Custom(
+                HttpStatus::new(200), 
+                content::RawJson(String::from("Your message has been sent!")))
+        }
+    }
+
     let validated = message.is_valid();
 
     if validated.is_err() {
@@ -103,26 +152,163 @@ pub async fn send_message(cms_db: &State<MessageCmsDb>, message: Json<NewMessage
     }
     let message = clean_msg.unwrap();
     
-    let msg_doc = Message {
+    let now = DateTime::from(Utc::now());
+    let mut msg_doc = Message {
         id: None,
-        created_at: Some(DateTime::from(Utc::now())),
+        created_at: Some(now),
         from: message.from,
         name: message.name,
         subject: message.subject,
         message: message.message,
+        locale: message.locale,
         read: false,
-        archived: false
+        archived: false,
+        delivery: match mailer.is_enabled() {
+            true => Some(DeliveryStatus {
+                state: DeliveryState::Pending,
+                attempts: 0,
+                last_error: None,
+                updated_at: now
+            }),
+            false => None
+        },
+        ack: None,
+        thread: Vec::new(),
+        deleted_at: None,
+        source_id: None,
+        content_hash: None,
+        spam: None
     };
+    msg_doc.spam = match bot_signal {
+        Some(signal) => Some(spam.quarantine(spam.check(&msg_doc), signal.as_str())),
+        None => spam.check(&msg_doc)
+    };
+
+    //* Quarantined spam is stored for admins to review but notifies no one, the
+    //* sender gets the usual answer so the filter can't be probed
+    let quarantined = msg_doc.spam.as_ref().map_or(false, |spam| spam.quarantined);
+    if quarantined {
+        msg_doc.delivery = None;
+    }
     
-    match cms_db.get_msg_col().insert_one(msg_doc, None).await {
-        Ok(_) => status::Custom(
-            HttpStatus::new(200), 
-            content::RawJson(String::from("Your message has been sent!"))),
+    let inserted = store.messages.insert(&msg_doc).await;
+    //* Only stored submissions keep their slot of the server limit
+    if inserted.is_ok() {
+        capacity.accept();
+    }
+
+    match inserted {
+        Ok(_) if quarantined => {
+            status::Custom(
+                HttpStatus::new(200), 
+                content::RawJson(String::from("Your message has been sent!")))
+        },
+        Ok(msg_oid) => {
+            events.publish(EventKind::MessageCreated, msg_oid, created_event_data(msg_oid, &msg_doc)).await;
+
+            if mailer.is_enabled() {
+                if let Err(err) = queue.enqueue(JobKind::OwnerNotification, msg_oid).await {
+                    warn!("Failed queueing owner notification for message {}: {}", msg_oid, err);
+                }
+
+                //* Throttled per address so the form can't be used to spam third parties
+                if ack_config.enabled && ack_config.allow(&msg_doc.from) {
+                    if let Err(err) = queue.enqueue(JobKind::Acknowledgement, msg_oid).await {
+                        warn!("Failed queueing acknowledgement for message {}: {}", msg_oid, err);
+                    }
+                }
+            }
+
+            status::Custom(
+                HttpStatus::new(200), 
+                content::RawJson(String::from("Your message has been sent!")))
+        },
         Err(err) => {
-            warn!("Failed to insert new message into CMS MSG DB: {}", err);
+            warn!("Failed to store new message: {}", err);
             status::Custom(
                 HttpStatus::new(500), 
                 content::RawJson(String::from("Sorry, something went wrong when sending your message. Please try again.")))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, sync::Arc};
+    use rocket::{
+        async_trait,
+        http::{ContentType, Header, Status},
+        local::asynchronous::Client
+    };
+    use super::*;
+    use crate::{
+        error_catcher,
+        events::Webhooks,
+        queue::QueueConfig,
+        security::challenge::{ChallengeConfig, ChallengeErr, ChallengeVerifier, CHALLENGE_HEADER},
+        store::MessageFilter
+    };
+
+    const PAYLOAD: &str = r#"{"from":"jane@example.com","name":"Jane","subject":"Hi","message":"Hello there!"}"#;
+
+    /// Takes "solved" as the only right answer
+    struct StubVerifier;
+
+    #[async_trait]
+    impl ChallengeVerifier for StubVerifier {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        async fn verify(&self, response: &str, _client_ip: Option<IpAddr>) -> Result<(), ChallengeErr> {
+            match response {
+                "solved" => Ok(()),
+                _ => Err(ChallengeErr::Invalid)
+            }
+        }
+    }
+
+    async fn client(store: Store) -> Client {
+        let queue = JobQueue::new(store.jobs.clone(), QueueConfig::from_env());
+        let events = EventBus::new(store.events.clone(), Webhooks::from_env(), queue.clone());
+
+        let rocket = rocket::build()
+            .manage(store)
+            .manage(Mailer::from_env().unwrap())
+            .manage(AckConfig::from_env())
+            .manage(queue)
+            .manage(events)
+            .manage(SpamFilter::from_env().unwrap())
+            .manage(BotTrap::from_env())
+            .manage(ChallengeConfig { verifier: Some(Arc::new(StubVerifier)) })
+            .register("/", catchers![error_catcher::challenge_required])
+            .mount("/", routes![send_message]);
+
+        Client::tracked(rocket).await.unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn submissions_must_solve_the_challenge() {
+        let store = Store::memory();
+        let client = client(store.clone()).await;
+
+        let missing = client.post("/send").header(ContentType::JSON).body(PAYLOAD).dispatch().await;
+        assert_eq!(missing.status(), Status::new(428));
+
+        let wrong = client.post("/send")
+            .header(ContentType::JSON)
+            .header(Header::new(CHALLENGE_HEADER, "guessed"))
+            .body(PAYLOAD)
+            .dispatch().await;
+        assert_eq!(wrong.status(), Status::new(428));
+        assert_eq!(store.messages.count(&MessageFilter::default()).await.unwrap(), 0);
+
+        let solved = client.post("/send")
+            .header(ContentType::JSON)
+            .header(Header::new(CHALLENGE_HEADER, "solved"))
+            .body(PAYLOAD)
+            .dispatch().await;
+        assert_eq!(solved.status(), Status::Ok);
+        assert_eq!(store.messages.count(&MessageFilter::default()).await.unwrap(), 1);
+    }
+}
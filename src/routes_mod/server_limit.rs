@@ -0,0 +1,98 @@
+use serde_json::json;
+use chrono::Duration;
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::Status as HttpStatus,
+   serde::{Deserialize, json::Json},
+   State
+};
+
+use crate::{
+   auth::{
+      auth0_token_related::PermCheckOpt,
+      auth0_perm_claims::ScopePerm,
+   },
+   guards::{Auth, ServerBreaker},
+   security::{ServerLimitStatus, MAX_SERVER_WINDOW_SECS}
+};
+
+/// Changes to the server limit, what's left out stays as is
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ServerLimitUpdate {
+   pub enabled: Option<bool>,
+   pub limit: Option<u32>,
+   pub window_secs: Option<i64>
+}
+
+pub fn server_limit_json(status: &ServerLimitStatus) -> serde_json::Value {
+   json!({
+      "enabled": status.enabled,
+      "limit": status.limit,
+      "window_secs": status.window.num_seconds(),
+      "count": status.count,
+      "tripped": status.tripped,
+      "retry_after": status.retry_after.num_seconds()
+   })
+}
+
+fn forbidden() -> Custom<RawJson<String>> {
+   Custom(
+      HttpStatus::new(403),
+      RawJson(json!({
+         "error": "Not authorized: insufficient permissions for this token"
+      }).to_string())
+   )
+}
+
+#[get("/limits/server")]
+pub fn get_server_limit(breaker: &State<ServerBreaker>, auth: Auth) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_LIMITS_MANAGE ];
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return forbidden();
+   }
+
+   let status = breaker.lock().status();
+   Custom(
+      HttpStatus::new(200),
+      RawJson(server_limit_json(&status).to_string())
+   )
+}
+
+/// Tunes the global submissions cap without a restart, it's back to the env config on the next one
+#[put("/limits/server", format = "application/json", data = "<update>")]
+pub fn tune_server_limit(breaker: &State<ServerBreaker>, auth: Auth, update: Json<ServerLimitUpdate>) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_LIMITS_MANAGE ];
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return forbidden();
+   }
+
+   let update = update.into_inner();
+   if update.limit == Some(0) || update.window_secs.map_or(false, |secs| secs <= 0) {
+      return Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": "limit and window_secs must be positive, set enabled to false to lift the limit"
+         }).to_string())
+      );
+   }
+   if update.window_secs.map_or(false, |secs| secs > MAX_SERVER_WINDOW_SECS) {
+      return Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": format!("window_secs can't be over {} (a week)", MAX_SERVER_WINDOW_SECS)
+         }).to_string())
+      );
+   }
+
+   let mut limit = breaker.lock();
+   limit.tune(update.enabled, update.limit, update.window_secs.map(Duration::seconds));
+   let status = limit.status();
+   drop(limit);
+
+   info!("Server limit tuned: {:?}", status);
+   Custom(
+      HttpStatus::new(200),
+      RawJson(server_limit_json(&status).to_string())
+   )
+}
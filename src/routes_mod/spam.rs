@@ -0,0 +1,135 @@
+use serde_json::json;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::Status as HttpStatus,
+   State
+};
+
+use crate::{
+   auth::{
+      auth0_token_related::PermCheckOpt,
+      auth0_perm_claims::ScopePerm,
+   },
+   events::EventBus,
+   guards::Auth,
+   mailer::Mailer,
+   models::{
+      message::{SpamInfo, DeliveryState, DeliveryStatus},
+      job::JobKind,
+      event::EventKind
+   },
+   queue::JobQueue,
+   spam::SpamFilter,
+   store::{Store, StoreErr, DeliveryTarget}
+};
+use super::{del_msg::Ids, send_msg::created_event_data};
+
+/// What happened with each message an admin labeled
+struct Labeled {
+   labeled: Vec<ObjectId>,
+   missing: Vec<ObjectId>
+}
+
+/// Labels the messages, teaching the classifier and moving them in or out of the quarantine.
+/// Released messages go through what a regular submission does: the created event and the
+/// owners notification
+async fn label_msgs(store: &Store, filter: &SpamFilter, mailer: &Mailer, queue: &JobQueue, events: &EventBus, oids: &[ObjectId], spam: bool) -> Result<Labeled, StoreErr> {
+   let mut res = Labeled { labeled: Vec::new(), missing: Vec::new() };
+
+   for msg_oid in oids.iter().copied() {
+      let msg = match store.messages.find(msg_oid).await? {
+         Some(msg) => msg,
+         None => {
+            res.missing.push(msg_oid);
+            continue;
+         }
+      };
+      let was_quarantined = msg.spam.as_ref().map_or(false, |info| info.quarantined);
+
+      let info = SpamInfo {
+         score: msg.spam.as_ref().map_or(0.0, |info| info.score),
+         hits: msg.spam.as_ref().map(|info| info.hits.clone()).unwrap_or_default(),
+         quarantined: spam,
+         label: Some(spam)
+      };
+      if !store.messages.set_spam(msg_oid, &info).await? {
+         res.missing.push(msg_oid);
+         continue;
+      }
+      //* Only learned once the label is stored, so the classifier matches what's in the store
+      filter.learn(&msg, spam);
+      res.labeled.push(msg_oid);
+
+      if was_quarantined && !spam && msg.deleted_at.is_none() {
+         events.publish(EventKind::MessageCreated, msg_oid, created_event_data(msg_oid, &msg)).await;
+
+         if mailer.is_enabled() && msg.delivery.is_none() {
+            let pending = DeliveryStatus {
+               state: DeliveryState::Pending,
+               attempts: 0,
+               last_error: None,
+               updated_at: DateTime::now()
+            };
+            store.messages.set_delivery(msg_oid, DeliveryTarget::Owners, &pending).await?;
+
+            if let Err(err) = queue.enqueue(JobKind::OwnerNotification, msg_oid).await {
+               warn!("Failed queueing owner notification for released message {}: {}", msg_oid, err);
+            }
+         }
+      }
+   }
+
+   Ok(res)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn label_route(store: &Store, filter: &SpamFilter, mailer: &Mailer, queue: &JobQueue, events: &EventBus, auth: Auth, ids: Ids, spam: bool) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_SPAM_MANAGE ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return Custom(
+         HttpStatus::new(403),
+         RawJson(json!({
+            "error": "Not authorized: insufficient permissions for this token"
+         }).to_string())
+      );
+   }
+
+   let oids = match ids.oids() {
+      Ok(oids) => oids,
+      Err(err) => return err
+   };
+
+   match label_msgs(store, filter, mailer, queue, events, &oids, spam).await {
+      Ok(Labeled { labeled, missing }) => Custom(
+         HttpStatus::new(if missing.is_empty() { 200 } else { 412 }),
+         RawJson(json!({
+            "labeled": labeled.iter().map(|id| id.to_string()).collect::<Vec<String>>(),
+            "missing": missing.iter().map(|id| id.to_string()).collect::<Vec<String>>()
+         }).to_string())
+      ),
+      Err(err) => {
+         warn!("Error labeling messages as spam: {}", err);
+
+         Custom(
+            HttpStatus::new(500),
+            RawJson(json!({
+               "error": "Internal server error. Don't worry, this is our fault."
+            }).to_string())
+         )
+      }
+   }
+}
+
+/// Quarantines the messages as spam and trains the classifier with them
+#[post("/spam/mark/<ids>")]
+pub async fn mark_spam(store: &State<Store>, filter: &State<SpamFilter>, mailer: &State<Mailer>, queue: &State<JobQueue>, events: &State<EventBus>, auth: Auth, ids: Ids) -> Custom<RawJson<String>> {
+   label_route(store, filter, mailer, queue, events, auth, ids, true).await
+}
+
+/// Releases the messages from the quarantine and trains the classifier with them as legit
+#[post("/spam/unmark/<ids>")]
+pub async fn unmark_spam(store: &State<Store>, filter: &State<SpamFilter>, mailer: &State<Mailer>, queue: &State<JobQueue>, events: &State<EventBus>, auth: Auth, ids: Ids) -> Custom<RawJson<String>> {
+   label_route(store, filter, mailer, queue, events, auth, ids, false).await
+}
@@ -0,0 +1,121 @@
+use std::str::FromStr;
+
+use serde_json::json;
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::Status as HttpStatus,
+   State
+};
+use mongodb::bson::oid::ObjectId;
+
+use crate::{
+   guards::Auth,
+   auth::{
+      auth0_token_related::PermCheckOpt,
+      auth0_perm_claims::ScopePerm,
+   },
+   mailer::templates::{MailTemplates, TemplateKind, TemplateVars},
+   models::message::ThreadDirection,
+   store::Store
+};
+
+/// Renders an email template, either with sample data or with a stored message
+#[get("/templates/<name>/preview?<locale>&<id>")]
+pub async fn preview_template(store: &State<Store>, templates: &State<MailTemplates>, auth: Auth,
+   name: String, locale: Option<String>, id: Option<String>
+) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_MSGS_READ ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return Custom(
+         HttpStatus::new(403),
+         RawJson(json!({
+            "error": "Not authorized: insufficient permissions for this token"
+         }).to_string())
+      );
+   }
+
+   let kind = match TemplateKind::from_name(&name) {
+      Some(kind) => kind,
+      None => return Custom(
+         HttpStatus::NotFound,
+         RawJson(json!({
+            "error": format!("Unknown template \"{}\"", name),
+            "templates": TemplateKind::ALL.iter().map(|kind| kind.as_str()).collect::<Vec<&str>>()
+         }).to_string())
+      )
+   };
+
+   let (vars, msg_locale) = match id {
+      Some(id) => {
+         let msg_oid = match ObjectId::from_str(&id) {
+            Ok(oid) => oid,
+            Err(_) => return Custom(
+               HttpStatus::new(400),
+               RawJson(json!({
+                  "error": "Invalid message id"
+               }).to_string())
+            )
+         };
+
+         let msg = match store.messages.find(msg_oid).await {
+            Ok(Some(msg)) => msg,
+            Ok(None) => return Custom(
+               HttpStatus::NotFound,
+               RawJson(json!({
+                  "error": "Message couldn't be found!"
+               }).to_string())
+            ),
+            Err(e) => {
+               warn!("There was an error while fetching message from the store! Err: {}", e);
+               return Custom(
+                  HttpStatus::new(500),
+                  RawJson(json!({
+                     "error": "There was an error while retrieving the message! Don't worry this is our fault!"
+                  }).to_string())
+               );
+            }
+         };
+
+         //* Replies preview the latest one sent, or a placeholder when none was
+         let last_reply = msg.thread.iter().rev().find(|entry| entry.direction == ThreadDirection::Outbound);
+         let vars = match last_reply {
+            Some(entry) => TemplateVars::for_reply(&msg, entry),
+            None => TemplateVars { reply: TemplateVars::sample().reply, ..TemplateVars::for_message(&msg) }
+         };
+
+         (vars, msg.locale.clone())
+      },
+      None => (TemplateVars::sample(), None)
+   };
+
+   //* Owners get notifications in their own locale, senders in theirs
+   let locale = match (locale, kind) {
+      (Some(locale), _) => Some(locale),
+      (None, TemplateKind::OwnerNotification) => Some(templates.owner_locale().to_string()),
+      (None, _) => msg_locale
+   };
+
+   match templates.render(kind, locale.as_deref(), &vars) {
+      Ok(rendered) => Custom(
+         HttpStatus::new(200),
+         RawJson(json!({
+            "template": kind.as_str(),
+            "locale": rendered.locale,
+            "available_locales": templates.locales(kind),
+            "subject": rendered.subject,
+            "text": rendered.text,
+            "html": rendered.html
+         }).to_string())
+      ),
+      Err(err) => {
+         warn!("Failed rendering template preview. Error: {}", err);
+         Custom(
+            HttpStatus::new(500),
+            RawJson(json!({
+               "error": err.to_string()
+            }).to_string())
+         )
+      }
+   }
+}
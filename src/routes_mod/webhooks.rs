@@ -0,0 +1,94 @@
+use serde_json::{json, Value as SerdeVal};
+use rocket::{
+   response::{status::Custom, content::RawJson},
+   http::Status as HttpStatus,
+   State
+};
+
+use crate::{
+   auth::{
+      auth0_token_related::PermCheckOpt,
+      auth0_perm_claims::ScopePerm,
+   },
+   events::EventBus,
+   guards::Auth,
+   models::job::JobState,
+   queue::JobQueue
+};
+use super::delivery_jobs::{job_json, forbidden};
+
+const DEFAULT_LOG_LIMIT: i64 = 50;
+const MAX_LOG_LIMIT: i64 = 200;
+
+/// Configured endpoints, their secrets are never exposed
+#[get("/webhooks")]
+pub async fn list_webhooks(events: &State<EventBus>, auth: Auth) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_DELIVERY_MANAGE ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return forbidden();
+   }
+
+   let endpoints = events.webhooks().endpoints().iter()
+      .map(|endpoint| json!({
+         "name": endpoint.name,
+         "url": endpoint.url.as_str(),
+         "events": match &endpoint.events {
+            Some(events) => events.iter().map(|kind| kind.as_str()).collect::<Vec<&str>>(),
+            None => vec![ "*" ]
+         }
+      }))
+      .collect::<Vec<SerdeVal>>();
+
+   Custom(
+      HttpStatus::new(200),
+      RawJson(json!({
+         "webhooks": endpoints
+      }).to_string())
+   )
+}
+
+/// Webhook delivery log, latest first
+#[get("/webhooks/deliveries?<endpoint>&<state>&<limit>")]
+pub async fn list_webhook_deliveries(queue: &State<JobQueue>, auth: Auth,
+   endpoint: Option<String>, state: Option<String>, limit: Option<i64>
+) -> Custom<RawJson<String>> {
+   let req_perms = vec![ ScopePerm::MAILER_WEBP_DELIVERY_MANAGE ];
+
+   if !auth.decoded_payload.check_perm(Some(PermCheckOpt::All(req_perms)), false, true) {
+      return forbidden();
+   }
+
+   let state = match state.as_deref() {
+      None => None,
+      Some("queued") => Some(JobState::Queued),
+      Some("leased") => Some(JobState::Leased),
+      Some("done") => Some(JobState::Done),
+      Some("dead") => Some(JobState::Dead),
+      Some(_) => return Custom(
+         HttpStatus::new(400),
+         RawJson(json!({
+            "error": "Invalid request. State must be: 'queued', 'leased', 'done' or 'dead'."
+         }).to_string())
+      )
+   };
+   let limit = limit.unwrap_or(DEFAULT_LOG_LIMIT).clamp(1, MAX_LOG_LIMIT);
+
+   match queue.webhook_deliveries(endpoint.as_deref(), state, limit).await {
+      Ok(jobs) => Custom(
+         HttpStatus::new(200),
+         RawJson(json!({
+            "deliveries": jobs.iter().map(job_json).collect::<Vec<SerdeVal>>()
+         }).to_string())
+      ),
+      Err(err) => {
+         warn!("Failed listing webhook deliveries. Error: {:?}", err);
+         Custom(
+            HttpStatus::new(500),
+            RawJson(json!({
+               "error": "Failed retrieving webhook deliveries. Don't worry this is a fault on our side!"
+            }).to_string())
+         )
+      }
+   }
+}
@@ -0,0 +1,198 @@
+use std::{collections::HashMap, env, sync::Mutex};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+
+use super::signing::{hmac_sha256_hex, verify_hmac_sha256_hex, to_hex};
+
+/// Why a submission looks like it came from a bot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BotSignal {
+   //* The hidden field humans never see was filled
+   Honeypot,
+   //* Sent sooner after the form was loaded than anyone could type it
+   TooFast,
+   //* Missing, forged, stale or already used form token
+   FormToken
+}
+
+impl BotSignal {
+   pub fn as_str(&self) -> &'static str {
+      match self {
+         BotSignal::Honeypot => "honeypot",
+         BotSignal::TooFast => "too_fast",
+         BotSignal::FormToken => "form_token"
+      }
+   }
+}
+
+/// What happens to submissions caught by the trap, the sender is told it was sent either way
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BotAction {
+   Discard,
+   Quarantine
+}
+
+struct FormTokens {
+   secret: Vec<u8>,
+   min_age: Duration,
+   max_age: Duration,
+   //* Nonces of the tokens already used, until they go stale
+   spent: Mutex<HashMap<String, i64>>
+}
+
+/// Cheap bot checks run before spam scoring: a honeypot field and, when
+/// `FORM_TOKEN_SECRET` is set, a signed token telling when the form was loaded
+pub struct BotTrap {
+   pub action: BotAction,
+   tokens: Option<FormTokens>
+}
+
+fn signed_part(issued_at: i64, nonce: &str) -> String {
+   format!("form:{}:{}", issued_at, nonce)
+}
+
+impl BotTrap {
+   pub fn from_env() -> Self {
+      let action = match env::var("BOT_TRAP_ACTION").as_deref() {
+         Ok("discard") | Err(_) => BotAction::Discard,
+         Ok("quarantine") => BotAction::Quarantine,
+         Ok(other) => panic!("BOT_TRAP_ACTION must be \"discard\" or \"quarantine\", got \"{}\"", other)
+      };
+
+      let tokens = match env::var("FORM_TOKEN_SECRET") {
+         Ok(secret) if !secret.is_empty() => {
+            let min_age_secs = match env::var("FORM_MIN_FILL_SECS") {
+               Ok(val) => match val.parse::<i64>() {
+                  Ok(secs) if secs >= 0 => secs,
+                  _ => panic!("FORM_MIN_FILL_SECS must be zero or a positive amount of seconds")
+               },
+               Err(_) => 3
+            };
+            let max_age_secs = match env::var("FORM_TOKEN_MAX_AGE_SECS") {
+               Ok(val) => match val.parse::<i64>() {
+                  Ok(secs) if secs > min_age_secs => secs,
+                  _ => panic!("FORM_TOKEN_MAX_AGE_SECS must be more seconds than FORM_MIN_FILL_SECS")
+               },
+               Err(_) => 24 * 60 * 60
+            };
+
+            Some(FormTokens {
+               secret: secret.into_bytes(),
+               min_age: Duration::seconds(min_age_secs),
+               max_age: Duration::seconds(max_age_secs),
+               spent: Mutex::new(HashMap::new())
+            })
+         },
+         _ => None
+      };
+
+      BotTrap { action, tokens }
+   }
+
+   /// `<issued at, unix millis>.<nonce>.<signature>`, none when form tokens are off
+   pub fn issue_token(&self) -> Option<String> {
+      let tokens = self.tokens.as_ref()?;
+      let issued_at = Utc::now().timestamp_millis();
+      let mut nonce = [0u8; 16];
+      rand::thread_rng().fill_bytes(&mut nonce);
+      let nonce = to_hex(&nonce);
+
+      let signature = hmac_sha256_hex(&tokens.secret, signed_part(issued_at, &nonce).as_bytes());
+      Some(format!("{}.{}.{}", issued_at, nonce, signature))
+   }
+
+   pub fn inspect(&self, honeypot: Option<&str>, form_token: Option<&str>) -> Option<BotSignal> {
+      if honeypot.map_or(false, |value| !value.trim().is_empty()) {
+         return Some(BotSignal::Honeypot);
+      }
+
+      let tokens = self.tokens.as_ref()?;
+      let parts = form_token.map(|token| token.trim().split('.').collect::<Vec<&str>>()).unwrap_or_default();
+      let (issued_at, nonce, signature) = match parts.as_slice() {
+         [ issued_at, nonce, signature ] => match issued_at.parse::<i64>() {
+            Ok(issued_at) => (issued_at, *nonce, *signature),
+            Err(_) => return Some(BotSignal::FormToken)
+         },
+         _ => return Some(BotSignal::FormToken)
+      };
+      if !verify_hmac_sha256_hex(&tokens.secret, signed_part(issued_at, nonce).as_bytes(), signature) {
+         return Some(BotSignal::FormToken);
+      }
+
+      let now = Utc::now().timestamp_millis();
+      let age = Duration::milliseconds(now - issued_at);
+      if age < tokens.min_age {
+         return Some(BotSignal::TooFast);
+      }
+      if age > tokens.max_age {
+         return Some(BotSignal::FormToken);
+      }
+
+      //* A token is good for a single submission, replaying it is as bad as forging one
+      let mut spent = match tokens.spent.lock() {
+         Ok(spent) => spent,
+         Err(poisoned) => poisoned.into_inner()
+      };
+      spent.retain(|_, stale_at| *stale_at >= now);
+      spent.insert(nonce.to_string(), issued_at + tokens.max_age.num_milliseconds())
+         .map(|_| BotSignal::FormToken)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const SECRET: &[u8] = b"form-token-secret";
+
+   fn trap(min_age: Duration, max_age: Duration) -> BotTrap {
+      BotTrap {
+         action: BotAction::Discard,
+         tokens: Some(FormTokens { secret: SECRET.to_vec(), min_age, max_age, spent: Mutex::new(HashMap::new()) })
+      }
+   }
+
+   fn signed_token(issued_at: i64, nonce: &str) -> String {
+      format!("{}.{}.{}", issued_at, nonce, hmac_sha256_hex(SECRET, signed_part(issued_at, nonce).as_bytes()))
+   }
+
+   #[test]
+   fn filled_honeypot_is_a_bot() {
+      let no_tokens = BotTrap { action: BotAction::Discard, tokens: None };
+
+      assert_eq!(no_tokens.inspect(Some("https://spam.example"), None), Some(BotSignal::Honeypot));
+      assert_eq!(no_tokens.inspect(Some("  "), None), None);
+      assert_eq!(no_tokens.inspect(None, None), None);
+   }
+
+   #[test]
+   fn valid_token_passes_once() {
+      let trap = trap(Duration::zero(), Duration::hours(1));
+      let token = trap.issue_token().unwrap();
+
+      assert_eq!(trap.inspect(Some(""), Some(&token)), None);
+      assert_eq!(trap.inspect(Some(""), Some(&token)), Some(BotSignal::FormToken));
+   }
+
+   #[test]
+   fn missing_or_forged_tokens_are_caught() {
+      let trap = trap(Duration::zero(), Duration::hours(1));
+      let now = Utc::now().timestamp_millis();
+      let forged = format!("{}.{}", now, signed_token(now, "abcd").rsplit('.').next().unwrap());
+
+      assert_eq!(trap.inspect(None, None), Some(BotSignal::FormToken));
+      assert_eq!(trap.inspect(None, Some("not-a-token")), Some(BotSignal::FormToken));
+      assert_eq!(trap.inspect(None, Some(&forged)), Some(BotSignal::FormToken));
+      assert_eq!(trap.inspect(None, Some(&signed_token(now, "abcd").replace("abcd", "abce"))), Some(BotSignal::FormToken));
+   }
+
+   #[test]
+   fn token_age_is_checked() {
+      let trap = trap(Duration::seconds(3), Duration::hours(1));
+      let now = Utc::now().timestamp_millis();
+
+      assert_eq!(trap.inspect(None, Some(&signed_token(now, "fresh"))), Some(BotSignal::TooFast));
+      assert_eq!(trap.inspect(None, Some(&signed_token(now - 2 * 60 * 60 * 1000, "stale"))), Some(BotSignal::FormToken));
+      assert_eq!(trap.inspect(None, Some(&signed_token(now - 60 * 1000, "filled"))), None);
+   }
+}
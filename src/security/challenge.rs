@@ -0,0 +1,301 @@
+use std::{
+   collections::HashMap,
+   env, fmt,
+   net::IpAddr,
+   sync::{Arc, Mutex},
+   time::Duration as StdDuration
+};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use reqwest::Client;
+use rocket::async_trait;
+use serde_json::Value as SerdeVal;
+use sha2::{Digest, Sha256};
+
+use super::signing::{hmac_sha256_hex, verify_hmac_sha256_hex, to_hex};
+
+/// Header carrying the solved challenge, or the CAPTCHA provider's response token
+pub const CHALLENGE_HEADER: &str = "X-Challenge-Response";
+
+#[derive(Debug, Clone)]
+pub enum ChallengeErr {
+   Missing,
+   Invalid,
+   Expired,
+   //* Proof-of-work challenges can only be redeemed once
+   Replayed,
+   Provider(String)
+}
+
+impl fmt::Display for ChallengeErr {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+         ChallengeErr::Missing => write!(f, "No challenge response was given"),
+         ChallengeErr::Invalid => write!(f, "The challenge response is invalid"),
+         ChallengeErr::Expired => write!(f, "The challenge expired, fetch a new one"),
+         ChallengeErr::Replayed => write!(f, "The challenge was already used, fetch a new one"),
+         ChallengeErr::Provider(msg) => write!(f, "The challenge could not be verified: {}", msg)
+      }
+   }
+}
+
+/// A challenge issued by the service itself, for the client to solve
+pub struct IssuedChallenge {
+   pub token: String,
+   pub difficulty: u8,
+   pub expires_at: i64
+}
+
+/// Verifies the challenge response of a submission. Implemented by the built-in
+/// proof-of-work and by hosted CAPTCHA providers, or by a stub in tests
+#[async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+   fn name(&self) -> &'static str;
+   /// A new challenge, only for verifiers that issue their own
+   fn issue(&self) -> Option<IssuedChallenge> {
+      None
+   }
+   async fn verify(&self, response: &str, client_ip: Option<IpAddr>) -> Result<(), ChallengeErr>;
+}
+
+/// Hashcash-like challenge: the client looks for a counter such that the SHA-256
+/// of `<token>:<counter>` starts with `difficulty` zero bits. Tokens are signed,
+/// so nothing is stored until they are redeemed
+pub struct ProofOfWork {
+   secret: Vec<u8>,
+   difficulty: u8,
+   ttl: Duration,
+   //* Redeemed nonces until they expire
+   spent: Mutex<HashMap<String, i64>>
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+   let mut bits = 0;
+   for byte in hash {
+      bits += byte.leading_zeros();
+      if *byte != 0 {
+         break;
+      }
+   }
+
+   bits
+}
+
+impl ProofOfWork {
+   pub fn new(secret: Vec<u8>, difficulty: u8, ttl: Duration) -> Self {
+      ProofOfWork { secret, difficulty, ttl, spent: Mutex::new(HashMap::new()) }
+   }
+}
+
+#[async_trait]
+impl ChallengeVerifier for ProofOfWork {
+   fn name(&self) -> &'static str {
+      "pow"
+   }
+
+   fn issue(&self) -> Option<IssuedChallenge> {
+      let mut nonce = [0u8; 16];
+      rand::thread_rng().fill_bytes(&mut nonce);
+      let expires_at = (Utc::now() + self.ttl).timestamp();
+
+      let payload = format!("{}.{}.{}", to_hex(&nonce), expires_at, self.difficulty);
+      let signature = hmac_sha256_hex(&self.secret, payload.as_bytes());
+
+      Some(IssuedChallenge {
+         token: format!("{}.{}", payload, signature),
+         difficulty: self.difficulty,
+         expires_at
+      })
+   }
+
+   async fn verify(&self, response: &str, _client_ip: Option<IpAddr>) -> Result<(), ChallengeErr> {
+      let (token, counter) = response.trim().rsplit_once(':').ok_or(ChallengeErr::Invalid)?;
+      counter.parse::<u64>().map_err(|_| ChallengeErr::Invalid)?;
+
+      let (payload, signature) = token.rsplit_once('.').ok_or(ChallengeErr::Invalid)?;
+      if !verify_hmac_sha256_hex(&self.secret, payload.as_bytes(), signature) {
+         return Err(ChallengeErr::Invalid);
+      }
+      let parts = payload.split('.').collect::<Vec<&str>>();
+      let (nonce, expires_at, difficulty) = match parts.as_slice() {
+         [ nonce, expires_at, difficulty ] => (
+            nonce.to_string(),
+            expires_at.parse::<i64>().map_err(|_| ChallengeErr::Invalid)?,
+            difficulty.parse::<u32>().map_err(|_| ChallengeErr::Invalid)?
+         ),
+         _ => return Err(ChallengeErr::Invalid)
+      };
+
+      let now = Utc::now().timestamp();
+      if expires_at < now {
+         return Err(ChallengeErr::Expired);
+      }
+      if leading_zero_bits(&Sha256::digest(response.trim().as_bytes())) < difficulty {
+         return Err(ChallengeErr::Invalid);
+      }
+
+      let mut spent = match self.spent.lock() {
+         Ok(spent) => spent,
+         Err(poisoned) => poisoned.into_inner()
+      };
+      spent.retain(|_, expires_at| *expires_at >= now);
+      match spent.insert(nonce, expires_at) {
+         Some(_) => Err(ChallengeErr::Replayed),
+         None => Ok(())
+      }
+   }
+}
+
+/// Hosted CAPTCHA checked through a `siteverify` API, which hCaptcha, Turnstile
+/// and reCAPTCHA all share
+pub struct SiteVerify {
+   name: &'static str,
+   url: &'static str,
+   secret: String,
+   client: Client
+}
+
+impl SiteVerify {
+   pub fn new(provider: &str, secret: String) -> Option<Self> {
+      let (name, url) = match provider {
+         "hcaptcha" => ("hcaptcha", "https://hcaptcha.com/siteverify"),
+         "turnstile" => ("turnstile", "https://challenges.cloudflare.com/turnstile/v0/siteverify"),
+         "recaptcha" => ("recaptcha", "https://www.google.com/recaptcha/api/siteverify"),
+         _ => return None
+      };
+      let client = Client::builder()
+         .timeout(StdDuration::from_secs(10))
+         .build()
+         .expect("Failed building the CAPTCHA HTTP client");
+
+      Some(SiteVerify { name, url, secret, client })
+   }
+}
+
+#[async_trait]
+impl ChallengeVerifier for SiteVerify {
+   fn name(&self) -> &'static str {
+      self.name
+   }
+
+   async fn verify(&self, response: &str, client_ip: Option<IpAddr>) -> Result<(), ChallengeErr> {
+      let mut form = vec![ ("secret", self.secret.clone()), ("response", response.trim().to_string()) ];
+      if let Some(ip) = client_ip {
+         form.push(("remoteip", ip.to_string()));
+      }
+
+      let res = self.client.post(self.url).form(&form).send().await
+         .map_err(|err| ChallengeErr::Provider(err.to_string()))?
+         .json::<SerdeVal>().await
+         .map_err(|err| ChallengeErr::Provider(err.to_string()))?;
+
+      match res["success"].as_bool() {
+         Some(true) => Ok(()),
+         _ => Err(ChallengeErr::Invalid)
+      }
+   }
+}
+
+/// The verifier submissions go through, none when challenges are off
+#[derive(Clone, Default)]
+pub struct ChallengeConfig {
+   pub verifier: Option<Arc<dyn ChallengeVerifier>>
+}
+
+impl ChallengeConfig {
+   pub fn from_env() -> Result<Self, String> {
+      let kind = env::var("CHALLENGE").unwrap_or_default().trim().to_lowercase();
+      if kind.is_empty() || kind == "off" {
+         return Ok(ChallengeConfig::default());
+      }
+
+      let secret = match env::var("CHALLENGE_SECRET") {
+         Ok(val) if !val.is_empty() => val,
+         _ => return Err(format!("CHALLENGE_SECRET must be set when CHALLENGE is \"{}\"", kind))
+      };
+
+      let verifier: Arc<dyn ChallengeVerifier> = match kind.as_str() {
+         "pow" => {
+            let difficulty = match env::var("CHALLENGE_POW_DIFFICULTY") {
+               Ok(val) => match val.parse::<u8>() {
+                  Ok(bits) if (1..=32).contains(&bits) => bits,
+                  _ => panic!("CHALLENGE_POW_DIFFICULTY must be between 1 and 32 bits")
+               },
+               Err(_) => 18
+            };
+            let ttl_secs = match env::var("CHALLENGE_TTL_SECS") {
+               Ok(val) => match val.parse::<i64>() {
+                  Ok(secs) if secs > 0 => secs,
+                  _ => panic!("CHALLENGE_TTL_SECS must be a positive amount of seconds")
+               },
+               Err(_) => 5 * 60
+            };
+
+            Arc::new(ProofOfWork::new(secret.into_bytes(), difficulty, Duration::seconds(ttl_secs)))
+         },
+         provider => match SiteVerify::new(provider, secret) {
+            Some(verifier) => Arc::new(verifier),
+            None => return Err(format!("CHALLENGE must be \"off\", \"pow\", \"hcaptcha\", \"turnstile\" or \"recaptcha\", got \"{}\"", provider))
+         }
+      };
+
+      Ok(ChallengeConfig { verifier: Some(verifier) })
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn pow(ttl: Duration) -> ProofOfWork {
+      ProofOfWork::new(b"challenge-secret".to_vec(), 8, ttl)
+   }
+
+   //* Counters are tried in order, so the first one meeting `solved` is found quickly
+   fn response(token: &str, solved: bool) -> String {
+      (0u64..)
+         .map(|counter| format!("{}:{}", token, counter))
+         .find(|response| (leading_zero_bits(&Sha256::digest(response.as_bytes())) >= 8) == solved)
+         .unwrap()
+   }
+
+   #[test]
+   fn leading_zero_bits_are_counted_across_bytes() {
+      assert_eq!(leading_zero_bits(&[0x00, 0x0f, 0xff]), 12);
+      assert_eq!(leading_zero_bits(&[0x80]), 0);
+      assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+   }
+
+   #[rocket::async_test]
+   async fn solved_challenge_is_redeemed_once() {
+      let verifier = pow(Duration::minutes(5));
+      let issued = verifier.issue().unwrap();
+      assert_eq!(issued.difficulty, 8);
+
+      let solved = response(&issued.token, true);
+      assert!(verifier.verify(&solved, None).await.is_ok());
+      assert!(matches!(verifier.verify(&solved, None).await, Err(ChallengeErr::Replayed)));
+   }
+
+   #[rocket::async_test]
+   async fn unsolved_or_tampered_challenges_are_invalid() {
+      let verifier = pow(Duration::minutes(5));
+      let token = verifier.issue().unwrap().token;
+
+      assert!(matches!(verifier.verify(&response(&token, false), None).await, Err(ChallengeErr::Invalid)));
+      assert!(matches!(verifier.verify(&token, None).await, Err(ChallengeErr::Invalid)));
+
+      //* Lowering the difficulty breaks the signature
+      let (payload, signature) = token.rsplit_once('.').unwrap();
+      let easier = format!("{}1.{}", payload.trim_end_matches(char::is_numeric), signature);
+      assert!(matches!(verifier.verify(&response(&easier, true), None).await, Err(ChallengeErr::Invalid)));
+   }
+
+   #[rocket::async_test]
+   async fn expired_challenge_is_refused() {
+      let verifier = pow(Duration::seconds(-1));
+      let token = verifier.issue().unwrap().token;
+
+      assert!(matches!(verifier.verify(&response(&token, true), None).await, Err(ChallengeErr::Expired)));
+   }
+}
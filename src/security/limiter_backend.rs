@@ -0,0 +1,114 @@
+use std::{env, time::Duration as StdDuration};
+use chrono::Utc;
+use mongodb::{
+   bson::{doc, DateTime as BsonDateTime},
+   options::{FindOneAndUpdateOptions, ReturnDocument},
+   Collection
+};
+use rocket::async_trait;
+
+use crate::{models::rate_counter::RateCounter, mongo::MessageCmsDb};
+use super::rate_limit::{RateDecision, RateLimitState, RateType};
+
+/// Where a rate limit policy keeps its counters. Every backend counts with the same
+/// sliding window, through `RateType::decide`
+#[async_trait]
+pub trait LimiterBackend: Send + Sync {
+   /// Counts a request of the client, unless it's over the limit
+   async fn hit(&self, key: String) -> Result<RateDecision, String>;
+   /// Drops idle clients every `every`, for backends that don't expire them on their own
+   fn spawn_evictor(&self, _every: StdDuration) {}
+}
+
+#[async_trait]
+impl LimiterBackend for RateLimitState<String> {
+   async fn hit(&self, key: String) -> Result<RateDecision, String> {
+      Ok(RateLimitState::hit(self, key))
+   }
+
+   fn spawn_evictor(&self, every: StdDuration) {
+      RateLimitState::spawn_evictor(self, every)
+   }
+}
+
+/// Counters in MongoDB, shared by every replica so limits hold across the fleet
+pub struct MongoLimiter {
+   col: Collection<RateCounter>,
+   policy: String,
+   rate: RateType
+}
+
+impl MongoLimiter {
+   fn counter_id(&self, key: &str, index: i64) -> String {
+      format!("{}|{}|{}", self.policy, key, index)
+   }
+}
+
+fn to_count(count: i64) -> u32 {
+   count.clamp(0, u32::MAX as i64) as u32
+}
+
+#[async_trait]
+impl LimiterBackend for MongoLimiter {
+   async fn hit(&self, key: String) -> Result<RateDecision, String> {
+      let now = Utc::now().timestamp_millis();
+      let index = self.rate.window_index(now);
+      let current_id = self.counter_id(&key, index);
+
+      let previous = self.col.find_one(doc! { "_id": self.counter_id(&key, index - 1) }, None).await
+         .map_err(|err| err.to_string())?
+         .map_or(0, |counter| counter.count);
+
+      //* Counted right away so concurrent requests, whichever replica gets them, each see their own count
+      let opts = FindOneAndUpdateOptions::builder()
+         .upsert(true)
+         .return_document(ReturnDocument::After)
+         .build();
+      let expires_at = BsonDateTime::from_millis((index + 2) * self.rate.window_millis());
+      let current = self.col.find_one_and_update(
+         doc! { "_id": current_id.as_str() },
+         doc! { "$inc": { "count": 1_i64 }, "$setOnInsert": { "expiresAt": expires_at } },
+         opts
+      ).await
+         .map_err(|err| err.to_string())?
+         .map_or(1, |counter| counter.count);
+
+      let decision = self.rate.decide(to_count(previous), to_count(current - 1), now);
+      if !decision.allowed {
+         //* Like in memory, limited requests don't count
+         self.col.update_one(doc! { "_id": current_id.as_str() }, doc! { "$inc": { "count": -1_i64 } }, None).await
+            .map_err(|err| err.to_string())?;
+      }
+
+      Ok(decision)
+   }
+}
+
+/// Backend the rate limit policies keep their counters in, set by `RATE_LIMIT_BACKEND`
+#[derive(Clone)]
+pub enum LimiterStore {
+   //* Each replica counts on its own
+   Memory,
+   Mongo(Collection<RateCounter>)
+}
+
+impl LimiterStore {
+   pub async fn from_env() -> Result<Self, String> {
+      match env::var("RATE_LIMIT_BACKEND").as_deref() {
+         Ok("memory") | Err(_) => Ok(LimiterStore::Memory),
+         Ok("mongo") => Ok(LimiterStore::Mongo(MessageCmsDb::init_rate_limits().await)),
+         Ok(other) => Err(format!("RATE_LIMIT_BACKEND must be \"memory\" or \"mongo\", got \"{}\"", other))
+      }
+   }
+
+   pub fn backend(&self, policy: &str, rate: RateType) -> Box<dyn LimiterBackend> {
+      match self {
+         LimiterStore::Memory => Box::new(RateLimitState::<String>::new(rate)),
+         LimiterStore::Mongo(col) => Box::new(MongoLimiter {
+            col: col.clone(),
+            policy: policy.to_string(),
+            rate
+         })
+      }
+   }
+}
@@ -1,6 +1,13 @@
 mod rate_limit;
+mod rate_policy;
+mod limiter_backend;
 mod sec_headers;
 pub mod sanitizers;
+pub mod challenge;
+pub mod bot_trap;
+pub mod signing;
 
 pub use rate_limit::*;
+pub use rate_policy::*;
+pub use limiter_backend::*;
 pub use sec_headers::*;
\ No newline at end of file
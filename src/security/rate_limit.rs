@@ -1,132 +1,250 @@
+use std::{env, hash::Hash, sync::Arc, time::Duration as StdDuration};
 use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
 
-pub struct ClientRecord {
-    ip: String,
-    count: u32,
-    last_request: DateTime<Utc>,
-    first_request: DateTime<Utc>,
-}
-
+#[derive(Clone, Copy)]
 pub struct RateType(Duration, u32);
 
 impl RateType {
     pub fn new(duration: Duration, req_limit: u32) -> Self {
         Self(duration, req_limit)
     }
+
+    pub fn limit(&self) -> u32 {
+        self.1
+    }
+
+    pub fn window_millis(&self) -> i64 {
+        self.0.num_milliseconds().max(1)
+    }
+
+    /// Fixed window `now_millis` falls in
+    pub fn window_index(&self, now_millis: i64) -> i64 {
+        now_millis.div_euclid(self.window_millis())
+    }
+
+    /// What counting one more request does, given the requests already counted in the
+    /// previous and current windows. Shared by every limiter backend
+    pub fn decide(&self, previous: u32, current: u32, now_millis: i64) -> RateDecision {
+        let window = self.window_millis();
+        let elapsed = now_millis.rem_euclid(window) as f64 / window as f64;
+        let limit = self.1;
+
+        let estimate = previous as f64 * (1.0 - elapsed) + current as f64;
+        if estimate + 1.0 > limit as f64 {
+            return RateDecision {
+                allowed: false,
+                limit,
+                remaining: 0,
+                retry_after: Duration::milliseconds(retry_after_millis(previous, current, elapsed, limit, window)),
+                reset_after: Duration::milliseconds(reset_after_millis(previous, current, elapsed, window)),
+            };
+        }
+
+        RateDecision {
+            allowed: true,
+            limit,
+            remaining: (limit as f64 - estimate - 1.0).floor().max(0.0) as u32,
+            retry_after: Duration::zero(),
+            reset_after: Duration::milliseconds(reset_after_millis(previous, current + 1, elapsed, window)),
+        }
+    }
 }
 
-pub struct RateLimitState {
-    clients: Vec<ClientRecord>,
-    limit: RateType,
-    reset_timeout: Duration,
+/// Requests counted in the current fixed window and the one before it. The previous
+/// count weighs less the further into the current window we are, so the limit slides
+/// along instead of resetting all at once on the window boundary
+struct ClientWindow {
+    index: i64,
+    current: u32,
+    previous: u32,
 }
 
-impl RateLimitState {
-    pub fn new(rate: RateType, reset_timeout: Duration) -> Self {
-        Self {
-            clients: Vec::new(),
-            limit: rate,
-            reset_timeout,
+impl ClientWindow {
+    fn roll(&mut self, index: i64) {
+        if index <= self.index {
+            return;
         }
+
+        self.previous = if index == self.index + 1 { self.current } else { 0 };
+        self.current = 0;
+        self.index = index;
     }
+}
+
+/// Outcome of counting a request against the limit
+#[derive(Debug, Clone, Copy)]
+pub struct RateDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    //* How long until one more request fits, zero when allowed
+    pub retry_after: Duration,
+    //* How long until the client's whole quota is back
+    pub reset_after: Duration,
+}
 
-    pub fn add_client(&mut self, ip: String) {
-        let now = Utc::now();
+/// Sliding window limiter keyed by client (an IP, a user, ...). Clients live in a
+/// sharded map, so a lookup only locks the shard of that client and costs the same
+/// however many clients are tracked. Cloning shares the same clients
+pub struct RateLimitState<K> {
+    clients: Arc<DashMap<K, ClientWindow>>,
+    limit: RateType,
+}
 
-        for client in self.clients.iter_mut() {
-            if client.ip == ip {
-                client.count += 1;
-                client.last_request = now;
-                return;
-            }
+impl<K> Clone for RateLimitState<K> {
+    fn clone(&self) -> Self {
+        Self {
+            clients: self.clients.clone(),
+            limit: self.limit,
         }
-
-        self.clients.push(ClientRecord {
-            ip,
-            count: 1,
-            last_request: now,
-            first_request: now,
-        });
     }
+}
 
-    fn get_client(&self, ip: &str) -> Option<&ClientRecord> {
-        for client in self.clients.iter() {
-            if client.ip == ip {
-                return Some(client);
-            }
+impl<K: Eq + Hash + Send + Sync + 'static> RateLimitState<K> {
+    pub fn new(rate: RateType) -> Self {
+        Self {
+            clients: Arc::new(DashMap::new()),
+            limit: rate,
         }
+    }
 
-        None
+    /// Counts a request of the client, unless it's over the limit
+    pub fn hit(&self, key: K) -> RateDecision {
+        self.hit_at(key, Utc::now().timestamp_millis())
     }
 
-    pub fn reset_client<'s>(&'s mut self, ip: &str) -> Option<&'s ClientRecord> {
-        for client in self.clients.iter_mut() {
-            if client.ip == ip {
-                client.count = 0;
-                client.last_request = Utc::now();
-                client.first_request = Utc::now();
-                return Some(client);
-            }
+    pub fn hit_at(&self, key: K, now_millis: i64) -> RateDecision {
+        let index = self.limit.window_index(now_millis);
+        let mut client = self.clients.entry(key).or_insert_with(|| ClientWindow {
+            index,
+            current: 0,
+            previous: 0,
+        });
+        client.roll(index);
+
+        let decision = self.limit.decide(client.previous, client.current, now_millis);
+        if decision.allowed {
+            client.current += 1;
         }
 
-        None
+        decision
     }
 
-    pub fn on_the_limit(&mut self, ip: &str) -> bool {
-        let client = self.get_client(ip);
-        if client.is_none() {
-            return false;
-        }
-        let client = client.unwrap();
+    /// Drops the clients whose requests all fell out of the sliding window
+    pub fn evict_idle(&self) -> usize {
+        self.evict_idle_at(Utc::now().timestamp_millis())
+    }
 
-        if (Utc::now().timestamp() - client.last_request.timestamp()) <= self.limit.0.num_seconds()
-            && client.count >= self.limit.1
-        {
-            false
-        } else {
-            true
-        }
+    pub fn evict_idle_at(&self, now_millis: i64) -> usize {
+        let index = self.limit.window_index(now_millis);
+        let before = self.clients.len();
+        self.clients.retain(|_, client| index - client.index < 2);
+
+        before.saturating_sub(self.clients.len())
     }
 
-    pub fn passed_reset_timeout(&self, ip: &str) -> bool {
-        let client = self.get_client(ip);
-        if client.is_none() {
-            return false;
-        }
-        let client = client.unwrap();
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
 
-        (Utc::now().timestamp() - client.last_request.timestamp())
-            > self.reset_timeout.num_seconds()
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
     }
 
-    pub fn full_check_on_the_limit(&mut self, ip: String) -> bool {
-        if self.passed_reset_timeout(&ip) {
-            self.reset_client(&ip);
-            return true;
+    /// Evicts idle clients every `every`, for as long as the server runs
+    pub fn spawn_evictor(&self, every: StdDuration) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            loop {
+                interval.tick().await;
+                state.evict_idle();
+            }
+        });
+    }
+}
+
+//* Solves `previous * (1 - elapsed) + current <= limit - 1` for the time it holds
+fn retry_after_millis(previous: u32, current: u32, elapsed: f64, limit: u32, window: i64) -> i64 {
+    let free = limit.saturating_sub(1) as f64;
+    let current = current as f64;
+
+    let wait = if current <= free {
+        match previous {
+            0 => 0.0,
+            previous => (1.0 - (free - current) / previous as f64 - elapsed).max(0.0),
         }
+    } else {
+        (1.0 - elapsed) + (1.0 - free / current)
+    };
 
-        self.add_client(ip.clone());
-        self.on_the_limit(&ip)
-    }
+    (wait * window as f64).ceil() as i64
 }
 
+//* Requests of the current window count until the end of the next one
+fn reset_after_millis(previous: u32, current: u32, elapsed: f64, window: i64) -> i64 {
+    let wait = if current > 0 {
+        2.0 - elapsed
+    } else if previous > 0 {
+        1.0 - elapsed
+    } else {
+        0.0
+    };
+
+    (wait * window as f64).ceil() as i64
+}
+
+/// Longest window the server limit can be given, a week
+pub const MAX_SERVER_WINDOW_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Snapshot of the server limit, for the health endpoint and admins
+#[derive(Debug, Clone, Copy)]
+pub struct ServerLimitStatus {
+    pub enabled: bool,
+    pub limit: u32,
+    pub window: Duration,
+    pub count: u32,
+    pub tripped: bool,
+    pub retry_after: Duration,
+}
+
+/// Caps the submissions the whole server accepts per window, whoever sends them.
+/// Once the cap is reached the breaker stays tripped until the window is over
 pub struct ServerLimit {
     limit: RateType,
+    enabled: bool,
     current_count: u32,
     last_reset: DateTime<Utc>,
-    reset_timeout: Duration,
 }
 
 impl ServerLimit {
-    pub fn new(limit: RateType, reset_timeout: Duration) -> Self {
+    pub fn new(limit: RateType, enabled: bool) -> Self {
         Self {
             limit,
-            reset_timeout,
+            enabled,
             current_count: 0,
             last_reset: Utc::now(),
         }
     }
 
+    /// `SERVER_LIMIT` submissions per `SERVER_LIMIT_WINDOW_SECS`, off when the limit is 0
+    pub fn from_env() -> Self {
+        let limit = match env::var("SERVER_LIMIT") {
+            Ok(val) => val.parse::<u32>().unwrap_or_else(|_| panic!("SERVER_LIMIT must be a number of submissions")),
+            Err(_) => 0,
+        };
+        let window_secs = match env::var("SERVER_LIMIT_WINDOW_SECS") {
+            Ok(val) => match val.parse::<i64>() {
+                Ok(secs) if secs > 0 && secs <= MAX_SERVER_WINDOW_SECS => secs,
+                _ => panic!("SERVER_LIMIT_WINDOW_SECS must be a positive amount of seconds, a week at most"),
+            },
+            Err(_) => 60,
+        };
+
+        Self::new(RateType::new(Duration::seconds(window_secs), limit.max(1)), limit > 0)
+    }
+
     pub fn reset(&mut self) {
         self.current_count = 0;
         self.last_reset = Utc::now();
@@ -136,19 +254,187 @@ impl ServerLimit {
         self.current_count += 1;
     }
 
+    /// Whether one more submission can be accepted, starting a new window when the last one is over
     pub fn check(&mut self) -> bool {
-        let now = Utc::now();
-        let diff = now.timestamp() - self.last_reset.timestamp();
-
-        if diff > self.reset_timeout.num_seconds() {
+        if Utc::now() - self.last_reset >= self.limit.0 {
             self.reset();
-            return true;
         }
 
-        if diff < self.limit.0.num_seconds() && self.current_count >= self.limit.1 {
-            false
-        } else {
-            true
+        !self.enabled || self.current_count < self.limit.1
+    }
+
+    /// Counts a submission right away if it fits, so concurrent ones can't all slip
+    /// under the cap. Gives the start of the window it was counted in
+    pub fn reserve(&mut self) -> Option<DateTime<Utc>> {
+        if !self.check() {
+            return None;
+        }
+
+        self.increment();
+        Some(self.last_reset)
+    }
+
+    /// Gives back a reserved submission that wasn't accepted after all, unless its window is over
+    pub fn release(&mut self, window_start: DateTime<Utc>) {
+        if window_start == self.last_reset {
+            self.current_count = self.current_count.saturating_sub(1);
+        }
+    }
+
+    /// Changes the cap at runtime, the current window keeps its count
+    pub fn tune(&mut self, enabled: Option<bool>, limit: Option<u32>, window: Option<Duration>) {
+        if let Some(enabled) = enabled {
+            self.enabled = enabled;
+        }
+        if let Some(limit) = limit {
+            self.limit.1 = limit;
+        }
+        if let Some(window) = window {
+            self.limit.0 = window;
+        }
+    }
+
+    pub fn status(&mut self) -> ServerLimitStatus {
+        let tripped = !self.check();
+
+        ServerLimitStatus {
+            enabled: self.enabled,
+            limit: self.limit.1,
+            window: self.limit.0,
+            count: self.current_count,
+            tripped,
+            retry_after: match tripped {
+                true => self.last_reset.checked_add_signed(self.limit.0)
+                    .map_or(self.limit.0, |ends_at| ends_at - Utc::now()),
+                false => Duration::zero(),
+            },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: i64 = 60_000;
+    //* Start of an arbitrary window, so tests don't depend on the clock
+    const START: i64 = WINDOW * 1_000;
+
+    fn state(limit: u32) -> RateLimitState<&'static str> {
+        RateLimitState::new(RateType::new(Duration::milliseconds(WINDOW), limit))
+    }
+
+    #[test]
+    fn allows_up_to_the_limit() {
+        let limiter = state(3);
+
+        let remaining = (0..3)
+            .map(|_| limiter.hit_at("client", START))
+            .map(|decision| {
+                assert!(decision.allowed);
+                decision.remaining
+            })
+            .collect::<Vec<u32>>();
+        assert_eq!(remaining, vec![2, 1, 0]);
+
+        let denied = limiter.hit_at("client", START);
+        assert!(!denied.allowed);
+        assert_eq!(denied.remaining, 0);
+        assert!(denied.retry_after > Duration::zero());
+    }
+
+    #[test]
+    fn clients_are_counted_apart() {
+        let limiter = state(1);
+
+        assert!(limiter.hit_at("a", START).allowed);
+        assert!(!limiter.hit_at("a", START).allowed);
+        assert!(limiter.hit_at("b", START).allowed);
+        assert_eq!(limiter.len(), 2);
+    }
+
+    #[test]
+    fn previous_window_weighs_less_as_time_goes() {
+        let limiter = state(3);
+        for _ in 0..3 {
+            limiter.hit_at("client", START);
+        }
+
+        //* Halfway through the next window the 3 previous requests count as 1.5
+        let halfway = START + WINDOW + WINDOW / 2;
+        assert!(limiter.hit_at("client", halfway).allowed);
+        assert!(!limiter.hit_at("client", halfway).allowed);
+    }
+
+    #[test]
+    fn denied_clients_fit_again_after_retry_after() {
+        let limiter = state(3);
+        for _ in 0..3 {
+            limiter.hit_at("client", START);
+        }
+
+        let denied = limiter.hit_at("client", START);
+        let retry_at = START + denied.retry_after.num_milliseconds();
+        assert!(!limiter.hit_at("client", retry_at - 1_000).allowed);
+        assert!(limiter.hit_at("client", retry_at).allowed);
+    }
+
+    #[test]
+    fn windows_skipped_over_are_forgotten() {
+        let limiter = state(2);
+        limiter.hit_at("client", START);
+        limiter.hit_at("client", START);
+
+        let later = START + WINDOW * 2;
+        assert!(limiter.hit_at("client", later).allowed);
+        assert!(limiter.hit_at("client", later).allowed);
+    }
+
+    #[test]
+    fn reset_after_counts_until_the_next_window_is_over() {
+        let limit = RateType::new(Duration::milliseconds(WINDOW), 10);
+
+        assert_eq!(limit.decide(0, 0, START).reset_after, Duration::milliseconds(WINDOW * 2));
+        assert_eq!(limit.decide(4, 0, START + WINDOW / 4).reset_after, Duration::milliseconds(WINDOW * 7 / 4));
+    }
+
+    #[test]
+    fn idle_clients_are_evicted() {
+        let limiter = state(5);
+        limiter.hit_at("idle", START);
+        limiter.hit_at("active", START + WINDOW);
+
+        assert_eq!(limiter.evict_idle_at(START + WINDOW), 0);
+        assert_eq!(limiter.evict_idle_at(START + WINDOW * 2), 1);
+        assert_eq!(limiter.len(), 1);
+    }
+
+    #[test]
+    fn server_limit_trips_until_tuned() {
+        let mut server = ServerLimit::new(RateType::new(Duration::minutes(1), 2), true);
+        assert!(server.check());
+
+        server.increment();
+        server.increment();
+        let status = server.status();
+        assert!(status.tripped);
+        assert_eq!(status.count, 2);
+        assert!(status.retry_after > Duration::zero());
+
+        server.tune(None, Some(3), None);
+        assert!(server.check());
+        server.tune(Some(false), Some(1), None);
+        assert!(server.check());
+    }
+
+    #[test]
+    fn released_reservations_free_their_slot() {
+        let mut server = ServerLimit::new(RateType::new(Duration::minutes(1), 1), true);
+
+        let window_start = server.reserve().unwrap();
+        assert!(server.reserve().is_none());
+
+        server.release(window_start);
+        assert!(server.reserve().is_some());
+    }
+}
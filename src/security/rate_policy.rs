@@ -0,0 +1,169 @@
+use std::{str::FromStr, sync::Arc, time::Duration as StdDuration};
+use chrono::Duration;
+use rocket::{http::Method, serde::Deserialize};
+
+use super::{
+   rate_limit::{RateDecision, RateType},
+   limiter_backend::{LimiterBackend, LimiterStore}
+};
+
+/// Who a policy counts requests for
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Identity {
+   Ip,
+   //* The `sub` of a valid JWT, the IP for anonymous requests
+   Sub,
+   //* The sender (`from`) of a JSON body, the IP when there's none
+   Email
+}
+
+impl Default for Identity {
+   fn default() -> Self {
+      Identity::Ip
+   }
+}
+
+fn any_path() -> String {
+   "/".to_string()
+}
+
+/// One `[[default.rate_limits]]` entry of `Rocket.toml`
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RatePolicyConfig {
+   pub name: String,
+   //* Path prefix, matched on whole segments
+   #[serde(default = "any_path")]
+   pub path: String,
+   //* Any method when empty
+   #[serde(default)]
+   pub methods: Vec<String>,
+   #[serde(default)]
+   pub identity: Identity,
+   pub limit: u32,
+   pub window_secs: i64,
+   //* Only counts requests no other policy matched
+   #[serde(default)]
+   pub fallback: bool
+}
+
+pub struct RatePolicy {
+   pub name: String,
+   pub identity: Identity,
+   pub fallback: bool,
+   path: String,
+   methods: Vec<Method>,
+   rate: RateType,
+   backend: Box<dyn LimiterBackend>
+}
+
+impl RatePolicy {
+   fn from_config(config: RatePolicyConfig, store: &LimiterStore) -> Result<Self, String> {
+      if config.limit == 0 || config.window_secs <= 0 {
+         return Err(format!("Rate limit policy \"{}\" needs a positive limit and window_secs", config.name));
+      }
+      if !config.path.starts_with('/') {
+         return Err(format!("Rate limit policy \"{}\" path must start with \"/\"", config.name));
+      }
+
+      let mut methods = Vec::new();
+      for method in config.methods.iter() {
+         match Method::from_str(method) {
+            Ok(method) => methods.push(method),
+            Err(_) => return Err(format!("Rate limit policy \"{}\" has an unknown method \"{}\"", config.name, method))
+         }
+      }
+
+      let rate = RateType::new(Duration::seconds(config.window_secs), config.limit);
+      Ok(RatePolicy {
+         backend: store.backend(&config.name, rate),
+         rate,
+         path: config.path.trim_end_matches('/').to_string(),
+         name: config.name,
+         identity: config.identity,
+         fallback: config.fallback,
+         methods
+      })
+   }
+
+   fn matches(&self, method: Method, path: &str) -> bool {
+      if !self.methods.is_empty() && !self.methods.contains(&method) {
+         return false;
+      }
+
+      match path.strip_prefix(self.path.as_str()) {
+         Some(rest) => rest.is_empty() || rest.starts_with('/'),
+         None => false
+      }
+   }
+
+   /// Counts a request of the client, `key` being its identity (e.g.: `ip:127.0.0.1`)
+   pub async fn hit(&self, key: String) -> Result<RateDecision, String> {
+      self.backend.hit(key).await
+   }
+
+   /// Refusal of a request that can't be counted, the client waits a whole window
+   pub fn refused(&self) -> RateDecision {
+      let window = Duration::milliseconds(self.rate.window_millis());
+
+      RateDecision {
+         allowed: false,
+         limit: self.rate.limit(),
+         remaining: 0,
+         retry_after: window,
+         reset_after: window
+      }
+   }
+}
+
+/// Rate limit policies in the order they are configured. A request is counted by
+/// every policy matching it, or by the fallback ones when no other does
+#[derive(Clone)]
+pub struct RatePolicies(Arc<Vec<RatePolicy>>);
+
+impl RatePolicies {
+   pub fn from_config(configs: Vec<RatePolicyConfig>, store: &LimiterStore) -> Result<Self, String> {
+      if configs.is_empty() {
+         return Ok(RatePolicies::fallback(store));
+      }
+
+      let policies = configs.into_iter()
+         .map(|config| RatePolicy::from_config(config, store))
+         .collect::<Result<Vec<RatePolicy>, String>>()?;
+
+      Ok(RatePolicies(Arc::new(policies)))
+   }
+
+   /// 25 requests a minute per IP on every route, when none are configured
+   pub fn fallback(store: &LimiterStore) -> Self {
+      let rate = RateType::new(Duration::minutes(1), 25);
+
+      RatePolicies(Arc::new(vec![
+         RatePolicy {
+            name: "default".to_string(),
+            identity: Identity::Ip,
+            fallback: true,
+            path: String::new(),
+            methods: Vec::new(),
+            rate,
+            backend: store.backend("default", rate)
+         }
+      ]))
+   }
+
+   pub fn matching(&self, method: Method, path: &str) -> Vec<&RatePolicy> {
+      let (fallbacks, policies): (Vec<&RatePolicy>, Vec<&RatePolicy>) = self.0.iter()
+         .filter(|policy| policy.matches(method, path))
+         .partition(|policy| policy.fallback);
+
+      if policies.is_empty() { fallbacks } else { policies }
+   }
+
+   /// Evicts idle clients of every policy every `every`
+   pub fn spawn_evictor(&self, every: StdDuration) {
+      for policy in self.0.iter() {
+         policy.backend.spawn_evictor(every);
+      }
+   }
+}
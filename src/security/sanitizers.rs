@@ -1,7 +1,20 @@
 use ammonia;
 
+pub const EMAIL_RGX: &str = r"^[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]+@[a-zA-Z0-9]+(\.[a-zA-Z0-9-]{0,61})+$";
+
 pub fn message_sanitizing(msg: String) -> String {
    ammonia::Builder::empty()
       .clean(&msg)
       .to_string()
-}
\ No newline at end of file
+}
+
+/// Plain text of a sanitized field, for everything that isn't HTML (plain text emails,
+/// headers, exports...). `&amp;` goes last so nothing is unescaped twice
+pub fn unescape_html(text: &str) -> String {
+   text.replace("&lt;", "<")
+      .replace("&gt;", ">")
+      .replace("&quot;", "\"")
+      .replace("&#39;", "'")
+      .replace("&nbsp;", "\u{a0}")
+      .replace("&amp;", "&")
+}
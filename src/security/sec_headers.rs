@@ -42,8 +42,11 @@ impl HeaderFairings {
     pub fn rocket_cors_config() -> CorsOptions {
         CorsOptions {
             allowed_origins: AllowedOrigins::some(&["https://victorgomez.dev"], &[r"^https://(.*\.)*victorgomez.dev$"]),
-            allowed_methods: vec![Method::from_str("GET").unwrap(), Method::from_str("POST").unwrap()].into_iter().map(From::from).collect(),
+            allowed_methods: vec![Method::from_str("GET").unwrap(), Method::from_str("POST").unwrap(), Method::from_str("PUT").unwrap()].into_iter().map(From::from).collect(),
             allowed_headers: AllowedHeaders::All,
+            //* So the contact form can tell when to try again
+            expose_headers: ["RateLimit-Limit", "RateLimit-Remaining", "RateLimit-Reset", "Retry-After"]
+                .iter().map(|header| header.to_string()).collect(),
             ..Default::default()
         }
     }
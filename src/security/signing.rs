@@ -0,0 +1,29 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Compares two secrets without leaking, through timing, how much of them matched
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+   if a.len() != b.len() {
+      return false;
+   }
+
+   a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+   bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hex encoded HMAC-SHA256 of `data`
+pub fn hmac_sha256_hex(key: &[u8], data: &[u8]) -> String {
+   let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+   mac.update(data);
+
+   to_hex(&mac.finalize().into_bytes())
+}
+
+pub fn verify_hmac_sha256_hex(key: &[u8], data: &[u8], signature: &str) -> bool {
+   constant_time_eq(hmac_sha256_hex(key, data).as_bytes(), signature.trim().to_lowercase().as_bytes())
+}
@@ -0,0 +1,114 @@
+use std::{collections::{HashMap, HashSet}, sync::{Arc, RwLock}};
+
+use crate::models::message::Message;
+use super::SpamRule;
+
+//* Below this many messages of each kind the classifier abstains
+const MIN_TRAINED: u32 = 10;
+//* Only the tokens telling the most about a message are combined
+const MAX_TOKENS: usize = 15;
+
+#[derive(Clone, Copy, Default)]
+struct TokenCount {
+   spam: u32,
+   ham: u32
+}
+
+/// Naive Bayes classifier learning from the messages admins label
+#[derive(Default)]
+pub struct Bayes {
+   tokens: HashMap<String, TokenCount>,
+   spam_msgs: u32,
+   ham_msgs: u32
+}
+
+//* Each distinct word of the message counts once, plus the sender's domain
+fn tokens(msg: &Message) -> HashSet<String> {
+   let mut tokens = [ msg.name.as_str(), msg.subject.as_str(), msg.message.as_str() ].iter()
+      .flat_map(|text| text.split(|c: char| !c.is_alphanumeric() && c != '\'' && c != '$'))
+      .filter(|word| (3..=24).contains(&word.chars().count()))
+      .map(|word| word.to_lowercase())
+      .collect::<HashSet<String>>();
+   if let Some((_, domain)) = msg.from.rsplit_once('@') {
+      tokens.insert(format!("domain:{}", domain.to_lowercase()));
+   }
+
+   tokens
+}
+
+impl Bayes {
+   /// Adds the message to what's known of spam (or not), or takes it back out with `forget`
+   pub fn learn(&mut self, msg: &Message, spam: bool, forget: bool) {
+      let step = |count: &mut u32| match forget {
+         true => *count = count.saturating_sub(1),
+         false => *count += 1
+      };
+
+      for token in tokens(msg) {
+         let count = self.tokens.entry(token).or_default();
+         match spam {
+            true => step(&mut count.spam),
+            false => step(&mut count.ham)
+         }
+      }
+      match spam {
+         true => step(&mut self.spam_msgs),
+         false => step(&mut self.ham_msgs)
+      }
+
+      self.tokens.retain(|_, count| count.spam > 0 || count.ham > 0);
+   }
+
+   /// Probability of the message being spam, none until enough messages were learned
+   pub fn spam_probability(&self, msg: &Message) -> Option<f64> {
+      if self.spam_msgs < MIN_TRAINED || self.ham_msgs < MIN_TRAINED {
+         return None;
+      }
+
+      //* Robinson's smoothing: rarely seen tokens stay close to a neutral 0.5
+      let mut probs = tokens(msg).iter()
+         .filter_map(|token| self.tokens.get(token))
+         .map(|count| {
+            let spam_freq = count.spam as f64 / self.spam_msgs as f64;
+            let ham_freq = count.ham as f64 / self.ham_msgs as f64;
+            let prob = spam_freq / (spam_freq + ham_freq);
+            let seen = (count.spam + count.ham) as f64;
+
+            ((0.5 + seen * prob) / (1.0 + seen)).clamp(0.01, 0.99)
+         })
+         .collect::<Vec<f64>>();
+      if probs.is_empty() {
+         return Some(0.5);
+      }
+      probs.sort_by(|a, b| (b - 0.5).abs().total_cmp(&(a - 0.5).abs()));
+      probs.truncate(MAX_TOKENS);
+
+      let (ln_spam, ln_ham) = probs.iter()
+         .fold((0.0, 0.0), |(ln_spam, ln_ham), prob| (ln_spam + prob.ln(), ln_ham + (1.0 - prob).ln()));
+
+      Some(1.0 / (1.0 + (ln_ham - ln_spam).exp()))
+   }
+}
+
+/// Scores from -weight (surely not spam) to +weight (surely spam)
+pub struct BayesRule {
+   pub model: Arc<RwLock<Bayes>>,
+   pub weight: f64
+}
+
+impl SpamRule for BayesRule {
+   fn name(&self) -> &'static str {
+      "bayes"
+   }
+
+   fn score(&self, msg: &Message) -> f64 {
+      let model = match self.model.read() {
+         Ok(model) => model,
+         Err(_) => return 0.0
+      };
+
+      model.spam_probability(msg)
+         .map(|prob| (prob - 0.5) * 2.0 * self.weight)
+         .unwrap_or_default()
+   }
+}
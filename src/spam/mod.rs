@@ -0,0 +1,171 @@
+mod bayes;
+mod rules;
+
+use std::{env, fmt, sync::{Arc, RwLock}};
+
+use crate::{
+   models::message::{Message, SpamHit, SpamInfo},
+   store::{Store, StoreErr, MessageFilter, ListQuery, SortField, Position}
+};
+
+pub use bayes::{Bayes, BayesRule};
+pub use rules::{PatternRule, LinkRule, DisposableDomainRule};
+
+//* Labeled messages are read this many at a time when training at startup
+const TRAINING_BATCH: i64 = 500;
+
+#[derive(Debug)]
+pub struct SpamErr(pub String);
+
+impl fmt::Display for SpamErr {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "Spam filter error: {}", self.0)
+   }
+}
+
+/// One check of the scoring pipeline. Rules don't know about each other, the
+/// scores they give add up and negative ones vouch for the message
+pub trait SpamRule: Send + Sync {
+   fn name(&self) -> &'static str;
+   fn score(&self, msg: &Message) -> f64;
+}
+
+fn env_num<T: std::str::FromStr>(name: &str, default: T) -> T {
+   match env::var(name) {
+      Ok(val) => val.trim().parse::<T>().unwrap_or_else(|_| panic!("{} must be a number", name)),
+      Err(_) => default
+   }
+}
+
+fn env_path(name: &str) -> Option<String> {
+   env::var(name).ok().filter(|path| !path.trim().is_empty())
+}
+
+/// Scores new messages and quarantines those reaching the threshold
+#[derive(Clone)]
+pub struct SpamFilter {
+   enabled: bool,
+   threshold: f64,
+   rules: Arc<Vec<Box<dyn SpamRule>>>,
+   bayes: Arc<RwLock<Bayes>>
+}
+
+impl SpamFilter {
+   pub fn from_env() -> Result<Self, SpamErr> {
+      let enabled = !matches!(env::var("SPAM_FILTER").as_deref(), Ok("off") | Ok("false"));
+      let bayes = Arc::new(RwLock::new(Bayes::default()));
+
+      let mut rules: Vec<Box<dyn SpamRule>> = vec![
+         Box::new(LinkRule::new(env_num("SPAM_MAX_LINKS", 2), env_num("SPAM_LINK_SCORE", 1.5))),
+         Box::new(DisposableDomainRule::new(env_path("SPAM_DISPOSABLE_DOMAINS_FILE").as_deref(), env_num("SPAM_DISPOSABLE_SCORE", 3.0))?),
+         Box::new(BayesRule { model: bayes.clone(), weight: env_num("SPAM_BAYES_WEIGHT", 5.0) })
+      ];
+      if let Some(path) = env_path("SPAM_RULES_FILE") {
+         rules.push(Box::new(PatternRule::from_file(&path)?));
+      }
+
+      Ok(SpamFilter {
+         enabled,
+         threshold: env_num("SPAM_THRESHOLD", 5.0),
+         rules: Arc::new(rules),
+         bayes
+      })
+   }
+
+   /// Runs every rule on the message, none when the filter is off
+   pub fn check(&self, msg: &Message) -> Option<SpamInfo> {
+      if !self.enabled {
+         return None;
+      }
+
+      let hits = self.rules.iter()
+         .map(|rule| SpamHit { rule: rule.name().to_string(), score: rule.score(msg) })
+         .filter(|hit| hit.score != 0.0)
+         .collect::<Vec<SpamHit>>();
+      let score = hits.iter().map(|hit| hit.score).sum::<f64>();
+
+      Some(SpamInfo {
+         score,
+         hits,
+         quarantined: score >= self.threshold,
+         label: None
+      })
+   }
+
+   /// Quarantines the message whatever it scored, the rule weighing the threshold on its own.
+   /// Used for the bot checks running outside of the pipeline
+   pub fn quarantine(&self, info: Option<SpamInfo>, rule: &str) -> SpamInfo {
+      let mut info = info.unwrap_or(SpamInfo { score: 0.0, hits: Vec::new(), quarantined: false, label: None });
+      info.hits.push(SpamHit { rule: rule.to_string(), score: self.threshold });
+      info.score += self.threshold;
+      info.quarantined = true;
+
+      info
+   }
+
+   /// Teaches the classifier what an admin said about the message, undoing any
+   /// earlier label of it
+   pub fn learn(&self, msg: &Message, spam: bool) {
+      let previous = msg.spam.as_ref().and_then(|info| info.label);
+      if previous == Some(spam) {
+         return;
+      }
+
+      let mut model = match self.bayes.write() {
+         Ok(model) => model,
+         Err(poisoned) => poisoned.into_inner()
+      };
+      if let Some(previous) = previous {
+         model.learn(msg, previous, true);
+      }
+      model.learn(msg, spam, false);
+   }
+
+   /// Learns every message admins labeled so far, trashed ones included
+   pub async fn train(&self, store: &Store) -> Result<usize, StoreErr> {
+      let mut learned = 0;
+
+      for (label, trashed) in [ (true, false), (true, true), (false, false), (false, true) ].iter().copied() {
+         let mut after: Option<Position> = None;
+         loop {
+            let query = ListQuery {
+               filter: MessageFilter {
+                  spam_label: Some(label),
+                  trashed,
+                  ..Default::default()
+               },
+               sort: SortField::CreatedAt,
+               descending: false,
+               after: after.take(),
+               skip: 0,
+               limit: TRAINING_BATCH
+            };
+            let listed = store.messages.list(&query).await?;
+
+            {
+               let mut model = match self.bayes.write() {
+                  Ok(model) => model,
+                  Err(poisoned) => poisoned.into_inner()
+               };
+               for listed in listed.iter() {
+                  model.learn(&listed.msg, label, false);
+               }
+            }
+            learned += listed.len();
+
+            match listed.last() {
+               Some(last) if listed.len() as i64 == TRAINING_BATCH => after = last.msg.id.map(|id| Position {
+                  value: SortField::CreatedAt.value_of(&last.msg),
+                  id
+               }),
+               _ => break
+            }
+            if after.is_none() {
+               break;
+            }
+         }
+      }
+
+      Ok(learned)
+   }
+}
@@ -0,0 +1,125 @@
+use std::fs;
+use regex::{Regex, RegexBuilder};
+
+use crate::models::message::Message;
+use super::{SpamRule, SpamErr};
+
+//* Throwaway inbox providers, extended through SPAM_DISPOSABLE_DOMAINS_FILE
+const DISPOSABLE_DOMAINS: [&str; 12] = [
+   "mailinator.com", "guerrillamail.com", "sharklasers.com", "10minutemail.com", "tempmail.com", "temp-mail.org",
+   "yopmail.com", "trashmail.com", "getnada.com", "maildrop.cc", "dispostable.com", "throwawaymail.com"
+];
+
+fn read_lines(path: &str) -> Result<Vec<String>, SpamErr> {
+   let content = fs::read_to_string(path).map_err(|err| SpamErr(format!("\"{}\": {}", path, err)))?;
+
+   Ok(content.lines()
+      .map(|line| line.trim())
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .map(|line| line.to_string())
+      .collect())
+}
+
+/// Weighted keyword and regex patterns, matched case insensitively against the
+/// sender's name, the subject and the message
+pub struct PatternRule {
+   patterns: Vec<(f64, Regex)>
+}
+
+impl PatternRule {
+   /// One `<weight> <regex>` per line, e.g.: "2.5 \bcrypto\s+invest"
+   pub fn from_file(path: &str) -> Result<Self, SpamErr> {
+      let mut patterns = Vec::new();
+      for line in read_lines(path)? {
+         let (weight, pattern) = line.split_once(char::is_whitespace)
+            .ok_or_else(|| SpamErr(format!("\"{}\": expected \"<weight> <regex>\", got \"{}\"", path, line)))?;
+         let weight = weight.parse::<f64>()
+            .map_err(|_| SpamErr(format!("\"{}\": invalid weight \"{}\"", path, weight)))?;
+         let pattern = RegexBuilder::new(pattern.trim()).case_insensitive(true).build()
+            .map_err(|err| SpamErr(format!("\"{}\": {}", path, err)))?;
+
+         patterns.push((weight, pattern));
+      }
+
+      Ok(PatternRule { patterns })
+   }
+}
+
+impl SpamRule for PatternRule {
+   fn name(&self) -> &'static str {
+      "patterns"
+   }
+
+   fn score(&self, msg: &Message) -> f64 {
+      let texts = [ msg.name.as_str(), msg.subject.as_str(), msg.message.as_str() ];
+
+      self.patterns.iter()
+         .filter(|(_, pattern)| texts.iter().any(|text| pattern.is_match(text)))
+         .map(|(weight, _)| weight)
+         .sum()
+   }
+}
+
+/// Scores every link past the first `allowed` ones
+pub struct LinkRule {
+   pub allowed: usize,
+   pub per_link: f64,
+   link_rgx: Regex
+}
+
+impl LinkRule {
+   pub fn new(allowed: usize, per_link: f64) -> Self {
+      LinkRule {
+         allowed,
+         per_link,
+         link_rgx: Regex::new(r"(?i)\b(?:https?://|www\.)\S+").unwrap()
+      }
+   }
+}
+
+impl SpamRule for LinkRule {
+   fn name(&self) -> &'static str {
+      "links"
+   }
+
+   fn score(&self, msg: &Message) -> f64 {
+      let links = self.link_rgx.find_iter(&msg.subject).count() + self.link_rgx.find_iter(&msg.message).count();
+
+      links.saturating_sub(self.allowed) as f64 * self.per_link
+   }
+}
+
+/// Senders writing from a disposable inbox provider, subdomains included
+pub struct DisposableDomainRule {
+   domains: Vec<String>,
+   pub weight: f64
+}
+
+impl DisposableDomainRule {
+   pub fn new(extra_domains_file: Option<&str>, weight: f64) -> Result<Self, SpamErr> {
+      let mut domains = DISPOSABLE_DOMAINS.iter().map(|domain| domain.to_string()).collect::<Vec<String>>();
+      if let Some(path) = extra_domains_file {
+         domains.extend(read_lines(path)?.into_iter().map(|domain| domain.to_lowercase()));
+      }
+
+      Ok(DisposableDomainRule { domains, weight })
+   }
+}
+
+impl SpamRule for DisposableDomainRule {
+   fn name(&self) -> &'static str {
+      "disposable_domain"
+   }
+
+   fn score(&self, msg: &Message) -> f64 {
+      let domain = match msg.from.rsplit_once('@') {
+         Some((_, domain)) => domain.to_lowercase(),
+         None => return 0.0
+      };
+
+      match self.domains.iter().any(|listed| domain == *listed || domain.ends_with(&format!(".{}", listed))) {
+         true => self.weight,
+         false => 0.0
+      }
+   }
+}
@@ -0,0 +1,650 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+use rocket::async_trait;
+use tokio::sync::RwLock;
+use mongodb::bson::{oid::ObjectId, DateTime as BsonDateTime};
+
+use crate::models::{
+   message::{Message, ThreadEntry, ThreadDirection, DeliveryStatus, SpamInfo},
+   job::{DeliveryJob, JobKind, JobState, JobAttempt, MAX_LOGGED_ATTEMPTS},
+   event::MessageEvent
+};
+use super::*;
+
+/// Keeps everything in the process memory. Nothing survives a restart, meant
+/// for tests and local development
+#[derive(Default)]
+pub struct MemoryStore {
+   messages: RwLock<BTreeMap<ObjectId, Message>>,
+   jobs: RwLock<BTreeMap<ObjectId, DeliveryJob>>,
+   events: RwLock<BTreeMap<ObjectId, MessageEvent>>
+}
+
+impl MemoryStore {
+   pub fn new() -> Self {
+      MemoryStore::default()
+   }
+}
+
+/// Rough stand-in for the Mongo text index: words are matched case-insensitively,
+/// "quoted phrases" must all appear and "-words" must not
+struct TextQuery {
+   terms: Vec<String>,
+   phrases: Vec<String>,
+   excluded: Vec<String>
+}
+
+//* Same weights as the Mongo text index
+const SEARCH_WEIGHTS: [f64; 3] = [5.0, 3.0, 1.0];
+
+impl TextQuery {
+   pub fn parse(query: &str) -> Self {
+      let mut text_query = TextQuery { terms: Vec::new(), phrases: Vec::new(), excluded: Vec::new() };
+
+      for (i, part) in query.to_lowercase().split('"').enumerate() {
+         //* Odd parts sit between quotes
+         if i % 2 == 1 {
+            if !part.trim().is_empty() {
+               text_query.phrases.push(part.trim().to_string());
+            }
+            continue;
+         }
+
+         for word in part.split_whitespace() {
+            match word.strip_prefix('-') {
+               Some(excluded) if !excluded.is_empty() => text_query.excluded.push(excluded.to_string()),
+               _ => text_query.terms.push(word.to_string())
+            }
+         }
+      }
+
+      text_query
+   }
+
+   fn words(text: &str) -> impl Iterator<Item = &str> {
+      text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty())
+   }
+
+   /// Weighted count of matched words, `None` when the message doesn't match
+   pub fn score(&self, msg: &Message) -> Option<f64> {
+      let fields = [msg.subject.to_lowercase(), msg.name.to_lowercase(), msg.message.to_lowercase()];
+
+      let has_word = |word: &str| fields.iter().any(|field| TextQuery::words(field).any(|w| w == word));
+      if self.excluded.iter().any(|word| has_word(word)) {
+         return None;
+      }
+      if !self.phrases.iter().all(|phrase| fields.iter().any(|field| field.contains(phrase.as_str()))) {
+         return None;
+      }
+
+      let mut score = 0.0;
+      for (field, weight) in fields.iter().zip(SEARCH_WEIGHTS.iter()) {
+         let hits = TextQuery::words(field)
+            .filter(|word| self.terms.iter().any(|term| term == word))
+            .count();
+         score += hits as f64 * weight;
+      }
+      for phrase in self.phrases.iter() {
+         for (field, weight) in fields.iter().zip(SEARCH_WEIGHTS.iter()) {
+            score += field.matches(phrase.as_str()).count() as f64 * weight;
+         }
+      }
+
+      match score > 0.0 {
+         true => Some(score),
+         false => None
+      }
+   }
+}
+
+fn date_matches(filter: &MessageFilter, msg: &Message) -> bool {
+   let created_at = match msg.created_at {
+      Some(created_at) => created_at.to_chrono(),
+      None => return filter.created_before.is_none() && filter.created_after.is_none() && filter.created_within.is_none()
+   };
+
+   if let Some(before) = filter.created_before {
+      return created_at < before;
+   }
+   if let Some(after) = filter.created_after {
+      return created_at > after;
+   }
+   if let Some((start, end)) = filter.created_within {
+      return created_at >= start && created_at <= end;
+   }
+
+   true
+}
+
+/// Whether the message passes the filter, along with its score when searching
+fn filter_match(filter: &MessageFilter, search: Option<&TextQuery>, msg: &Message) -> Option<Option<f64>> {
+   if filter.read.map_or(false, |read| msg.read != read)
+   || filter.archived.map_or(false, |archived| msg.archived != archived)
+   || filter.senders.as_ref().map_or(false, |senders| !senders.contains(&msg.from))
+   || msg.deleted_at.is_some() != filter.trashed
+   || filter.quarantined.map_or(false, |quarantined| msg.spam.as_ref().map_or(false, |spam| spam.quarantined) != quarantined)
+   || filter.spam_label.map_or(false, |label| msg.spam.as_ref().and_then(|spam| spam.label) != Some(label))
+   || !date_matches(filter, msg) {
+      return None;
+   }
+
+   match search {
+      Some(search) => search.score(msg).map(Some),
+      None => Some(None)
+   }
+}
+
+//* Same order Mongo gives to mixed types: nulls first
+fn cmp_sort_values(a: &SortValue, b: &SortValue) -> Ordering {
+   match (a, b) {
+      (SortValue::Null, SortValue::Null) => Ordering::Equal,
+      (SortValue::Null, _) => Ordering::Less,
+      (_, SortValue::Null) => Ordering::Greater,
+      (SortValue::Date(a), SortValue::Date(b)) => a.cmp(b),
+      (SortValue::Text(a), SortValue::Text(b)) => a.cmp(b),
+      (SortValue::Date(_), SortValue::Text(_)) => Ordering::Greater,
+      (SortValue::Text(_), SortValue::Date(_)) => Ordering::Less
+   }
+}
+
+/// Filters, sorts and pages the given messages the way Mongo would. Also used
+/// by backends that can't search on their own
+pub fn list_messages<'a>(msgs: impl Iterator<Item = &'a Message>, query: &ListQuery) -> Vec<Listed> {
+   let search = query.filter.search.as_deref().map(TextQuery::parse);
+
+   let mut listed = msgs
+      .filter_map(|msg| filter_match(&query.filter, search.as_ref(), msg)
+         .map(|score| Listed { msg: msg.clone(), score }))
+      .collect::<Vec<Listed>>();
+
+   //* Ties are broken by id so that every message has a stable position
+   let key_cmp = |a_value: &SortValue, a_id: Option<ObjectId>, b_value: &SortValue, b_id: Option<ObjectId>| {
+      let ord = cmp_sort_values(a_value, b_value).then(a_id.cmp(&b_id));
+      if query.descending { ord.reverse() } else { ord }
+   };
+   match query.sort {
+      SortField::Relevance => listed.sort_by(|a, b| b.score.unwrap_or_default()
+         .partial_cmp(&a.score.unwrap_or_default())
+         .unwrap_or(Ordering::Equal)
+         .then(a.msg.id.cmp(&b.msg.id))),
+      field => listed.sort_by(|a, b| key_cmp(
+         &field.value_of(&a.msg), a.msg.id,
+         &field.value_of(&b.msg), b.msg.id
+      ))
+   }
+
+   if let Some(position) = &query.after {
+      listed.retain(|listed| key_cmp(
+         &query.sort.value_of(&listed.msg), listed.msg.id,
+         &position.value, Some(position.id)
+      ) == Ordering::Greater);
+   }
+
+   //* Threads are never part of listings, same as with Mongo
+   listed.into_iter()
+      .skip(query.skip as usize)
+      .take(query.limit.max(0) as usize)
+      .map(|mut listed| {
+         listed.msg.thread = Vec::new();
+         listed
+      })
+      .collect()
+}
+
+/// Whether `addr` is the sender's `email`, addresses are matched case-insensitively
+pub fn same_sender(addr: &str, email: &str) -> bool {
+   addr.trim().eq_ignore_ascii_case(email.trim())
+}
+
+/// Replaces the sender's address and name in the thread emails. Also used by backends
+/// storing threads as plain documents
+pub fn anonymize_thread(thread: &mut [ThreadEntry], email: &str, alias_email: &str, alias_name: &str) {
+   for entry in thread.iter_mut() {
+      if same_sender(&entry.from, email) {
+         entry.from = alias_email.to_string();
+         //* Inbound emails carry the display name of whoever sent them
+         if entry.direction == ThreadDirection::Inbound && entry.author.is_some() {
+            entry.author = Some(alias_name.to_string());
+         }
+      }
+
+      //* Inbound recipients are comma separated, the sender may be copied in
+      if entry.to.split(',').any(|addr| same_sender(addr, email)) {
+         entry.to = entry.to.split(',')
+            .map(|addr| if same_sender(addr, email) { alias_email } else { addr.trim() })
+            .collect::<Vec<&str>>()
+            .join(", ");
+      }
+   }
+}
+
+pub fn count_messages<'a>(msgs: impl Iterator<Item = &'a Message>, filter: &MessageFilter) -> u64 {
+   let search = filter.search.as_deref().map(TextQuery::parse);
+
+   msgs.filter(|msg| filter_match(filter, search.as_ref(), msg).is_some())
+      .count() as u64
+}
+
+#[async_trait]
+impl MessageStore for MemoryStore {
+   async fn insert(&self, msg: &Message) -> Result<ObjectId, StoreErr> {
+      let id = msg.id.unwrap_or_else(ObjectId::new);
+      let mut msg = msg.clone();
+      msg.id = Some(id);
+
+      self.messages.write().await.insert(id, msg);
+      Ok(id)
+   }
+
+   async fn find(&self, id: ObjectId) -> Result<Option<Message>, StoreErr> {
+      Ok(self.messages.read().await.get(&id).cloned())
+   }
+
+   async fn find_by_thread_email(&self, message_ids: &[String]) -> Result<Option<Message>, StoreErr> {
+      Ok(self.messages.read().await.values()
+         .find(|msg| msg.thread.iter().any(|entry| message_ids.contains(&entry.message_id)))
+         .cloned())
+   }
+
+   async fn find_imported(&self, source_id: Option<&str>, content_hash: &str) -> Result<Option<ObjectId>, StoreErr> {
+      Ok(self.messages.read().await.iter()
+         .find(|(_, msg)| (source_id.is_some() && msg.source_id.as_deref() == source_id)
+            || msg.content_hash.as_deref() == Some(content_hash))
+         .map(|(id, _)| *id))
+   }
+
+   async fn list(&self, query: &ListQuery) -> Result<Vec<Listed>, StoreErr> {
+      Ok(list_messages(self.messages.read().await.values(), query))
+   }
+
+   async fn count(&self, filter: &MessageFilter) -> Result<u64, StoreErr> {
+      Ok(count_messages(self.messages.read().await.values(), filter))
+   }
+
+   async fn update_flags(&self, id: ObjectId, flags: FlagsUpdate) -> Result<Option<Message>, StoreErr> {
+      let mut messages = self.messages.write().await;
+      let msg = match messages.get_mut(&id) {
+         Some(msg) => msg,
+         None => return Ok(None)
+      };
+
+      let previous = msg.clone();
+      if let Some(read) = flags.read {
+         msg.read = read;
+      }
+      if let Some(archived) = flags.archived {
+         msg.archived = archived;
+      }
+
+      Ok(Some(previous))
+   }
+
+   async fn delete(&self, ids: &[ObjectId]) -> Result<Vec<ObjectId>, StoreErr> {
+      let mut messages = self.messages.write().await;
+
+      Ok(ids.iter()
+         .filter(|id| messages.remove(id).is_some())
+         .copied()
+         .collect())
+   }
+
+   async fn trash(&self, ids: &[ObjectId], now: BsonDateTime) -> Result<Vec<ObjectId>, StoreErr> {
+      let mut messages = self.messages.write().await;
+
+      Ok(ids.iter()
+         .filter(|id| match messages.get_mut(id) {
+            Some(msg) if msg.deleted_at.is_none() => {
+               msg.deleted_at = Some(now);
+               true
+            },
+            _ => false
+         })
+         .copied()
+         .collect())
+   }
+
+   async fn restore(&self, ids: &[ObjectId]) -> Result<Vec<ObjectId>, StoreErr> {
+      let mut messages = self.messages.write().await;
+
+      Ok(ids.iter()
+         .filter(|id| messages.get_mut(id)
+            .and_then(|msg| msg.deleted_at.take())
+            .is_some())
+         .copied()
+         .collect())
+   }
+
+   async fn purge_trashed(&self, before: BsonDateTime) -> Result<Vec<ObjectId>, StoreErr> {
+      let mut messages = self.messages.write().await;
+
+      let expired = messages.iter()
+         .filter(|(_, msg)| msg.deleted_at.map_or(false, |deleted_at| deleted_at < before))
+         .map(|(id, _)| *id)
+         .collect::<Vec<ObjectId>>();
+      for id in expired.iter() {
+         messages.remove(id);
+      }
+
+      Ok(expired)
+   }
+
+   async fn ids_by_sender(&self, email: &str) -> Result<Vec<ObjectId>, StoreErr> {
+      Ok(self.messages.read().await.iter()
+         .filter(|(_, msg)| same_sender(&msg.from, email))
+         .map(|(id, _)| *id)
+         .collect())
+   }
+
+   async fn anonymize_sender(&self, email: &str, alias_email: &str, alias_name: &str) -> Result<Vec<ObjectId>, StoreErr> {
+      let mut messages = self.messages.write().await;
+
+      let mut anonymized = Vec::new();
+      for (id, msg) in messages.iter_mut().filter(|(_, msg)| same_sender(&msg.from, email)) {
+         msg.from = alias_email.to_string();
+         msg.name = alias_name.to_string();
+         anonymize_thread(&mut msg.thread, email, alias_email, alias_name);
+         anonymized.push(*id);
+      }
+
+      Ok(anonymized)
+   }
+
+   async fn set_spam(&self, id: ObjectId, spam: &SpamInfo) -> Result<bool, StoreErr> {
+      match self.messages.write().await.get_mut(&id) {
+         Some(msg) => {
+            msg.spam = Some(spam.clone());
+            Ok(true)
+         },
+         None => Ok(false)
+      }
+   }
+
+   async fn push_thread_entry(&self, id: ObjectId, entry: &ThreadEntry, mark_unread: bool) -> Result<bool, StoreErr> {
+      match self.messages.write().await.get_mut(&id) {
+         Some(msg) => {
+            msg.thread.push(entry.clone());
+            if mark_unread {
+               msg.read = false;
+            }
+            Ok(true)
+         },
+         None => Ok(false)
+      }
+   }
+
+   async fn set_delivery(&self, id: ObjectId, target: DeliveryTarget, status: &DeliveryStatus) -> Result<(), StoreErr> {
+      if let Some(msg) = self.messages.write().await.get_mut(&id) {
+         match target {
+            DeliveryTarget::Owners => msg.delivery = Some(status.clone()),
+            DeliveryTarget::Ack => msg.ack = Some(status.clone()),
+            DeliveryTarget::Reply(entry_id) => {
+               if let Some(entry) = msg.thread.iter_mut().find(|entry| entry.id == entry_id) {
+                  entry.delivery = Some(status.clone());
+               }
+            }
+         }
+      }
+
+      Ok(())
+   }
+
+   async fn check_conn(&self) -> ConnCheck {
+      ConnCheck::Ok
+   }
+}
+
+#[async_trait]
+impl JobStore for MemoryStore {
+   async fn insert(&self, job: &DeliveryJob) -> Result<(), StoreErr> {
+      let id = job.id.unwrap_or_else(ObjectId::new);
+      let mut job = job.clone();
+      job.id = Some(id);
+
+      self.jobs.write().await.insert(id, job);
+      Ok(())
+   }
+
+   async fn lease(&self, now: BsonDateTime, until: BsonDateTime) -> Result<Option<DeliveryJob>, StoreErr> {
+      let mut jobs = self.jobs.write().await;
+
+      let due = jobs.values_mut()
+         .filter(|job| match job.state {
+            JobState::Queued => job.run_at <= now,
+            JobState::Leased => job.leased_until.map_or(true, |leased_until| leased_until <= now),
+            _ => false
+         })
+         .min_by_key(|job| job.run_at);
+
+      Ok(due.map(|job| {
+         //* A lease that ran out is an attempt that never finished (e.g.: the worker died mid-send)
+         if job.state == JobState::Leased {
+            job.attempts += 1;
+         }
+         job.state = JobState::Leased;
+         job.leased_until = Some(until);
+         job.updated_at = now;
+         job.clone()
+      }))
+   }
+
+   async fn finish_attempt(&self, job: &DeliveryJob, error: Option<&str>, now: BsonDateTime) -> Result<bool, StoreErr> {
+      let mut jobs = self.jobs.write().await;
+      let stored = match job.id.and_then(|id| jobs.get_mut(&id)) {
+         Some(stored) if stored.state == JobState::Leased && stored.leased_until == job.leased_until => stored,
+         _ => return Ok(false)
+      };
+
+      stored.state = job.state;
+      stored.attempts = job.attempts;
+      stored.run_at = job.run_at;
+      stored.last_error = job.last_error.clone();
+      stored.leased_until = None;
+      stored.updated_at = now;
+      stored.log.push(JobAttempt { at: now, error: error.map(String::from) });
+
+      let overflow = stored.log.len().saturating_sub(MAX_LOGGED_ATTEMPTS as usize);
+      stored.log.drain(..overflow);
+
+      Ok(true)
+   }
+
+   async fn list(&self, filter: &JobFilter) -> Result<Vec<DeliveryJob>, StoreErr> {
+      let mut jobs = self.jobs.read().await.values()
+         .filter(|job| filter.state.map_or(true, |state| job.state == state))
+         .filter(|job| filter.message_ids.as_ref().map_or(true, |ids| ids.contains(&job.message_id)))
+         .filter(|job| match &job.kind {
+            JobKind::Webhook { endpoint, .. } => filter.endpoint.as_ref().map_or(true, |wanted| wanted == endpoint),
+            _ => !filter.webhooks_only && filter.endpoint.is_none()
+         })
+         .cloned()
+         .collect::<Vec<DeliveryJob>>();
+
+      jobs.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+      if let Some(limit) = filter.limit {
+         jobs.truncate(limit.max(0) as usize);
+      }
+
+      Ok(jobs)
+   }
+
+   async fn redrive(&self, ids: &[ObjectId], now: BsonDateTime) -> Result<u64, StoreErr> {
+      let mut jobs = self.jobs.write().await;
+
+      let mut redriven = 0;
+      for id in ids {
+         if let Some(job) = jobs.get_mut(id).filter(|job| job.state == JobState::Dead) {
+            job.state = JobState::Queued;
+            job.attempts = 0;
+            job.run_at = now;
+            job.updated_at = now;
+            redriven += 1;
+         }
+      }
+
+      Ok(redriven)
+   }
+
+   async fn delete_for_messages(&self, message_ids: &[ObjectId]) -> Result<u64, StoreErr> {
+      let mut jobs = self.jobs.write().await;
+
+      let before = jobs.len();
+      jobs.retain(|_, job| !message_ids.contains(&job.message_id));
+
+      Ok((before - jobs.len()) as u64)
+   }
+}
+
+#[async_trait]
+impl EventStore for MemoryStore {
+   async fn insert(&self, event: &MessageEvent) -> Result<(), StoreErr> {
+      self.events.write().await.insert(event.id, event.clone());
+      Ok(())
+   }
+
+   async fn find(&self, id: ObjectId) -> Result<Option<MessageEvent>, StoreErr> {
+      Ok(self.events.read().await.get(&id).cloned())
+   }
+
+   async fn since(&self, last_id: ObjectId, limit: i64) -> Result<Vec<MessageEvent>, StoreErr> {
+      use std::ops::Bound::{Excluded, Unbounded};
+
+      Ok(self.events.read().await
+         .range((Excluded(last_id), Unbounded))
+         .take(limit.max(0) as usize)
+         .map(|(_, event)| event.clone())
+         .collect())
+   }
+
+   async fn for_messages(&self, message_ids: &[ObjectId]) -> Result<Vec<MessageEvent>, StoreErr> {
+      //* Ids are ordered by creation time
+      Ok(self.events.read().await.values()
+         .filter(|event| message_ids.contains(&event.message_id))
+         .cloned()
+         .collect())
+   }
+
+   async fn delete_for_messages(&self, message_ids: &[ObjectId]) -> Result<u64, StoreErr> {
+      let mut events = self.events.write().await;
+
+      let before = events.len();
+      events.retain(|_, event| !message_ids.contains(&event.message_id));
+
+      Ok((before - events.len()) as u64)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn entry(direction: ThreadDirection, from: &str, to: &str, author: Option<&str>) -> ThreadEntry {
+      ThreadEntry {
+         id: ObjectId::new(),
+         direction,
+         from: from.to_string(),
+         to: to.to_string(),
+         subject: "Re: Hi".to_string(),
+         body: "Hello!".to_string(),
+         message_id: format!("<{}@example.com>", ObjectId::new().to_hex()),
+         in_reply_to: None,
+         references: Vec::new(),
+         author: author.map(String::from),
+         attachments: Vec::new(),
+         created_at: BsonDateTime::now(),
+         delivery: None
+      }
+   }
+
+   fn message(from: &str, thread: Vec<ThreadEntry>) -> Message {
+      Message {
+         id: None,
+         created_at: Some(BsonDateTime::now()),
+         from: from.to_string(),
+         name: "Jane Doe".to_string(),
+         subject: "Hi".to_string(),
+         message: "Hello there!".to_string(),
+         locale: None,
+         read: false,
+         archived: false,
+         delivery: None,
+         ack: None,
+         thread,
+         deleted_at: None,
+         source_id: None,
+         content_hash: None,
+         spam: None
+      }
+   }
+
+   #[test]
+   fn anonymize_thread_scrubs_every_trace_of_the_sender() {
+      let mut thread = vec![
+         entry(ThreadDirection::Outbound, "owner@example.com", "Jane@Example.com", Some("auth0|admin")),
+         entry(ThreadDirection::Inbound, "jane@example.com", "replies+1@example.com, JANE@example.com", Some("Jane Doe")),
+         entry(ThreadDirection::Inbound, "john@example.com", "replies+1@example.com", Some("John Doe"))
+      ];
+
+      anonymize_thread(&mut thread, "jane@example.com", "alias@anonymized.invalid", "Anonymized");
+
+      assert_eq!(thread[0].to, "alias@anonymized.invalid");
+      //* Admins replying aren't the sender
+      assert_eq!(thread[0].author.as_deref(), Some("auth0|admin"));
+      assert_eq!(thread[1].from, "alias@anonymized.invalid");
+      assert_eq!(thread[1].to, "replies+1@example.com, alias@anonymized.invalid");
+      assert_eq!(thread[1].author.as_deref(), Some("Anonymized"));
+      assert_eq!(thread[2].from, "john@example.com");
+      assert_eq!(thread[2].author.as_deref(), Some("John Doe"));
+   }
+
+   #[rocket::async_test]
+   async fn attempts_of_a_lost_lease_are_refused() {
+      let store = MemoryStore::new();
+      let now = BsonDateTime::now();
+      JobStore::insert(&store, &DeliveryJob {
+         id: None,
+         message_id: ObjectId::new(),
+         kind: JobKind::OwnerNotification,
+         state: JobState::Queued,
+         attempts: 0,
+         max_attempts: 5,
+         run_at: now,
+         leased_until: None,
+         last_error: None,
+         log: Vec::new(),
+         created_at: now,
+         updated_at: now
+      }).await.unwrap();
+
+      let expired = store.lease(now, now).await.unwrap().unwrap();
+      let later = BsonDateTime::from_millis(now.timestamp_millis() + 1_000);
+      let taken_over = store.lease(later, BsonDateTime::from_millis(later.timestamp_millis() + 60_000)).await.unwrap().unwrap();
+      assert_eq!(taken_over.attempts, 1);
+
+      let done = |job: &DeliveryJob| DeliveryJob { state: JobState::Done, ..job.clone() };
+      assert!(!store.finish_attempt(&done(&expired), None, later).await.unwrap());
+      assert!(store.finish_attempt(&done(&taken_over), None, later).await.unwrap());
+   }
+
+   #[rocket::async_test]
+   async fn senders_are_matched_whatever_their_case() {
+      let store = MemoryStore::new();
+      let jane = MessageStore::insert(&store, &message("Jane@Example.com", Vec::new())).await.unwrap();
+      let threaded = MessageStore::insert(&store, &message("jane@example.com", vec![
+         entry(ThreadDirection::Inbound, "jane@example.com", "replies@example.com", Some("Jane Doe"))
+      ])).await.unwrap();
+      let john = MessageStore::insert(&store, &message("john@example.com", Vec::new())).await.unwrap();
+
+      let mut ids = store.ids_by_sender("JANE@example.com").await.unwrap();
+      ids.sort();
+      assert_eq!(ids, vec![jane, threaded]);
+
+      let mut anonymized = store.anonymize_sender("jane@EXAMPLE.com", "alias@anonymized.invalid", "Anonymized").await.unwrap();
+      anonymized.sort();
+      assert_eq!(anonymized, vec![jane, threaded]);
+
+      let msg = MessageStore::find(&store, threaded).await.unwrap().unwrap();
+      assert_eq!((msg.from.as_str(), msg.name.as_str()), ("alias@anonymized.invalid", "Anonymized"));
+      assert_eq!(msg.thread[0].from, "alias@anonymized.invalid");
+      assert_eq!(MessageStore::find(&store, john).await.unwrap().unwrap().from, "john@example.com");
+      assert!(store.ids_by_sender("jane@example.com").await.unwrap().is_empty());
+   }
+}
@@ -0,0 +1,230 @@
+mod memory;
+mod mongo;
+mod sql;
+
+use std::{env, fmt, sync::Arc};
+use chrono::{DateTime, Utc};
+use rocket::async_trait;
+use mongodb::bson::{oid::ObjectId, DateTime as BsonDateTime};
+
+use crate::models::{
+   message::{Message, ThreadEntry, DeliveryStatus, SpamInfo},
+   job::{DeliveryJob, JobState},
+   event::MessageEvent
+};
+
+pub use memory::MemoryStore;
+pub use mongo::MongoStore;
+pub use sql::SqlStore;
+
+#[derive(Debug)]
+pub struct StoreErr(pub String);
+
+impl fmt::Display for StoreErr {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "Store error: {}", self.0)
+   }
+}
+
+pub enum ConnCheck {
+   Ok,
+   Issue(StoreErr)
+}
+
+/// Backend agnostic message filter, every set criterion must match
+#[derive(Clone, Debug, Default)]
+pub struct MessageFilter {
+   pub read: Option<bool>,
+   pub archived: Option<bool>,
+   pub senders: Option<Vec<String>>,
+   //* Both bounds are exclusive
+   pub created_before: Option<DateTime<Utc>>,
+   pub created_after: Option<DateTime<Utc>>,
+   //* Inclusive on both ends
+   pub created_within: Option<(DateTime<Utc>, DateTime<Utc>)>,
+   //* Full-text query over subject, message and name
+   pub search: Option<String>,
+   //* Trashed messages are only ever listed on their own
+   pub trashed: bool,
+   //* Whether quarantined spam is kept (Some(true)), left out (Some(false)) or both
+   pub quarantined: Option<bool>,
+   //* What an admin labeled the message, spam (true) or not (false)
+   pub spam_label: Option<bool>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortField {
+   CreatedAt,
+   Sender,
+   Name,
+   Subject,
+   //* Text search score, only available along with a search query
+   Relevance
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SortValue {
+   Date(i64),
+   Text(String),
+   Null
+}
+
+impl SortField {
+   pub fn value_of(&self, msg: &Message) -> SortValue {
+      match self {
+         SortField::CreatedAt => msg.created_at
+            .map(|date| SortValue::Date(date.timestamp_millis()))
+            .unwrap_or(SortValue::Null),
+         SortField::Sender => SortValue::Text(msg.from.clone()),
+         SortField::Name => SortValue::Text(msg.name.clone()),
+         SortField::Subject => SortValue::Text(msg.subject.clone()),
+         SortField::Relevance => SortValue::Null
+      }
+   }
+}
+
+/// Keyset position: the sort value and id of the last message already listed
+#[derive(Clone, Debug)]
+pub struct Position {
+   pub value: SortValue,
+   pub id: ObjectId
+}
+
+pub struct ListQuery {
+   pub filter: MessageFilter,
+   pub sort: SortField,
+   pub descending: bool,
+   pub after: Option<Position>,
+   pub skip: u64,
+   pub limit: i64
+}
+
+/// A listed message, without its thread, along with its search score if any
+pub struct Listed {
+   pub msg: Message,
+   pub score: Option<f64>
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FlagsUpdate {
+   pub read: Option<bool>,
+   pub archived: Option<bool>
+}
+
+/// Which delivery status of a message a job reports on
+#[derive(Clone, Copy, Debug)]
+pub enum DeliveryTarget {
+   Owners,
+   Ack,
+   Reply(ObjectId)
+}
+
+#[async_trait]
+pub trait MessageStore: Send + Sync {
+   /// Stores a new message, returning its id
+   async fn insert(&self, msg: &Message) -> Result<ObjectId, StoreErr>;
+   async fn find(&self, id: ObjectId) -> Result<Option<Message>, StoreErr>;
+   /// Message whose thread holds an email with one of the given Message-IDs
+   async fn find_by_thread_email(&self, message_ids: &[String]) -> Result<Option<Message>, StoreErr>;
+   /// Imported message with the given source Message-ID or, failing that, content hash
+   async fn find_imported(&self, source_id: Option<&str>, content_hash: &str) -> Result<Option<ObjectId>, StoreErr>;
+   async fn list(&self, query: &ListQuery) -> Result<Vec<Listed>, StoreErr>;
+   async fn count(&self, filter: &MessageFilter) -> Result<u64, StoreErr>;
+   /// Sets the given flags, returning the message as it was before
+   async fn update_flags(&self, id: ObjectId, flags: FlagsUpdate) -> Result<Option<Message>, StoreErr>;
+   /// Deletes the messages for good, returning the ids that actually existed
+   async fn delete(&self, ids: &[ObjectId]) -> Result<Vec<ObjectId>, StoreErr>;
+   /// Moves the messages to the trash, returning the ids that weren't already there
+   async fn trash(&self, ids: &[ObjectId], now: BsonDateTime) -> Result<Vec<ObjectId>, StoreErr>;
+   /// Takes the messages out of the trash, returning the ids that were in it
+   async fn restore(&self, ids: &[ObjectId]) -> Result<Vec<ObjectId>, StoreErr>;
+   /// Deletes for good the messages trashed before `before`, returning their ids
+   async fn purge_trashed(&self, before: BsonDateTime) -> Result<Vec<ObjectId>, StoreErr>;
+   /// Every message sent from `email` whatever its case, trashed ones included
+   async fn ids_by_sender(&self, email: &str) -> Result<Vec<ObjectId>, StoreErr>;
+   /// Replaces the sender's address and name with the given alias, wherever they
+   /// appear in the messages and their threads. Returns the ids of the messages changed
+   async fn anonymize_sender(&self, email: &str, alias_email: &str, alias_name: &str) -> Result<Vec<ObjectId>, StoreErr>;
+   /// Replaces the spam verdict of the message, returns false when it doesn't exist
+   async fn set_spam(&self, id: ObjectId, spam: &SpamInfo) -> Result<bool, StoreErr>;
+   /// Appends an email to the thread, returns false when the message doesn't exist
+   async fn push_thread_entry(&self, id: ObjectId, entry: &ThreadEntry, mark_unread: bool) -> Result<bool, StoreErr>;
+   async fn set_delivery(&self, id: ObjectId, target: DeliveryTarget, status: &DeliveryStatus) -> Result<(), StoreErr>;
+   async fn check_conn(&self) -> ConnCheck;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct JobFilter {
+   pub state: Option<JobState>,
+   pub webhooks_only: bool,
+   pub endpoint: Option<String>,
+   pub message_ids: Option<Vec<ObjectId>>,
+   pub limit: Option<i64>
+}
+
+#[async_trait]
+pub trait JobStore: Send + Sync {
+   async fn insert(&self, job: &DeliveryJob) -> Result<(), StoreErr>;
+   /// Atomically takes the next due job, queued or with an expired lease, and leases it until `until`.
+   /// Taking over an expired lease counts the attempt it was leased for
+   async fn lease(&self, now: BsonDateTime, until: BsonDateTime) -> Result<Option<DeliveryJob>, StoreErr>;
+   /// Applies the outcome of an attempt to a leased job: state, attempts, run_at and last_error are
+   /// taken from `job`, the attempt is appended to its log. Only while the job still holds the lease
+   /// `job` was taken with, told apart by its `leased_until`. False when the lease was lost
+   async fn finish_attempt(&self, job: &DeliveryJob, error: Option<&str>, now: BsonDateTime) -> Result<bool, StoreErr>;
+   /// Latest updated first
+   async fn list(&self, filter: &JobFilter) -> Result<Vec<DeliveryJob>, StoreErr>;
+   /// Queues dead jobs again with a fresh attempts budget, returns how many were
+   async fn redrive(&self, ids: &[ObjectId], now: BsonDateTime) -> Result<u64, StoreErr>;
+   /// Drops every job of the given messages whatever its state, returns how many were
+   async fn delete_for_messages(&self, message_ids: &[ObjectId]) -> Result<u64, StoreErr>;
+}
+
+#[async_trait]
+pub trait EventStore: Send + Sync {
+   async fn insert(&self, event: &MessageEvent) -> Result<(), StoreErr>;
+   async fn find(&self, id: ObjectId) -> Result<Option<MessageEvent>, StoreErr>;
+   /// Events that happened after `last_id`, oldest first
+   async fn since(&self, last_id: ObjectId, limit: i64) -> Result<Vec<MessageEvent>, StoreErr>;
+   /// Every event of the given messages, oldest first
+   async fn for_messages(&self, message_ids: &[ObjectId]) -> Result<Vec<MessageEvent>, StoreErr>;
+   /// Drops every event of the given messages, returns how many were
+   async fn delete_for_messages(&self, message_ids: &[ObjectId]) -> Result<u64, StoreErr>;
+}
+
+/// Storage backends of the service, picked by `MESSAGE_STORE`
+#[derive(Clone)]
+pub struct Store {
+   pub messages: Arc<dyn MessageStore>,
+   pub jobs: Arc<dyn JobStore>,
+   pub events: Arc<dyn EventStore>
+}
+
+impl Store {
+   pub async fn from_env() -> Self {
+      match env::var("MESSAGE_STORE").as_deref() {
+         Ok("mongo") | Err(_) => Store::with_backend(Arc::new(MongoStore::init().await)),
+         Ok("sql") => Store::with_backend(Arc::new(SqlStore::connect().await)),
+         //* Nothing survives a restart, meant for tests and local development
+         Ok("memory") => {
+            warn!("Using the in-memory store, messages will be lost on shutdown");
+            Store::with_backend(Arc::new(MemoryStore::new()))
+         },
+         Ok(other) => panic!("MESSAGE_STORE must be \"mongo\", \"sql\" or \"memory\", got \"{}\"", other)
+      }
+   }
+
+   /// Empty in-memory store, for tests
+   #[cfg(test)]
+   pub fn memory() -> Self {
+      Store::with_backend(Arc::new(MemoryStore::new()))
+   }
+
+   fn with_backend<B: MessageStore + JobStore + EventStore + 'static>(backend: Arc<B>) -> Self {
+      Store {
+         messages: backend.clone(),
+         jobs: backend.clone(),
+         events: backend
+      }
+   }
+}
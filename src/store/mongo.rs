@@ -0,0 +1,504 @@
+use rocket::{async_trait, futures::TryStreamExt};
+use mongodb::{
+   bson::{doc, from_document, oid::ObjectId, to_bson, Bson, DateTime as BsonDateTime, Document},
+   options::{Collation, CollationStrength, DistinctOptions, FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+   error::Error as MongoError
+};
+
+use crate::{
+   mongo::{MessageCmsDb, ConnCheck as MongoConnCheck},
+   models::{
+      message::{Message, ThreadEntry, DeliveryStatus, SpamInfo},
+      job::{DeliveryJob, JobState, MAX_LOGGED_ATTEMPTS},
+      event::MessageEvent
+   }
+};
+use super::{*, memory::anonymize_thread};
+
+impl From<MongoError> for StoreErr {
+   fn from(err: MongoError) -> Self {
+      StoreErr(err.to_string())
+   }
+}
+
+//* Senders are matched regardless of case, like mail servers do
+fn sender_collation() -> Collation {
+   Collation::builder().locale("en").strength(CollationStrength::Secondary).build()
+}
+
+/// MongoDB backed storage, the default one
+pub struct MongoStore {
+   db: MessageCmsDb
+}
+
+impl MongoStore {
+   pub async fn init() -> Self {
+      MongoStore { db: MessageCmsDb::init().await }
+   }
+}
+
+fn message_filter(filter: &MessageFilter) -> Document {
+   let mut mongo_query_filters = Document::new();
+
+   if let Some(before) = filter.created_before {
+      mongo_query_filters.insert("createdAt", doc! { "$lt": BsonDateTime::from_chrono(before) });
+   } else if let Some(after) = filter.created_after {
+      mongo_query_filters.insert("createdAt", doc! { "$gt": BsonDateTime::from_chrono(after) });
+   } else if let Some((start, end)) = filter.created_within {
+      mongo_query_filters.insert("createdAt", doc! { "$gte": BsonDateTime::from_chrono(start), "$lte": BsonDateTime::from_chrono(end) });
+   }
+
+   if let Some(read) = filter.read {
+      mongo_query_filters.insert("read", doc! { "$eq": read });
+   }
+
+   if let Some(archived) = filter.archived {
+      mongo_query_filters.insert("archived", doc! { "$eq": archived });
+   }
+
+   if let Some(senders) = &filter.senders {
+      mongo_query_filters.insert("from", doc! { "$in": Bson::from(senders.clone()) });
+   }
+
+   //* Missing `deletedAt` fields match null too, messages predating the trash aren't in it
+   match filter.trashed {
+      true => mongo_query_filters.insert("deletedAt", doc! { "$ne": Bson::Null }),
+      false => mongo_query_filters.insert("deletedAt", doc! { "$eq": Bson::Null })
+   };
+
+   //* Messages scored before the spam pipeline existed have no `spam` at all
+   match filter.quarantined {
+      Some(true) => { mongo_query_filters.insert("spam.quarantined", doc! { "$eq": true }); },
+      Some(false) => { mongo_query_filters.insert("spam.quarantined", doc! { "$ne": true }); },
+      None => {}
+   }
+
+   if let Some(label) = filter.spam_label {
+      mongo_query_filters.insert("spam.label", doc! { "$eq": label });
+   }
+
+   //* Backed by the messages text index, see `MessageCmsDb::init`
+   if let Some(search) = &filter.search {
+      mongo_query_filters.insert("$text", doc! { "$search": search.clone() });
+   }
+
+   mongo_query_filters
+}
+
+fn sort_db_field(field: SortField) -> &'static str {
+   match field {
+      SortField::CreatedAt => "createdAt",
+      SortField::Sender => "from",
+      SortField::Name => "name",
+      SortField::Subject => "subject",
+      SortField::Relevance => "score"
+   }
+}
+
+fn sort_value_bson(value: &SortValue) -> Bson {
+   match value {
+      SortValue::Date(millis) => Bson::DateTime(BsonDateTime::from_millis(*millis)),
+      SortValue::Text(text) => Bson::String(text.clone()),
+      SortValue::Null => Bson::Null
+   }
+}
+
+#[async_trait]
+impl MessageStore for MongoStore {
+   async fn insert(&self, msg: &Message) -> Result<ObjectId, StoreErr> {
+      let res = self.db.get_msg_col().insert_one(msg, None).await?;
+
+      res.inserted_id.as_object_id()
+         .ok_or_else(|| StoreErr("Inserted message has no ObjectId".to_string()))
+   }
+
+   async fn find(&self, id: ObjectId) -> Result<Option<Message>, StoreErr> {
+      Ok(self.db.get_msg_col().find_one(doc! { "_id": { "$eq": id } }, None).await?)
+   }
+
+   async fn find_by_thread_email(&self, message_ids: &[String]) -> Result<Option<Message>, StoreErr> {
+      let refs = Bson::from(message_ids.to_vec());
+
+      Ok(self.db.get_msg_col().find_one(doc! { "thread.messageId": { "$in": refs } }, None).await?)
+   }
+
+   async fn find_imported(&self, source_id: Option<&str>, content_hash: &str) -> Result<Option<ObjectId>, StoreErr> {
+      let mut conds = vec![ doc! { "contentHash": content_hash } ];
+      if let Some(source_id) = source_id {
+         conds.push(doc! { "sourceId": source_id });
+      }
+
+      let msg = self.db.get_msg_col().find_one(doc! { "$or": conds }, None).await?;
+      Ok(msg.and_then(|msg| msg.id))
+   }
+
+   async fn list(&self, query: &ListQuery) -> Result<Vec<Listed>, StoreErr> {
+      let mut filter = message_filter(&query.filter);
+
+      //* `$text` has to stay at the top level of the filter, hence the `$and`
+      if let Some(position) = &query.after {
+         let op = if query.descending { "$lt" } else { "$gt" };
+         let field = sort_db_field(query.sort);
+         let value = sort_value_bson(&position.value);
+
+         filter.insert("$and", vec![ doc! {
+            "$or": [
+               { field: { op: value.clone() } },
+               { field: value, "_id": { op: position.id } }
+            ]
+         } ]);
+      }
+
+      //* Ties are broken by id so that every document has a stable position
+      let direction = if query.descending { -1 } else { 1 };
+      let sort = match query.sort {
+         SortField::Relevance => doc! { "score": { "$meta": "textScore" }, "_id": 1 },
+         field => doc! { sort_db_field(field): direction, "_id": direction }
+      };
+      //* Threads can grow large and are never part of listings
+      let mut projection = doc! { "thread": 0 };
+      if query.filter.search.is_some() {
+         projection = doc! { "thread": 0, "score": { "$meta": "textScore" } };
+      }
+
+      let options = FindOptions::builder()
+         .sort(sort)
+         .projection(projection)
+         .skip(query.skip)
+         .limit(query.limit)
+         .build();
+
+      let docs: Vec<Document> = self.db.get_msg_col().clone_with_type::<Document>()
+         .find(filter, options).await?
+         .try_collect().await?;
+
+      let mut listed = Vec::with_capacity(docs.len());
+      for doc in docs {
+         let score = doc.get_f64("score").ok();
+         match from_document::<Message>(doc) {
+            Ok(msg) => listed.push(Listed { msg, score }),
+            Err(err) => warn!("Failed to deserialize a doc from MongoDB. Error: {:?}", err)
+         }
+      }
+
+      Ok(listed)
+   }
+
+   async fn count(&self, filter: &MessageFilter) -> Result<u64, StoreErr> {
+      Ok(self.db.get_msg_col().count_documents(message_filter(filter), None).await?)
+   }
+
+   async fn update_flags(&self, id: ObjectId, flags: FlagsUpdate) -> Result<Option<Message>, StoreErr> {
+      let mut set = Document::new();
+      if let Some(read) = flags.read {
+         set.insert("read", read);
+      }
+      if let Some(archived) = flags.archived {
+         set.insert("archived", archived);
+      }
+
+      //* Returns the document as it was before the update by default
+      let query = doc! { "_id": { "$eq": id } };
+      Ok(self.db.get_msg_col().find_one_and_update(query, doc! { "$set": set }, None).await?)
+   }
+
+   async fn delete(&self, ids: &[ObjectId]) -> Result<Vec<ObjectId>, StoreErr> {
+      let delete_filter = doc! { "_id": { "$in": Bson::from_iter(ids.iter().copied()) } };
+
+      //* Deleted documents can't be told apart afterwards, they are noted down first
+      let existing = self.db.get_msg_col().distinct("_id", delete_filter.clone(), None).await?
+         .into_iter()
+         .filter_map(|id| id.as_object_id())
+         .collect::<Vec<ObjectId>>();
+      self.db.get_msg_col().delete_many(delete_filter, None).await?;
+
+      Ok(existing)
+   }
+
+   async fn trash(&self, ids: &[ObjectId], now: BsonDateTime) -> Result<Vec<ObjectId>, StoreErr> {
+      let trash_filter = doc! {
+         "_id": { "$in": Bson::from_iter(ids.iter().copied()) },
+         "deletedAt": { "$eq": Bson::Null }
+      };
+
+      let trashed = self.db.get_msg_col().distinct("_id", trash_filter.clone(), None).await?
+         .into_iter()
+         .filter_map(|id| id.as_object_id())
+         .collect::<Vec<ObjectId>>();
+      self.db.get_msg_col().update_many(trash_filter, doc! { "$set": { "deletedAt": now } }, None).await?;
+
+      Ok(trashed)
+   }
+
+   async fn restore(&self, ids: &[ObjectId]) -> Result<Vec<ObjectId>, StoreErr> {
+      let restore_filter = doc! {
+         "_id": { "$in": Bson::from_iter(ids.iter().copied()) },
+         "deletedAt": { "$ne": Bson::Null }
+      };
+
+      let restored = self.db.get_msg_col().distinct("_id", restore_filter.clone(), None).await?
+         .into_iter()
+         .filter_map(|id| id.as_object_id())
+         .collect::<Vec<ObjectId>>();
+      self.db.get_msg_col().update_many(restore_filter, doc! { "$unset": { "deletedAt": "" } }, None).await?;
+
+      Ok(restored)
+   }
+
+   async fn purge_trashed(&self, before: BsonDateTime) -> Result<Vec<ObjectId>, StoreErr> {
+      let expired = self.db.get_msg_col().distinct("_id", doc! { "deletedAt": { "$lt": before } }, None).await?
+         .into_iter()
+         .filter_map(|id| id.as_object_id())
+         .collect::<Vec<ObjectId>>();
+
+      //* Restored in between means it's no longer due, hence the repeated condition
+      let purge_filter = doc! {
+         "_id": { "$in": Bson::from_iter(expired.iter().copied()) },
+         "deletedAt": { "$lt": before }
+      };
+      self.db.get_msg_col().delete_many(purge_filter, None).await?;
+
+      //* Those still around were restored in between, only the others are gone
+      let kept = self.db.get_msg_col().distinct("_id", doc! { "_id": { "$in": Bson::from_iter(expired.iter().copied()) } }, None).await?
+         .into_iter()
+         .filter_map(|id| id.as_object_id())
+         .collect::<Vec<ObjectId>>();
+
+      Ok(expired.into_iter().filter(|id| !kept.contains(id)).collect())
+   }
+
+   async fn ids_by_sender(&self, email: &str) -> Result<Vec<ObjectId>, StoreErr> {
+      let options = DistinctOptions::builder().collation(sender_collation()).build();
+
+      Ok(self.db.get_msg_col().distinct("_id", doc! { "from": { "$eq": email } }, options).await?
+         .into_iter()
+         .filter_map(|id| id.as_object_id())
+         .collect())
+   }
+
+   async fn anonymize_sender(&self, email: &str, alias_email: &str, alias_name: &str) -> Result<Vec<ObjectId>, StoreErr> {
+      let anonymized = self.ids_by_sender(email).await?;
+      if anonymized.is_empty() {
+         return Ok(anonymized);
+      }
+
+      let ids = Bson::from_iter(anonymized.iter().copied());
+
+      //* Threads are rewritten here, the address can hide in a comma separated list of recipients
+      let threaded: Vec<Message> = self.db.get_msg_col()
+         .find(doc! { "_id": { "$in": ids.clone() }, "thread": { "$exists": true } }, None).await?
+         .try_collect().await?;
+      for mut msg in threaded {
+         anonymize_thread(&mut msg.thread, email, alias_email, alias_name);
+         let thread = to_bson(&msg.thread).map_err(|err| StoreErr(err.to_string()))?;
+
+         if let Some(id) = msg.id {
+            self.db.get_msg_col().update_one(doc! { "_id": { "$eq": id } }, doc! { "$set": { "thread": thread } }, None).await?;
+         }
+      }
+
+      let update_data = doc! { "$set": { "from": alias_email, "name": alias_name } };
+      self.db.get_msg_col().update_many(doc! { "_id": { "$in": ids } }, update_data, None).await?;
+
+      Ok(anonymized)
+   }
+
+   async fn set_spam(&self, id: ObjectId, spam: &SpamInfo) -> Result<bool, StoreErr> {
+      let spam = to_bson(spam).map_err(|err| StoreErr(err.to_string()))?;
+
+      let res = self.db.get_msg_col().update_one(doc! { "_id": { "$eq": id } }, doc! { "$set": { "spam": spam } }, None).await?;
+      Ok(res.matched_count > 0)
+   }
+
+   async fn push_thread_entry(&self, id: ObjectId, entry: &ThreadEntry, mark_unread: bool) -> Result<bool, StoreErr> {
+      let entry_bson = to_bson(entry).map_err(|err| StoreErr(err.to_string()))?;
+
+      let query = doc! { "_id": { "$eq": id } };
+      let mut update_data = doc! { "$push": { "thread": entry_bson } };
+      if mark_unread {
+         update_data.insert("$set", doc! { "read": false });
+      }
+
+      let res = self.db.get_msg_col().update_one(query, update_data, None).await?;
+      Ok(res.matched_count > 0)
+   }
+
+   async fn set_delivery(&self, id: ObjectId, target: DeliveryTarget, status: &DeliveryStatus) -> Result<(), StoreErr> {
+      let status = to_bson(status).map_err(|err| StoreErr(err.to_string()))?;
+
+      let (query, update_data) = match target {
+         DeliveryTarget::Owners => (doc! { "_id": { "$eq": id } }, doc! { "$set": { "delivery": status } }),
+         DeliveryTarget::Ack => (doc! { "_id": { "$eq": id } }, doc! { "$set": { "ack": status } }),
+         DeliveryTarget::Reply(entry_id) => (
+            doc! { "_id": { "$eq": id }, "thread.id": { "$eq": entry_id } },
+            doc! { "$set": { "thread.$.delivery": status } }
+         )
+      };
+
+      self.db.get_msg_col().update_one(query, update_data, None).await?;
+      Ok(())
+   }
+
+   async fn check_conn(&self) -> ConnCheck {
+      match self.db.check_conn().await {
+         MongoConnCheck::Ok => ConnCheck::Ok,
+         MongoConnCheck::Issue(err) => ConnCheck::Issue(err.into())
+      }
+   }
+}
+
+/// `$push` operand appending an attempt to the job log, keeping only the latest ones
+fn log_attempt(attempt: Document) -> Document {
+   doc! { "log": { "$each": [ attempt ], "$slice": -MAX_LOGGED_ATTEMPTS } }
+}
+
+#[async_trait]
+impl JobStore for MongoStore {
+   async fn insert(&self, job: &DeliveryJob) -> Result<(), StoreErr> {
+      self.db.get_job_col().insert_one(job, None).await?;
+      Ok(())
+   }
+
+   async fn lease(&self, now: BsonDateTime, until: BsonDateTime) -> Result<Option<DeliveryJob>, StoreErr> {
+      let filter = doc! {
+         "$or": [
+            { "state": JobState::Queued.as_str(), "runAt": { "$lte": now } },
+            { "state": JobState::Leased.as_str(), "leasedUntil": { "$lte": now } }
+         ]
+      };
+      //* A lease that ran out is an attempt that never finished (e.g.: the worker died mid-send)
+      let update_data = vec![ doc! {
+         "$set": {
+            "attempts": {
+               "$cond": [ { "$eq": [ "$state", JobState::Leased.as_str() ] }, { "$add": [ "$attempts", 1 ] }, "$attempts" ]
+            },
+            "state": JobState::Leased.as_str(),
+            "leasedUntil": until,
+            "updatedAt": now
+         }
+      } ];
+      let options = FindOneAndUpdateOptions::builder()
+         .sort(doc! { "runAt": 1 })
+         .return_document(ReturnDocument::After)
+         .build();
+
+      Ok(self.db.get_job_col().find_one_and_update(filter, update_data, options).await?)
+   }
+
+   async fn finish_attempt(&self, job: &DeliveryJob, error: Option<&str>, now: BsonDateTime) -> Result<bool, StoreErr> {
+      //* Every lease ends later than the one it took over, so `leasedUntil` tells them apart
+      let query = doc! { "_id": job.id, "state": JobState::Leased.as_str(), "leasedUntil": job.leased_until };
+
+      let mut set = doc! {
+         "state": job.state.as_str(),
+         "attempts": job.attempts,
+         "runAt": job.run_at,
+         "updatedAt": now
+      };
+      let mut unset = doc! { "leasedUntil": "" };
+      match &job.last_error {
+         Some(last_error) => { set.insert("lastError", last_error.clone()); },
+         None => { unset.insert("lastError", ""); }
+      }
+
+      let mut attempt = doc! { "at": now };
+      if let Some(error) = error {
+         attempt.insert("error", error);
+      }
+
+      let update_data = doc! {
+         "$set": set,
+         "$unset": unset,
+         "$push": log_attempt(attempt)
+      };
+
+      let res = self.db.get_job_col().update_one(query, update_data, None).await?;
+      Ok(res.matched_count == 1)
+   }
+
+   async fn list(&self, filter: &JobFilter) -> Result<Vec<DeliveryJob>, StoreErr> {
+      let mut query = Document::new();
+      if let Some(state) = filter.state {
+         query.insert("state", state.as_str());
+      }
+      if filter.webhooks_only {
+         query.insert("kind.type", "webhook");
+      }
+      if let Some(endpoint) = &filter.endpoint {
+         query.insert("kind.endpoint", endpoint.clone());
+      }
+      if let Some(message_ids) = &filter.message_ids {
+         query.insert("messageId", doc! { "$in": Bson::from_iter(message_ids.iter().copied()) });
+      }
+
+      let options = FindOptions::builder()
+         .sort(doc! { "updatedAt": -1 })
+         .limit(filter.limit)
+         .build();
+
+      Ok(self.db.get_job_col().find(query, options).await?
+         .try_collect().await?)
+   }
+
+   async fn redrive(&self, ids: &[ObjectId], now: BsonDateTime) -> Result<u64, StoreErr> {
+      let query = doc! {
+         "_id": { "$in": Bson::from_iter(ids.iter().copied()) },
+         "state": JobState::Dead.as_str()
+      };
+      let update_data = doc! {
+         "$set": {
+            "state": JobState::Queued.as_str(),
+            "attempts": 0,
+            "runAt": now,
+            "updatedAt": now
+         }
+      };
+
+      let res = self.db.get_job_col().update_many(query, update_data, None).await?;
+      Ok(res.modified_count)
+   }
+
+   async fn delete_for_messages(&self, message_ids: &[ObjectId]) -> Result<u64, StoreErr> {
+      let query = doc! { "messageId": { "$in": Bson::from_iter(message_ids.iter().copied()) } };
+
+      Ok(self.db.get_job_col().delete_many(query, None).await?.deleted_count)
+   }
+}
+
+#[async_trait]
+impl EventStore for MongoStore {
+   async fn insert(&self, event: &MessageEvent) -> Result<(), StoreErr> {
+      self.db.get_event_col().insert_one(event, None).await?;
+      Ok(())
+   }
+
+   async fn find(&self, id: ObjectId) -> Result<Option<MessageEvent>, StoreErr> {
+      Ok(self.db.get_event_col().find_one(doc! { "_id": id }, None).await?)
+   }
+
+   async fn since(&self, last_id: ObjectId, limit: i64) -> Result<Vec<MessageEvent>, StoreErr> {
+      let options = FindOptions::builder()
+         .sort(doc! { "_id": 1 })
+         .limit(limit)
+         .build();
+
+      Ok(self.db.get_event_col().find(doc! { "_id": { "$gt": last_id } }, options).await?
+         .try_collect().await?)
+   }
+
+   async fn for_messages(&self, message_ids: &[ObjectId]) -> Result<Vec<MessageEvent>, StoreErr> {
+      let query = doc! { "messageId": { "$in": Bson::from_iter(message_ids.iter().copied()) } };
+      let options = FindOptions::builder()
+         .sort(doc! { "_id": 1 })
+         .build();
+
+      Ok(self.db.get_event_col().find(query, options).await?
+         .try_collect().await?)
+   }
+
+   async fn delete_for_messages(&self, message_ids: &[ObjectId]) -> Result<u64, StoreErr> {
+      let query = doc! { "messageId": { "$in": Bson::from_iter(message_ids.iter().copied()) } };
+
+      Ok(self.db.get_event_col().delete_many(query, None).await?.deleted_count)
+   }
+}
@@ -0,0 +1,1025 @@
+use std::{env, str::FromStr};
+use chrono::Utc;
+use rocket::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{
+   any::{Any, AnyArguments, AnyConnectOptions, AnyKind, AnyPool, AnyPoolOptions, AnyRow},
+   query::Query,
+   Row
+};
+use mongodb::bson::{oid::ObjectId, DateTime as BsonDateTime};
+
+use crate::models::{
+   message::{Message, ThreadEntry, DeliveryStatus, SpamInfo},
+   job::{DeliveryJob, JobKind, JobState, JobAttempt, MAX_LOGGED_ATTEMPTS},
+   event::{EventKind, MessageEvent}
+};
+use super::{*, memory::{list_messages, count_messages, anonymize_thread}};
+
+/// Schema changes, applied in order and only once. Released migrations must
+/// never be edited, add a new one instead
+const MIGRATIONS: [(i64, &[&str]); 4] = [
+   (1, &[
+      "CREATE TABLE messages (
+         id TEXT PRIMARY KEY,
+         created_at BIGINT,
+         sender TEXT NOT NULL,
+         name TEXT NOT NULL,
+         subject TEXT NOT NULL,
+         message TEXT NOT NULL,
+         locale TEXT,
+         is_read BOOLEAN NOT NULL,
+         is_archived BOOLEAN NOT NULL,
+         delivery TEXT,
+         ack TEXT,
+         thread TEXT NOT NULL
+      )",
+      "CREATE INDEX messages_created_at ON messages (created_at)",
+      "CREATE INDEX messages_sender ON messages (sender)",
+      //* Message-IDs of the thread emails, so inbound replies can be matched
+      "CREATE TABLE thread_emails (
+         email_id TEXT NOT NULL,
+         message_id TEXT NOT NULL
+      )",
+      "CREATE INDEX thread_emails_email_id ON thread_emails (email_id)",
+      "CREATE INDEX thread_emails_message_id ON thread_emails (message_id)",
+      "CREATE TABLE delivery_jobs (
+         id TEXT PRIMARY KEY,
+         message_id TEXT NOT NULL,
+         kind TEXT NOT NULL,
+         kind_type TEXT NOT NULL,
+         endpoint TEXT,
+         state TEXT NOT NULL,
+         attempts BIGINT NOT NULL,
+         max_attempts BIGINT NOT NULL,
+         run_at BIGINT NOT NULL,
+         leased_until BIGINT,
+         last_error TEXT,
+         log TEXT NOT NULL,
+         created_at BIGINT NOT NULL,
+         updated_at BIGINT NOT NULL
+      )",
+      "CREATE INDEX delivery_jobs_lease ON delivery_jobs (state, run_at)",
+      "CREATE TABLE message_events (
+         id TEXT PRIMARY KEY,
+         kind TEXT NOT NULL,
+         message_id TEXT NOT NULL,
+         occurred_at BIGINT NOT NULL,
+         data TEXT NOT NULL
+      )",
+      "CREATE INDEX message_events_occurred_at ON message_events (occurred_at)"
+   ]),
+   (2, &[
+      "ALTER TABLE messages ADD COLUMN deleted_at BIGINT",
+      "CREATE INDEX messages_deleted_at ON messages (deleted_at)"
+   ]),
+   (3, &[
+      "ALTER TABLE messages ADD COLUMN source_id TEXT",
+      "ALTER TABLE messages ADD COLUMN content_hash TEXT",
+      "CREATE INDEX messages_source_id ON messages (source_id)",
+      "CREATE INDEX messages_content_hash ON messages (content_hash)"
+   ]),
+   //* The verdict itself is JSON, the flags are copied out of it to be filtered on
+   (4, &[
+      "ALTER TABLE messages ADD COLUMN spam TEXT",
+      "ALTER TABLE messages ADD COLUMN quarantined BOOLEAN NOT NULL DEFAULT FALSE",
+      "ALTER TABLE messages ADD COLUMN spam_label BOOLEAN",
+      "CREATE INDEX messages_quarantined ON messages (quarantined)"
+   ])
+];
+
+const MESSAGE_COLS: &str = "id, created_at, sender, name, subject, message, locale, is_read, is_archived, delivery, ack, deleted_at, source_id, content_hash, spam";
+const JOB_COLS: &str = "id, message_id, kind, state, attempts, max_attempts, run_at, leased_until, last_error, log, created_at, updated_at";
+//* Another worker may lease the picked job first, it is then picked again
+const LEASE_RETRIES: usize = 3;
+
+impl From<sqlx::Error> for StoreErr {
+   fn from(err: sqlx::Error) -> Self {
+      StoreErr(err.to_string())
+   }
+}
+
+#[derive(Clone)]
+enum SqlArg {
+   Int(i64),
+   Text(String),
+   Bool(bool)
+}
+
+/// Positional parameters of a query built on the fly
+#[derive(Default)]
+struct Params(Vec<SqlArg>);
+
+impl Params {
+   /// Adds a parameter, returning its placeholder
+   fn add(&mut self, arg: SqlArg) -> String {
+      self.0.push(arg);
+      format!("${}", self.0.len())
+   }
+
+   fn list(&mut self, args: impl Iterator<Item = SqlArg>) -> String {
+      args.map(|arg| self.add(arg)).collect::<Vec<String>>().join(", ")
+   }
+
+   fn bind<'q>(self, mut query: Query<'q, Any, AnyArguments<'q>>) -> Query<'q, Any, AnyArguments<'q>> {
+      for arg in self.0 {
+         query = match arg {
+            SqlArg::Int(val) => query.bind(val),
+            SqlArg::Text(val) => query.bind(val),
+            SqlArg::Bool(val) => query.bind(val)
+         };
+      }
+
+      query
+   }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, StoreErr> {
+   serde_json::to_string(value).map_err(|err| StoreErr(err.to_string()))
+}
+
+fn from_json<T: DeserializeOwned>(raw: &str) -> Result<T, StoreErr> {
+   serde_json::from_str(raw).map_err(|err| StoreErr(err.to_string()))
+}
+
+fn opt_json<T: Serialize>(value: &Option<T>) -> Result<Option<String>, StoreErr> {
+   value.as_ref().map(to_json).transpose()
+}
+
+fn parse_oid(hex: &str) -> Result<ObjectId, StoreErr> {
+   ObjectId::parse_str(hex).map_err(|err| StoreErr(err.to_string()))
+}
+
+fn message_from_row(row: &AnyRow, with_thread: bool) -> Result<Message, StoreErr> {
+   Ok(Message {
+      id: Some(parse_oid(&row.try_get::<String, _>("id")?)?),
+      created_at: row.try_get::<Option<i64>, _>("created_at")?.map(BsonDateTime::from_millis),
+      from: row.try_get("sender")?,
+      name: row.try_get("name")?,
+      subject: row.try_get("subject")?,
+      message: row.try_get("message")?,
+      locale: row.try_get("locale")?,
+      read: row.try_get("is_read")?,
+      archived: row.try_get("is_archived")?,
+      delivery: row.try_get::<Option<String>, _>("delivery")?.as_deref().map(from_json).transpose()?,
+      ack: row.try_get::<Option<String>, _>("ack")?.as_deref().map(from_json).transpose()?,
+      thread: match with_thread {
+         true => from_json(&row.try_get::<String, _>("thread")?)?,
+         false => Vec::new()
+      },
+      deleted_at: row.try_get::<Option<i64>, _>("deleted_at")?.map(BsonDateTime::from_millis),
+      source_id: row.try_get("source_id")?,
+      content_hash: row.try_get("content_hash")?,
+      spam: row.try_get::<Option<String>, _>("spam")?.as_deref().map(from_json).transpose()?
+   })
+}
+
+fn job_state(name: &str) -> Result<JobState, StoreErr> {
+   match name {
+      "queued" => Ok(JobState::Queued),
+      "leased" => Ok(JobState::Leased),
+      "done" => Ok(JobState::Done),
+      "dead" => Ok(JobState::Dead),
+      _ => Err(StoreErr(format!("Unknown job state \"{}\"", name)))
+   }
+}
+
+fn job_from_row(row: &AnyRow) -> Result<DeliveryJob, StoreErr> {
+   Ok(DeliveryJob {
+      id: Some(parse_oid(&row.try_get::<String, _>("id")?)?),
+      message_id: parse_oid(&row.try_get::<String, _>("message_id")?)?,
+      kind: from_json(&row.try_get::<String, _>("kind")?)?,
+      state: job_state(&row.try_get::<String, _>("state")?)?,
+      attempts: row.try_get::<i64, _>("attempts")? as u32,
+      max_attempts: row.try_get::<i64, _>("max_attempts")? as u32,
+      run_at: BsonDateTime::from_millis(row.try_get("run_at")?),
+      leased_until: row.try_get::<Option<i64>, _>("leased_until")?.map(BsonDateTime::from_millis),
+      last_error: row.try_get("last_error")?,
+      log: from_json(&row.try_get::<String, _>("log")?)?,
+      created_at: BsonDateTime::from_millis(row.try_get("created_at")?),
+      updated_at: BsonDateTime::from_millis(row.try_get("updated_at")?)
+   })
+}
+
+fn event_from_row(row: &AnyRow) -> Result<MessageEvent, StoreErr> {
+   let kind = row.try_get::<String, _>("kind")?;
+
+   Ok(MessageEvent {
+      id: parse_oid(&row.try_get::<String, _>("id")?)?,
+      kind: EventKind::from_name(&kind).ok_or_else(|| StoreErr(format!("Unknown event kind \"{}\"", kind)))?,
+      message_id: parse_oid(&row.try_get::<String, _>("message_id")?)?,
+      occurred_at: BsonDateTime::from_millis(row.try_get("occurred_at")?),
+      data: from_json(&row.try_get::<String, _>("data")?)?
+   })
+}
+
+//* Same tag serde gives the kind, kept in its own column to filter on
+fn job_kind_type(kind: &JobKind) -> &'static str {
+   match kind {
+      JobKind::OwnerNotification => "owner_notification",
+      JobKind::Acknowledgement => "acknowledgement",
+      JobKind::Reply { .. } => "reply",
+      JobKind::Webhook { .. } => "webhook"
+   }
+}
+
+/// Same semantics as the Mongo filter, except for the text search which is
+/// left to the caller
+fn message_where(filter: &MessageFilter, params: &mut Params) -> String {
+   let mut conds = vec![ "1 = 1".to_string() ];
+
+   if let Some(before) = filter.created_before {
+      conds.push(format!("created_at < {}", params.add(SqlArg::Int(before.timestamp_millis()))));
+   } else if let Some(after) = filter.created_after {
+      conds.push(format!("created_at > {}", params.add(SqlArg::Int(after.timestamp_millis()))));
+   } else if let Some((start, end)) = filter.created_within {
+      conds.push(format!(
+         "created_at >= {} AND created_at <= {}",
+         params.add(SqlArg::Int(start.timestamp_millis())), params.add(SqlArg::Int(end.timestamp_millis()))
+      ));
+   }
+
+   if let Some(read) = filter.read {
+      conds.push(format!("is_read = {}", params.add(SqlArg::Bool(read))));
+   }
+
+   if let Some(archived) = filter.archived {
+      conds.push(format!("is_archived = {}", params.add(SqlArg::Bool(archived))));
+   }
+
+   match &filter.senders {
+      Some(senders) if senders.is_empty() => conds.push("1 = 0".to_string()),
+      Some(senders) => conds.push(format!("sender IN ({})", params.list(senders.iter().cloned().map(SqlArg::Text)))),
+      None => {}
+   }
+
+   match filter.trashed {
+      true => conds.push("deleted_at IS NOT NULL".to_string()),
+      false => conds.push("deleted_at IS NULL".to_string())
+   }
+
+   if let Some(quarantined) = filter.quarantined {
+      conds.push(format!("quarantined = {}", params.add(SqlArg::Bool(quarantined))));
+   }
+
+   if let Some(label) = filter.spam_label {
+      conds.push(format!("spam_label = {}", params.add(SqlArg::Bool(label))));
+   }
+
+   conds.join(" AND ")
+}
+
+/// SQLite for a single node or PostgreSQL for a shared database, picked by
+/// the `SQL_DATABASE_URL` scheme
+pub struct SqlStore {
+   pool: AnyPool,
+   kind: AnyKind,
+   events_retention_days: i64
+}
+
+impl SqlStore {
+   pub async fn connect() -> Self {
+      #[allow(non_snake_case)]
+      let SQL_DATABASE_URL = match env::var("SQL_DATABASE_URL") {
+         Ok(val) => val,
+         Err(_) => panic!("SQL_DATABASE_URL environment must be set when MESSAGE_STORE is \"sql\"")
+      };
+
+      let max_connections = match env::var("SQL_MAX_CONNECTIONS") {
+         Ok(val) => match val.parse::<u32>() {
+            Ok(max) if max > 0 => max,
+            _ => panic!("SQL_MAX_CONNECTIONS must be a positive integer")
+         },
+         Err(_) => 5
+      };
+      let events_retention_days = match env::var("EVENTS_RETENTION_DAYS") {
+         Ok(val) => val.parse::<i64>().expect("EVENTS_RETENTION_DAYS must be a positive integer"),
+         Err(_) => 7
+      };
+
+      let options = AnyConnectOptions::from_str(&SQL_DATABASE_URL)
+         .expect("Failed to parse the SQL database URL");
+      let kind = options.kind();
+
+      let pool = match AnyPoolOptions::new().max_connections(max_connections).connect_with(options).await {
+         Ok(pool) => pool,
+         Err(err) => panic!("Failed to connect to the SQL database: {}", err)
+      };
+
+      let store = SqlStore { pool, kind, events_retention_days };
+      if let Err(err) = store.migrate().await {
+         panic!("Failed migrating the SQL database: {}", err);
+      }
+
+      store
+   }
+
+   async fn migrate(&self) -> Result<(), StoreErr> {
+      sqlx::query("CREATE TABLE IF NOT EXISTS schema_migrations (version BIGINT PRIMARY KEY, applied_at BIGINT NOT NULL)")
+         .execute(&self.pool).await?;
+
+      let applied = sqlx::query("SELECT version FROM schema_migrations")
+         .fetch_all(&self.pool).await?
+         .iter()
+         .map(|row| row.try_get::<i64, _>("version"))
+         .collect::<Result<Vec<i64>, sqlx::Error>>()?;
+
+      for (version, statements) in MIGRATIONS.iter() {
+         if applied.contains(version) {
+            continue;
+         }
+
+         let mut tx = self.pool.begin().await?;
+         for statement in statements.iter() {
+            sqlx::query(statement).execute(&mut tx).await?;
+         }
+         sqlx::query("INSERT INTO schema_migrations (version, applied_at) VALUES ($1, $2)")
+            .bind(*version)
+            .bind(Utc::now().timestamp_millis())
+            .execute(&mut tx).await?;
+         tx.commit().await?;
+
+         info!("Applied SQL migration {}", version);
+      }
+
+      Ok(())
+   }
+
+   //* Postgres compares text with the database locale, Mongo and SQLite byte by byte
+   fn text_col(&self, col: &str) -> String {
+      match self.kind {
+         AnyKind::Postgres => format!("{} COLLATE \"C\"", col),
+         _ => col.to_string()
+      }
+   }
+
+   //* SQLite locks the whole database on write instead
+   fn lock_rows(&self) -> &'static str {
+      match self.kind {
+         AnyKind::Postgres => " FOR UPDATE",
+         _ => ""
+      }
+   }
+
+   fn sort_expr(&self, field: SortField) -> String {
+      match field {
+         //* Missing dates sort first, same as Mongo's nulls
+         SortField::CreatedAt => "COALESCE(created_at, 0)".to_string(),
+         SortField::Sender => self.text_col("sender"),
+         SortField::Name => self.text_col("name"),
+         SortField::Subject => self.text_col("subject"),
+         SortField::Relevance => self.text_col("id")
+      }
+   }
+
+   async fn fetch_thread(&self, tx: &mut sqlx::Transaction<'_, Any>, id: ObjectId) -> Result<Option<Vec<ThreadEntry>>, StoreErr> {
+      let sql = format!("SELECT thread FROM messages WHERE id = $1{}", self.lock_rows());
+
+      match sqlx::query(&sql).bind(id.to_hex()).fetch_optional(&mut *tx).await? {
+         Some(row) => Ok(Some(from_json(&row.try_get::<String, _>("thread")?)?)),
+         None => Ok(None)
+      }
+   }
+
+   /// Deletes the messages along with their thread emails
+   async fn delete_rows(&self, tx: &mut sqlx::Transaction<'_, Any>, ids: &[ObjectId]) -> Result<(), StoreErr> {
+      if ids.is_empty() {
+         return Ok(());
+      }
+      let hex_ids = || ids.iter().map(|id| SqlArg::Text(id.to_hex()));
+
+      let mut params = Params::default();
+      let sql = format!("DELETE FROM messages WHERE id IN ({})", params.list(hex_ids()));
+      params.bind(sqlx::query(&sql)).execute(&mut *tx).await?;
+
+      let mut params = Params::default();
+      let sql = format!("DELETE FROM thread_emails WHERE message_id IN ({})", params.list(hex_ids()));
+      params.bind(sqlx::query(&sql)).execute(&mut *tx).await?;
+
+      Ok(())
+   }
+
+   /// Those of the given messages matching `cond`, locked until the transaction ends
+   async fn select_ids(&self, tx: &mut sqlx::Transaction<'_, Any>, ids: &[ObjectId], cond: &str) -> Result<Vec<ObjectId>, StoreErr> {
+      if ids.is_empty() {
+         return Ok(Vec::new());
+      }
+
+      let mut params = Params::default();
+      let sql = format!(
+         "SELECT id FROM messages WHERE id IN ({}) AND {}{}",
+         params.list(ids.iter().map(|id| SqlArg::Text(id.to_hex()))), cond, self.lock_rows()
+      );
+
+      params.bind(sqlx::query(&sql)).fetch_all(&mut *tx).await?
+         .iter()
+         .map(|row| parse_oid(&row.try_get::<String, _>("id")?))
+         .collect()
+   }
+
+   async fn find_job(&self, id: &str) -> Result<Option<DeliveryJob>, StoreErr> {
+      let sql = format!("SELECT {} FROM delivery_jobs WHERE id = $1", JOB_COLS);
+
+      sqlx::query(&sql).bind(id).fetch_optional(&self.pool).await?
+         .as_ref()
+         .map(job_from_row)
+         .transpose()
+   }
+
+   async fn set_reply_delivery(&self, id: ObjectId, entry_id: ObjectId, status: &DeliveryStatus) -> Result<(), StoreErr> {
+      let mut tx = self.pool.begin().await?;
+
+      let mut thread = match self.fetch_thread(&mut tx, id).await? {
+         Some(thread) => thread,
+         None => return Ok(())
+      };
+      if let Some(entry) = thread.iter_mut().find(|entry| entry.id == entry_id) {
+         entry.delivery = Some(status.clone());
+      }
+
+      sqlx::query("UPDATE messages SET thread = $1 WHERE id = $2")
+         .bind(to_json(&thread)?)
+         .bind(id.to_hex())
+         .execute(&mut tx).await?;
+      tx.commit().await?;
+
+      Ok(())
+   }
+}
+
+#[async_trait]
+impl MessageStore for SqlStore {
+   async fn insert(&self, msg: &Message) -> Result<ObjectId, StoreErr> {
+      let id = msg.id.unwrap_or_else(ObjectId::new);
+
+      let mut tx = self.pool.begin().await?;
+      sqlx::query(
+         "INSERT INTO messages (id, created_at, sender, name, subject, message, locale, is_read, is_archived, delivery, ack, thread, deleted_at, source_id, content_hash,
+            spam, quarantined, spam_label)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)"
+      )
+         .bind(id.to_hex())
+         .bind(msg.created_at.map(|date| date.timestamp_millis()))
+         .bind(msg.from.as_str())
+         .bind(msg.name.as_str())
+         .bind(msg.subject.as_str())
+         .bind(msg.message.as_str())
+         .bind(msg.locale.clone())
+         .bind(msg.read)
+         .bind(msg.archived)
+         .bind(opt_json(&msg.delivery)?)
+         .bind(opt_json(&msg.ack)?)
+         .bind(to_json(&msg.thread)?)
+         .bind(msg.deleted_at.map(|date| date.timestamp_millis()))
+         .bind(msg.source_id.clone())
+         .bind(msg.content_hash.clone())
+         .bind(opt_json(&msg.spam)?)
+         .bind(msg.spam.as_ref().map_or(false, |spam| spam.quarantined))
+         .bind(msg.spam.as_ref().and_then(|spam| spam.label))
+         .execute(&mut tx).await?;
+
+      for entry in msg.thread.iter() {
+         sqlx::query("INSERT INTO thread_emails (email_id, message_id) VALUES ($1, $2)")
+            .bind(entry.message_id.as_str())
+            .bind(id.to_hex())
+            .execute(&mut tx).await?;
+      }
+      tx.commit().await?;
+
+      Ok(id)
+   }
+
+   async fn find(&self, id: ObjectId) -> Result<Option<Message>, StoreErr> {
+      let sql = format!("SELECT {}, thread FROM messages WHERE id = $1", MESSAGE_COLS);
+
+      sqlx::query(&sql).bind(id.to_hex()).fetch_optional(&self.pool).await?
+         .map(|row| message_from_row(&row, true))
+         .transpose()
+   }
+
+   async fn find_by_thread_email(&self, message_ids: &[String]) -> Result<Option<Message>, StoreErr> {
+      if message_ids.is_empty() {
+         return Ok(None);
+      }
+
+      let mut params = Params::default();
+      let sql = format!(
+         "SELECT message_id FROM thread_emails WHERE email_id IN ({}) LIMIT 1",
+         params.list(message_ids.iter().cloned().map(SqlArg::Text))
+      );
+
+      match params.bind(sqlx::query(&sql)).fetch_optional(&self.pool).await? {
+         Some(row) => self.find(parse_oid(&row.try_get::<String, _>("message_id")?)?).await,
+         None => Ok(None)
+      }
+   }
+
+   async fn find_imported(&self, source_id: Option<&str>, content_hash: &str) -> Result<Option<ObjectId>, StoreErr> {
+      let row = sqlx::query("SELECT id FROM messages WHERE source_id = $1 OR content_hash = $2 LIMIT 1")
+         .bind(source_id)
+         .bind(content_hash)
+         .fetch_optional(&self.pool).await?;
+
+      row.map(|row| parse_oid(&row.try_get::<String, _>("id")?)).transpose()
+   }
+
+   async fn list(&self, query: &ListQuery) -> Result<Vec<Listed>, StoreErr> {
+      let mut params = Params::default();
+      let mut sql = format!("SELECT {} FROM messages WHERE {}", MESSAGE_COLS, message_where(&query.filter, &mut params));
+
+      //* No portable full-text search, the rows the other filters kept are searched here
+      if query.filter.search.is_some() {
+         let msgs = params.bind(sqlx::query(&sql)).fetch_all(&self.pool).await?
+            .iter()
+            .map(|row| message_from_row(row, false))
+            .collect::<Result<Vec<Message>, StoreErr>>()?;
+
+         return Ok(list_messages(msgs.iter(), query));
+      }
+
+      //* Ties are broken by id so that every message has a stable position
+      let sort_expr = self.sort_expr(query.sort);
+      let id_expr = self.text_col("id");
+      let direction = if query.descending { "DESC" } else { "ASC" };
+
+      if let Some(position) = &query.after {
+         let op = if query.descending { "<" } else { ">" };
+         let value = match (&position.value, query.sort) {
+            (SortValue::Date(millis), _) => SqlArg::Int(*millis),
+            (SortValue::Text(text), _) => SqlArg::Text(text.clone()),
+            (SortValue::Null, SortField::CreatedAt) => SqlArg::Int(0),
+            (SortValue::Null, _) => SqlArg::Text(String::new())
+         };
+
+         sql.push_str(&format!(
+            " AND ({} {} {} OR ({} = {} AND {} {} {}))",
+            sort_expr, op, params.add(value.clone()),
+            sort_expr, params.add(value),
+            id_expr, op, params.add(SqlArg::Text(position.id.to_hex()))
+         ));
+      }
+      sql.push_str(&format!(
+         " ORDER BY {} {}, {} {} LIMIT {} OFFSET {}",
+         sort_expr, direction, id_expr, direction,
+         params.add(SqlArg::Int(query.limit.max(0))), params.add(SqlArg::Int(query.skip as i64))
+      ));
+
+      params.bind(sqlx::query(&sql)).fetch_all(&self.pool).await?
+         .iter()
+         .map(|row| message_from_row(row, false).map(|msg| Listed { msg, score: None }))
+         .collect()
+   }
+
+   async fn count(&self, filter: &MessageFilter) -> Result<u64, StoreErr> {
+      let mut params = Params::default();
+      let where_sql = message_where(filter, &mut params);
+
+      if filter.search.is_some() {
+         let sql = format!("SELECT {} FROM messages WHERE {}", MESSAGE_COLS, where_sql);
+         let msgs = params.bind(sqlx::query(&sql)).fetch_all(&self.pool).await?
+            .iter()
+            .map(|row| message_from_row(row, false))
+            .collect::<Result<Vec<Message>, StoreErr>>()?;
+
+         return Ok(count_messages(msgs.iter(), filter));
+      }
+
+      let sql = format!("SELECT COUNT(*) AS total FROM messages WHERE {}", where_sql);
+      let row = params.bind(sqlx::query(&sql)).fetch_one(&self.pool).await?;
+
+      Ok(row.try_get::<i64, _>("total")? as u64)
+   }
+
+   async fn update_flags(&self, id: ObjectId, flags: FlagsUpdate) -> Result<Option<Message>, StoreErr> {
+      let mut tx = self.pool.begin().await?;
+
+      let sql = format!("SELECT {}, thread FROM messages WHERE id = $1{}", MESSAGE_COLS, self.lock_rows());
+      let previous = match sqlx::query(&sql).bind(id.to_hex()).fetch_optional(&mut tx).await? {
+         Some(row) => message_from_row(&row, true)?,
+         None => return Ok(None)
+      };
+
+      sqlx::query("UPDATE messages SET is_read = COALESCE($1, is_read), is_archived = COALESCE($2, is_archived) WHERE id = $3")
+         .bind(flags.read)
+         .bind(flags.archived)
+         .bind(id.to_hex())
+         .execute(&mut tx).await?;
+      tx.commit().await?;
+
+      Ok(Some(previous))
+   }
+
+   async fn delete(&self, ids: &[ObjectId]) -> Result<Vec<ObjectId>, StoreErr> {
+      if ids.is_empty() {
+         return Ok(Vec::new());
+      }
+      let hex_ids = ids.iter().map(|id| SqlArg::Text(id.to_hex()));
+
+      let mut tx = self.pool.begin().await?;
+
+      let mut params = Params::default();
+      let sql = format!("SELECT id FROM messages WHERE id IN ({}){}", params.list(hex_ids), self.lock_rows());
+      let existing = params.bind(sqlx::query(&sql)).fetch_all(&mut tx).await?
+         .iter()
+         .map(|row| parse_oid(&row.try_get::<String, _>("id")?))
+         .collect::<Result<Vec<ObjectId>, StoreErr>>()?;
+      self.delete_rows(&mut tx, &existing).await?;
+      tx.commit().await?;
+
+      Ok(existing)
+   }
+
+   async fn trash(&self, ids: &[ObjectId], now: BsonDateTime) -> Result<Vec<ObjectId>, StoreErr> {
+      let mut tx = self.pool.begin().await?;
+
+      let trashed = self.select_ids(&mut tx, ids, "deleted_at IS NULL").await?;
+      if !trashed.is_empty() {
+         let mut params = Params::default();
+         let sql = format!(
+            "UPDATE messages SET deleted_at = {} WHERE id IN ({})",
+            params.add(SqlArg::Int(now.timestamp_millis())),
+            params.list(trashed.iter().map(|id| SqlArg::Text(id.to_hex())))
+         );
+         params.bind(sqlx::query(&sql)).execute(&mut tx).await?;
+      }
+      tx.commit().await?;
+
+      Ok(trashed)
+   }
+
+   async fn restore(&self, ids: &[ObjectId]) -> Result<Vec<ObjectId>, StoreErr> {
+      let mut tx = self.pool.begin().await?;
+
+      let restored = self.select_ids(&mut tx, ids, "deleted_at IS NOT NULL").await?;
+      if !restored.is_empty() {
+         let mut params = Params::default();
+         let sql = format!(
+            "UPDATE messages SET deleted_at = NULL WHERE id IN ({})",
+            params.list(restored.iter().map(|id| SqlArg::Text(id.to_hex())))
+         );
+         params.bind(sqlx::query(&sql)).execute(&mut tx).await?;
+      }
+      tx.commit().await?;
+
+      Ok(restored)
+   }
+
+   async fn purge_trashed(&self, before: BsonDateTime) -> Result<Vec<ObjectId>, StoreErr> {
+      let mut tx = self.pool.begin().await?;
+
+      let sql = format!("SELECT id FROM messages WHERE deleted_at < $1{}", self.lock_rows());
+      let expired = sqlx::query(&sql)
+         .bind(before.timestamp_millis())
+         .fetch_all(&mut tx).await?
+         .iter()
+         .map(|row| parse_oid(&row.try_get::<String, _>("id")?))
+         .collect::<Result<Vec<ObjectId>, StoreErr>>()?;
+      self.delete_rows(&mut tx, &expired).await?;
+      tx.commit().await?;
+
+      Ok(expired)
+   }
+
+   async fn ids_by_sender(&self, email: &str) -> Result<Vec<ObjectId>, StoreErr> {
+      sqlx::query("SELECT id FROM messages WHERE LOWER(sender) = LOWER($1)")
+         .bind(email)
+         .fetch_all(&self.pool).await?
+         .iter()
+         .map(|row| parse_oid(&row.try_get::<String, _>("id")?))
+         .collect()
+   }
+
+   async fn anonymize_sender(&self, email: &str, alias_email: &str, alias_name: &str) -> Result<Vec<ObjectId>, StoreErr> {
+      let mut tx = self.pool.begin().await?;
+
+      let sql = format!("SELECT id, thread FROM messages WHERE LOWER(sender) = LOWER($1){}", self.lock_rows());
+      let rows = sqlx::query(&sql).bind(email).fetch_all(&mut tx).await?;
+
+      let mut anonymized = Vec::with_capacity(rows.len());
+      for row in rows.iter() {
+         let id = row.try_get::<String, _>("id")?;
+         let mut thread: Vec<ThreadEntry> = from_json(&row.try_get::<String, _>("thread")?)?;
+         anonymize_thread(&mut thread, email, alias_email, alias_name);
+
+         sqlx::query("UPDATE messages SET sender = $1, name = $2, thread = $3 WHERE id = $4")
+            .bind(alias_email)
+            .bind(alias_name)
+            .bind(to_json(&thread)?)
+            .bind(id.as_str())
+            .execute(&mut tx).await?;
+         anonymized.push(parse_oid(&id)?);
+      }
+      tx.commit().await?;
+
+      Ok(anonymized)
+   }
+
+   async fn set_spam(&self, id: ObjectId, spam: &SpamInfo) -> Result<bool, StoreErr> {
+      let res = sqlx::query("UPDATE messages SET spam = $1, quarantined = $2, spam_label = $3 WHERE id = $4")
+         .bind(to_json(spam)?)
+         .bind(spam.quarantined)
+         .bind(spam.label)
+         .bind(id.to_hex())
+         .execute(&self.pool).await?;
+
+      Ok(res.rows_affected() > 0)
+   }
+
+   async fn push_thread_entry(&self, id: ObjectId, entry: &ThreadEntry, mark_unread: bool) -> Result<bool, StoreErr> {
+      let mut tx = self.pool.begin().await?;
+
+      let mut thread = match self.fetch_thread(&mut tx, id).await? {
+         Some(thread) => thread,
+         None => return Ok(false)
+      };
+      thread.push(entry.clone());
+
+      let sql = match mark_unread {
+         true => "UPDATE messages SET thread = $1, is_read = FALSE WHERE id = $2",
+         false => "UPDATE messages SET thread = $1 WHERE id = $2"
+      };
+      sqlx::query(sql)
+         .bind(to_json(&thread)?)
+         .bind(id.to_hex())
+         .execute(&mut tx).await?;
+      sqlx::query("INSERT INTO thread_emails (email_id, message_id) VALUES ($1, $2)")
+         .bind(entry.message_id.as_str())
+         .bind(id.to_hex())
+         .execute(&mut tx).await?;
+      tx.commit().await?;
+
+      Ok(true)
+   }
+
+   async fn set_delivery(&self, id: ObjectId, target: DeliveryTarget, status: &DeliveryStatus) -> Result<(), StoreErr> {
+      let col = match target {
+         DeliveryTarget::Owners => "delivery",
+         DeliveryTarget::Ack => "ack",
+         //* Replies live inside the thread
+         DeliveryTarget::Reply(entry_id) => return self.set_reply_delivery(id, entry_id, status).await
+      };
+
+      sqlx::query(&format!("UPDATE messages SET {} = $1 WHERE id = $2", col))
+         .bind(to_json(status)?)
+         .bind(id.to_hex())
+         .execute(&self.pool).await?;
+
+      Ok(())
+   }
+
+   async fn check_conn(&self) -> ConnCheck {
+      match sqlx::query("SELECT 1").execute(&self.pool).await {
+         Ok(_) => ConnCheck::Ok,
+         Err(err) => ConnCheck::Issue(err.into())
+      }
+   }
+}
+
+#[async_trait]
+impl JobStore for SqlStore {
+   async fn insert(&self, job: &DeliveryJob) -> Result<(), StoreErr> {
+      let endpoint = match &job.kind {
+         JobKind::Webhook { endpoint, .. } => Some(endpoint.clone()),
+         _ => None
+      };
+
+      sqlx::query(
+         "INSERT INTO delivery_jobs (id, message_id, kind, kind_type, endpoint, state, attempts, max_attempts, run_at, leased_until, last_error, log, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)"
+      )
+         .bind(job.id.unwrap_or_else(ObjectId::new).to_hex())
+         .bind(job.message_id.to_hex())
+         .bind(to_json(&job.kind)?)
+         .bind(job_kind_type(&job.kind))
+         .bind(endpoint)
+         .bind(job.state.as_str())
+         .bind(job.attempts as i64)
+         .bind(job.max_attempts as i64)
+         .bind(job.run_at.timestamp_millis())
+         .bind(job.leased_until.map(|date| date.timestamp_millis()))
+         .bind(job.last_error.clone())
+         .bind(to_json(&job.log)?)
+         .bind(job.created_at.timestamp_millis())
+         .bind(job.updated_at.timestamp_millis())
+         .execute(&self.pool).await?;
+
+      Ok(())
+   }
+
+   async fn lease(&self, now: BsonDateTime, until: BsonDateTime) -> Result<Option<DeliveryJob>, StoreErr> {
+      let now = now.timestamp_millis();
+
+      for _ in 0..LEASE_RETRIES {
+         let candidate = sqlx::query(
+            "SELECT id FROM delivery_jobs
+            WHERE (state = $1 AND run_at <= $2) OR (state = $3 AND leased_until <= $4)
+            ORDER BY run_at LIMIT 1"
+         )
+            .bind(JobState::Queued.as_str())
+            .bind(now)
+            .bind(JobState::Leased.as_str())
+            .bind(now)
+            .fetch_optional(&self.pool).await?;
+         let id = match candidate {
+            Some(row) => row.try_get::<String, _>("id")?,
+            None => return Ok(None)
+         };
+
+         //* Only leased if still due, i.e. nobody else took it in between. A lease that ran
+         //* out is an attempt that never finished (e.g.: the worker died mid-send)
+         let res = sqlx::query(
+            "UPDATE delivery_jobs SET state = $1, leased_until = $2, updated_at = $3,
+               attempts = CASE WHEN state = $9 THEN attempts + 1 ELSE attempts END
+            WHERE id = $4 AND ((state = $5 AND run_at <= $6) OR (state = $7 AND leased_until <= $8))"
+         )
+            .bind(JobState::Leased.as_str())
+            .bind(until.timestamp_millis())
+            .bind(now)
+            .bind(id.as_str())
+            .bind(JobState::Queued.as_str())
+            .bind(now)
+            .bind(JobState::Leased.as_str())
+            .bind(now)
+            .bind(JobState::Leased.as_str())
+            .execute(&self.pool).await?;
+
+         if res.rows_affected() == 1 {
+            return self.find_job(&id).await;
+         }
+      }
+
+      Ok(None)
+   }
+
+   async fn finish_attempt(&self, job: &DeliveryJob, error: Option<&str>, now: BsonDateTime) -> Result<bool, StoreErr> {
+      let id = match job.id {
+         Some(id) => id.to_hex(),
+         None => return Ok(false)
+      };
+      let mut log = match self.find_job(&id).await? {
+         Some(stored) if stored.state == JobState::Leased && stored.leased_until == job.leased_until => stored.log,
+         _ => return Ok(false)
+      };
+
+      log.push(JobAttempt { at: now, error: error.map(String::from) });
+      let overflow = log.len().saturating_sub(MAX_LOGGED_ATTEMPTS as usize);
+      log.drain(..overflow);
+
+      //* Every lease ends later than the one it took over, so `leased_until` tells them apart
+      let res = sqlx::query(
+         "UPDATE delivery_jobs SET state = $1, attempts = $2, run_at = $3, last_error = $4, leased_until = NULL, updated_at = $5, log = $6
+         WHERE id = $7 AND state = $8 AND leased_until = $9"
+      )
+         .bind(job.state.as_str())
+         .bind(job.attempts as i64)
+         .bind(job.run_at.timestamp_millis())
+         .bind(job.last_error.clone())
+         .bind(now.timestamp_millis())
+         .bind(to_json(&log)?)
+         .bind(id)
+         .bind(JobState::Leased.as_str())
+         .bind(job.leased_until.map(|until| until.timestamp_millis()))
+         .execute(&self.pool).await?;
+
+      Ok(res.rows_affected() == 1)
+   }
+
+   async fn list(&self, filter: &JobFilter) -> Result<Vec<DeliveryJob>, StoreErr> {
+      let mut params = Params::default();
+      let mut conds = vec![ "1 = 1".to_string() ];
+
+      if let Some(state) = filter.state {
+         conds.push(format!("state = {}", params.add(SqlArg::Text(state.as_str().to_string()))));
+      }
+      if filter.webhooks_only {
+         conds.push(format!("kind_type = {}", params.add(SqlArg::Text("webhook".to_string()))));
+      }
+      if let Some(endpoint) = &filter.endpoint {
+         conds.push(format!("endpoint = {}", params.add(SqlArg::Text(endpoint.clone()))));
+      }
+      match &filter.message_ids {
+         Some(ids) if ids.is_empty() => conds.push("1 = 0".to_string()),
+         Some(ids) => conds.push(format!("message_id IN ({})", params.list(ids.iter().map(|id| SqlArg::Text(id.to_hex()))))),
+         None => {}
+      }
+
+      let mut sql = format!("SELECT {} FROM delivery_jobs WHERE {} ORDER BY updated_at DESC", JOB_COLS, conds.join(" AND "));
+      if let Some(limit) = filter.limit {
+         sql.push_str(&format!(" LIMIT {}", params.add(SqlArg::Int(limit.max(0)))));
+      }
+
+      params.bind(sqlx::query(&sql)).fetch_all(&self.pool).await?
+         .iter()
+         .map(job_from_row)
+         .collect()
+   }
+
+   async fn redrive(&self, ids: &[ObjectId], now: BsonDateTime) -> Result<u64, StoreErr> {
+      if ids.is_empty() {
+         return Ok(0);
+      }
+
+      let mut params = Params::default();
+      let sql = format!(
+         "UPDATE delivery_jobs SET state = {}, attempts = 0, run_at = {}, updated_at = {} WHERE state = {} AND id IN ({})",
+         params.add(SqlArg::Text(JobState::Queued.as_str().to_string())),
+         params.add(SqlArg::Int(now.timestamp_millis())),
+         params.add(SqlArg::Int(now.timestamp_millis())),
+         params.add(SqlArg::Text(JobState::Dead.as_str().to_string())),
+         params.list(ids.iter().map(|id| SqlArg::Text(id.to_hex())))
+      );
+
+      Ok(params.bind(sqlx::query(&sql)).execute(&self.pool).await?.rows_affected())
+   }
+
+   async fn delete_for_messages(&self, message_ids: &[ObjectId]) -> Result<u64, StoreErr> {
+      if message_ids.is_empty() {
+         return Ok(0);
+      }
+
+      let mut params = Params::default();
+      let sql = format!(
+         "DELETE FROM delivery_jobs WHERE message_id IN ({})",
+         params.list(message_ids.iter().map(|id| SqlArg::Text(id.to_hex())))
+      );
+
+      Ok(params.bind(sqlx::query(&sql)).execute(&self.pool).await?.rows_affected())
+   }
+}
+
+#[async_trait]
+impl EventStore for SqlStore {
+   async fn insert(&self, event: &MessageEvent) -> Result<(), StoreErr> {
+      sqlx::query("INSERT INTO message_events (id, kind, message_id, occurred_at, data) VALUES ($1, $2, $3, $4, $5)")
+         .bind(event.id.to_hex())
+         .bind(event.kind.as_str())
+         .bind(event.message_id.to_hex())
+         .bind(event.occurred_at.timestamp_millis())
+         .bind(to_json(&event.data)?)
+         .execute(&self.pool).await?;
+
+      //* Stands in for Mongo's TTL index
+      let expired_before = Utc::now().timestamp_millis() - self.events_retention_days * 24 * 60 * 60 * 1000;
+      if let Err(err) = sqlx::query("DELETE FROM message_events WHERE occurred_at < $1").bind(expired_before).execute(&self.pool).await {
+         warn!("Failed pruning expired message events. Error: {}", err);
+      }
+
+      Ok(())
+   }
+
+   async fn find(&self, id: ObjectId) -> Result<Option<MessageEvent>, StoreErr> {
+      sqlx::query("SELECT id, kind, message_id, occurred_at, data FROM message_events WHERE id = $1")
+         .bind(id.to_hex())
+         .fetch_optional(&self.pool).await?
+         .as_ref()
+         .map(event_from_row)
+         .transpose()
+   }
+
+   async fn since(&self, last_id: ObjectId, limit: i64) -> Result<Vec<MessageEvent>, StoreErr> {
+      //* Hex ids sort the same way as ObjectIds
+      let sql = format!(
+         "SELECT id, kind, message_id, occurred_at, data FROM message_events WHERE {} > $1 ORDER BY {} LIMIT $2",
+         self.text_col("id"), self.text_col("id")
+      );
+
+      sqlx::query(&sql)
+         .bind(last_id.to_hex())
+         .bind(limit.max(0))
+         .fetch_all(&self.pool).await?
+         .iter()
+         .map(event_from_row)
+         .collect()
+   }
+
+   async fn for_messages(&self, message_ids: &[ObjectId]) -> Result<Vec<MessageEvent>, StoreErr> {
+      if message_ids.is_empty() {
+         return Ok(Vec::new());
+      }
+
+      let mut params = Params::default();
+      let sql = format!(
+         "SELECT id, kind, message_id, occurred_at, data FROM message_events WHERE message_id IN ({}) ORDER BY {}",
+         params.list(message_ids.iter().map(|id| SqlArg::Text(id.to_hex()))), self.text_col("id")
+      );
+
+      params.bind(sqlx::query(&sql)).fetch_all(&self.pool).await?
+         .iter()
+         .map(event_from_row)
+         .collect()
+   }
+
+   async fn delete_for_messages(&self, message_ids: &[ObjectId]) -> Result<u64, StoreErr> {
+      if message_ids.is_empty() {
+         return Ok(0);
+      }
+
+      let mut params = Params::default();
+      let sql = format!(
+         "DELETE FROM message_events WHERE message_id IN ({})",
+         params.list(message_ids.iter().map(|id| SqlArg::Text(id.to_hex())))
+      );
+
+      Ok(params.bind(sqlx::query(&sql)).execute(&self.pool).await?.rows_affected())
+   }
+}